@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use ssh_utils_lib::config::app_vault::decrypt_password;
+
+// a secret as stored in the vault, with the id it belongs to
+fuzz_target!(|input: (&str, &str)| {
+    let (id, secret) = input;
+    let _ = decrypt_password(id, secret, &[0u8; 32]);
+});
@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use ssh_utils_lib::config::app_vault::decrypt_vault;
+
+// a whole encrypted_data.bin, only the header and lengths are reached without the key
+fuzz_target!(|data: &[u8]| {
+    let _ = decrypt_vault(data, &[0u8; 32]);
+});
@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use ssh_utils_lib::config::app_vault::parse_vault;
+
+// the vault as it is after decryption
+fuzz_target!(|data: &[u8]| {
+    let _ = parse_vault(data);
+});
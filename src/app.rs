@@ -1,5 +1,7 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
 use std::io::stdout;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -8,6 +10,7 @@ use crossterm::cursor::RestorePosition;
 use crossterm::event;
 use crossterm::event::Event;
 use crossterm::event::KeyCode::*;
+use crossterm::event::KeyEvent;
 use crossterm::event::KeyEventKind;
 use crossterm::event::KeyModifiers;
 use crossterm::execute;
@@ -35,21 +38,48 @@ use ratatui::widgets::StatefulWidget;
 use ratatui::widgets::Widget;
 use ratatui::widgets::Wrap;
 use ratatui::Terminal;
-use russh_keys::key::KeyPair;
+use russh_keys::decode_secret_key;
 use russh_keys::load_secret_key;
 use tokio::time::sleep;
 
-use crate::config::app_config::Config;
-use crate::config::app_vault::decrypt_password;
+use crate::config::app_config::{read_config, Config, Multiplexer, MultiplexerKind, SshOptions};
+use crate::config::import::{local_user, save_imported};
+use crate::config::filter::{Fields, ServerFilter};
+use crate::config::ssh_config;
+use crate::config::backup;
+use crate::config::paths::ConfigPaths;
+use crate::config::storage::{self, ChangedOnDisk};
+use crate::config::store;
+use crate::config::app_vault::{self, decrypt_password, decrypt_vault, encrypt_password, read_vault};
 use crate::config::app_vault::EncryptionKey;
-use crate::config::app_vault::Vault;
+use crate::config::app_vault::{Vault, VaultError};
 use crate::debug_log;
 use crate::helper::convert_to_array;
+use crate::ssh::certificate::{describe, expiry, is_expired, load_certificate};
+use crate::ssh::identity::{display_path, expand_tilde, identity_candidates, Identity};
+use crate::ssh::copy_id::install_command;
+use crate::ssh::key_file::{fingerprint, read_public_key};
 use crate::ssh::key_session::KeySession;
+use crate::ssh::multiplexer::{attach_command, list_command, parse_sessions};
 use crate::ssh::password_session::PasswordSession;
-use crate::ssh::ssh_session::{AuthMethod, SshSession};
+use crate::ssh::ssh_session::{AuthMethod, AuthRejected, SshSession};
+use crate::widgets::confirm_box::ConfirmBox;
+use crate::widgets::key_manager::KeyManager;
+use crate::widgets::list_picker::ListPicker;
 use crate::widgets::popup_input_box::PopupInputBox;
 use crate::widgets::server_creator::ServerCreator;
+use crate::widgets::session_picker::SessionPicker;
+
+/// every key binding of the server list, shown by `?`
+const HELP: &str = "Enter connect        A add server
+E edit server        D delete server
+I server details     T tmux/screen sessions
+/ filter (tag:prod)  ESC clear filter
+C copy key (ssh-copy-id)
+M manage keys        O import ssh config
+U undo last change
+←/→ Enter fold/unfold group
+Q/ESC quit";
 
 struct ServerItem {
     name: String,
@@ -58,25 +88,199 @@ struct ServerItem {
     id: String,
     shell: String,
     port: u16,
+    identity_files: Vec<String>,
+    multiplexer: Option<Multiplexer>,
+    ssh_options: SshOptions,
+    proxy_jump: Option<String>,
+    group: String,
+    tags: Vec<String>,
+    notes: String,
+}
+
+impl ServerItem {
+    fn filter_fields(&self) -> Fields<'_> {
+        Fields {
+            name: &self.name,
+            ip: &self.address,
+            user: &self.username,
+            group: &self.group,
+            tags: &self.tags,
+            notes: &self.notes,
+        }
+    }
+}
+
+/// a line of the server list
+enum Row {
+    /// a folder, with the number of servers below it
+    Group {
+        path: String,
+        name: String,
+        depth: usize,
+        count: usize,
+        collapsed: bool,
+    },
+    /// index in `ServerList::items`
+    Server { index: usize, depth: usize },
+}
+
+/// servers and subgroups of a group
+#[derive(Default)]
+struct GroupNode {
+    children: BTreeMap<String, GroupNode>,
+    servers: Vec<usize>,
+}
+
+impl GroupNode {
+    fn count(&self) -> usize {
+        self.servers.len() + self.children.values().map(GroupNode::count).sum::<usize>()
+    }
+}
+
+/// groups first, then the servers directly in the node
+fn push_rows(
+    rows: &mut Vec<Row>,
+    collapsed: &BTreeSet<String>,
+    node: &GroupNode,
+    path: &str,
+    depth: usize,
+) {
+    for (name, child) in &node.children {
+        let child_path = if path.is_empty() {
+            name.clone()
+        } else {
+            format!("{}/{}", path, name)
+        };
+        let is_collapsed = collapsed.contains(&child_path);
+        rows.push(Row::Group {
+            path: child_path.clone(),
+            name: name.clone(),
+            depth,
+            count: child.count(),
+            collapsed: is_collapsed,
+        });
+        if !is_collapsed {
+            push_rows(rows, collapsed, child, &child_path, depth + 1);
+        }
+    }
+    rows.extend(node.servers.iter().map(|&index| Row::Server { index, depth }));
 }
 
 struct ServerList {
     state: ListState,
     items: Vec<ServerItem>,
+    /// visible lines, rebuilt when the servers change or a group is folded
+    rows: Vec<Row>,
+    /// paths of the folded groups
+    collapsed: BTreeSet<String>,
+    /// query typed after `/`, only matching servers are listed
+    query: String,
+    filter: ServerFilter,
 }
 
 impl ServerList {
     fn with_items(items: Vec<ServerItem>) -> ServerList {
-        ServerList {
+        let mut list = ServerList {
             state: ListState::default(),
             items,
+            rows: Vec::new(),
+            collapsed: BTreeSet::new(),
+            query: String::new(),
+            filter: ServerFilter::default(),
+        };
+        list.build_rows();
+        list
+    }
+
+    /// replace the servers, keeping folded groups and the selection
+    fn set_items(&mut self, items: Vec<ServerItem>) {
+        self.items = items;
+        self.build_rows();
+    }
+
+    /// list only the servers matching the query, an empty one shows all
+    fn set_filter(&mut self, query: &str) {
+        self.query = query.trim().to_string();
+        self.filter = ServerFilter::parse(&self.query);
+        self.state.select(Some(0));
+        self.build_rows();
+    }
+
+    fn build_rows(&mut self) {
+        let mut root = GroupNode::default();
+        for (index, item) in self.items.iter().enumerate() {
+            if !self.filter.matches_fields(item.filter_fields()) {
+                continue;
+            }
+            let mut node = &mut root;
+            for level in item.group.split('/').filter(|level| !level.is_empty()) {
+                node = node.children.entry(level.to_string()).or_default();
+            }
+            node.servers.push(index);
+        }
+        self.rows.clear();
+        push_rows(&mut self.rows, &self.collapsed, &root, "", 0);
+        if let Some(i) = self.state.selected() {
+            self.state
+                .select((!self.rows.is_empty()).then(|| i.min(self.rows.len() - 1)));
+        }
+    }
+
+    /// index in `items` of the selected server, None on a group
+    fn selected_server(&self) -> Option<usize> {
+        match self.rows.get(self.state.selected()?)? {
+            Row::Server { index, .. } => Some(*index),
+            Row::Group { .. } => None,
+        }
+    }
+
+    /// fold or unfold the selected group
+    fn set_collapsed(&mut self, collapse: bool) {
+        let Some(Row::Group { path, .. }) = self.state.selected().and_then(|i| self.rows.get(i))
+        else {
+            return;
+        };
+        if collapse {
+            self.collapsed.insert(path.clone());
+        } else {
+            self.collapsed.remove(path);
+        }
+        self.build_rows();
+    }
+
+    fn toggle(&mut self) {
+        if let Some(Row::Group { collapsed, .. }) = self.state.selected().and_then(|i| self.rows.get(i)) {
+            self.set_collapsed(!collapsed);
+        }
+    }
+
+    /// fold the selected group, or go up to the group of the selection
+    fn collapse(&mut self) {
+        let Some(i) = self.state.selected().filter(|i| *i < self.rows.len()) else {
+            return;
+        };
+        let depth = match &self.rows[i] {
+            Row::Group { collapsed: false, .. } => return self.set_collapsed(true),
+            Row::Group { depth, .. } | Row::Server { depth, .. } => *depth,
+        };
+        if depth == 0 {
+            return;
+        }
+        let parent = self.rows[..i]
+            .iter()
+            .rposition(|row| matches!(row, Row::Group { depth: d, .. } if *d == depth - 1));
+        if parent.is_some() {
+            self.state.select(parent);
         }
     }
 
     fn next(&mut self) {
+        if self.rows.is_empty() {
+            return;
+        }
         let i = match self.state.selected() {
             Some(i) => {
-                if i >= self.items.len() - 1 {
+                if i >= self.rows.len() - 1 {
                     0
                 } else {
                     i + 1
@@ -88,10 +292,13 @@ impl ServerList {
     }
 
     fn previous(&mut self) {
+        if self.rows.is_empty() {
+            return;
+        }
         let i = match self.state.selected() {
             Some(i) => {
                 if i == 0 {
-                    self.items.len() - 1
+                    self.rows.len() - 1
                 } else {
                     i - 1
                 }
@@ -121,18 +328,25 @@ pub struct App<'a> {
     show_popup: bool,
     popup_info: Option<PopupInfo>,
     is_connecting: bool,
+    /// shown once the current action is done, so it isn't hidden by a session
+    warning: Option<String>,
 }
 
 impl<'a> Widget for &mut App<'a> {
     fn render(self, area: Rect, buf: &mut Buffer) {
+        let detail = self.selected_detail();
         let vertical = Layout::vertical([
             Constraint::Length(1),
             Constraint::Min(0),
+            Constraint::Length(detail.is_some() as u16),
             Constraint::Length(1),
         ]);
-        let [head_area, body_area, foot_area] = vertical.areas(area);
+        let [head_area, body_area, detail_area, foot_area] = vertical.areas(area);
         self.render_header(head_area, buf);
         self.render_servers(body_area, buf);
+        if let Some(detail) = detail {
+            Widget::render(Text::from(detail).italic(), detail_area, buf);
+        }
         self.render_footer(foot_area, buf);
     }
 }
@@ -147,20 +361,71 @@ impl<'a> App<'a> {
     }
 
     fn render_footer(&self, area: Rect, buf: &mut Buffer) {
-        let text = Text::from("  Add (A), Edit (E), Delete (D), Quit (ESC)").dim();
-        Widget::render(text, area, buf);
+        let text = if self.server_list.filter.is_empty() {
+            Text::from("  Add (A), Edit (E), Delete (D), Info (I), Filter (/), Help (?), Quit (ESC)")
+        } else {
+            Text::from(format!(
+                "  Filter: {} ({} of {}), Clear (ESC)",
+                self.server_list.query,
+                self.server_list
+                    .items
+                    .iter()
+                    .filter(|item| self.server_list.filter.matches_fields(item.filter_fields()))
+                    .count(),
+                self.server_list.items.len()
+            ))
+        };
+        Widget::render(text.dim(), area, buf);
+    }
+
+    /// tags and the first line of the notes of the selected server, if it has any
+    fn selected_detail(&self) -> Option<String> {
+        let item = &self.server_list.items[self.server_list.selected_server()?];
+        let mut parts = Vec::new();
+        if !item.tags.is_empty() {
+            parts.push(format!("tags: {}", item.tags.join(", ")));
+        }
+        if let Some(first) = item.notes.lines().next() {
+            let more = item.notes.lines().count() - 1;
+            if more > 0 {
+                parts.push(format!("notes: {} (+{} lines, I to show)", first, more));
+            } else {
+                parts.push(format!("notes: {}", first));
+            }
+        }
+        (!parts.is_empty()).then(|| format!("  {}", parts.join("  ")))
     }
 
     fn render_servers(&mut self, area: Rect, buf: &mut Buffer) {
         let items: Vec<ListItem> = self
             .server_list
-            .items
+            .rows
             .iter()
-            .map(|item| {
-                ListItem::new(format!(
-                    "{:<10} {:<15} {:<20}",
-                    item.username, item.address, item.name
+            .map(|row| match row {
+                Row::Group {
+                    name,
+                    depth,
+                    count,
+                    collapsed,
+                    ..
+                } => ListItem::new(format!(
+                    "{}{} {} ({})",
+                    "  ".repeat(*depth),
+                    if *collapsed { "▸" } else { "▾" },
+                    name,
+                    count
                 ))
+                .bold(),
+                Row::Server { index, depth } => {
+                    let item = &self.server_list.items[*index];
+                    ListItem::new(format!(
+                        "{}{:<10} {:<15} {:<20}",
+                        "  ".repeat(*depth),
+                        item.username,
+                        item.address,
+                        item.name
+                    ))
+                }
             })
             .collect();
 
@@ -194,16 +459,24 @@ impl<'a> App<'a> {
                 username: server.user,
                 shell: server.shell,
                 port: server.port,
+                identity_files: server.identity_files,
+                multiplexer: server.multiplexer,
+                ssh_options: server.ssh_options,
+                proxy_jump: server.proxy_jump,
+                group: server.group,
+                tags: server.tags,
+                notes: server.notes,
             })
             .collect();
         let app = Self {
             server_list: ServerList::with_items(server_items),
-            vault: vault,
-            config: config,
+            vault,
+            config,
             encryption_key,
             show_popup: false,
             popup_info: None,
             is_connecting: false,
+            warning: None,
         };
         Ok(app)
     }
@@ -227,8 +500,15 @@ impl<'a> App<'a> {
                     .border_style(Style::default().fg(border_color))
                     .title(title)
                     .borders(Borders::ALL);
-                let area = Self::centered_rect(50, 60, f.area());
                 if let Some(message) = message {
+                    // give multi-line messages like the detail view the whole height
+                    let width = (f.area().width / 2).saturating_sub(2).max(1) as usize;
+                    let rows: usize = message
+                        .lines()
+                        .map(|line| line.chars().count().div_ceil(width).max(1))
+                        .sum();
+                    let percent_y = if rows > 4 { 100 } else { 60 };
+                    let area = Self::centered_rect(50, percent_y, f.area());
                     let text = Paragraph::new(Text::raw(message).fg(Color::White))
                         .style(Style::default())
                         .wrap(Wrap { trim: true })
@@ -270,9 +550,9 @@ impl<'a> App<'a> {
             .split(popup_layout[1])[1]
     }
 
-    pub async fn run(&mut self, mut terminal: &mut Terminal<impl Backend>) -> Result<()> {
+    pub async fn run(&mut self, terminal: &mut Terminal<impl Backend>) -> Result<()> {
         loop {
-            self.draw(&mut terminal)?;
+            self.draw(terminal)?;
             if let Event::Key(key) = event::read()? {
                 if key.kind == KeyEventKind::Press {
                     if !self.is_connecting && self.show_popup {
@@ -282,211 +562,735 @@ impl<'a> App<'a> {
                     if self.is_connecting {
                         continue;
                     }
-                    match key.code {
-                        Char('q') | Esc => {
-                            return Ok(());
-                        }
-                        Char('j') | Down => self.server_list.next(),
-                        Char('k') | Up => self.server_list.previous(),
-                        Char('c') => {
-                            // Set this hotkey because of man's habit
-                            if key.modifiers == KeyModifiers::CONTROL {
-                                return Ok(());
-                            }
-                        }
-                        Char('a') => {
-                            // Add server
-                            let mut server_creator =
-                                ServerCreator::new(self.vault, self.config, &self.encryption_key);
+                    let result = self.handle_key(key, terminal).await;
+                    if self.reload_if_changed(result)? == Some(true) {
+                        return Ok(());
+                    }
+                    // the change was saved, but not everything derived from it
+                    if let Some(warning) = store::take_warning() {
+                        self.render_popup(warning, PopupType::Error)?;
+                    }
+                    if let Some(warning) = self.warning.take() {
+                        self.render_warning(warning)?;
+                    }
+                }
+            }
+        }
+    }
+
+    /**
+        act on a key press in the server list
+        true -> quit
+    */
+    async fn handle_key(
+        &mut self,
+        key: KeyEvent,
+        terminal: &mut Terminal<impl Backend>,
+    ) -> Result<bool> {
+        match key.code {
+            Esc if !self.server_list.filter.is_empty() => {
+                self.server_list.set_filter("");
+            }
+            Char('q') | Esc => {
+                return Ok(true);
+            }
+            Char('/') => {
+                // Only list matching servers
+                let mut input_box = PopupInputBox::new_plain(
+                    " Filter (tag:x group:x or text, empty for all): ".to_string(),
+                );
+                if let Some(query) = input_box.run(terminal)? {
+                    self.server_list.set_filter(&query);
+                }
+            }
+            Char('j') | Down => self.server_list.next(),
+            Char('k') | Up => self.server_list.previous(),
+            Char('h') | Left => self.server_list.collapse(),
+            Char('l') | Right => self.server_list.set_collapsed(false),
+            Char('c')
+                // Set this hotkey because of man's habit
+                if key.modifiers == KeyModifiers::CONTROL => {
+                    return Ok(true);
+                }
+            Char('a') => {
+                // Add server
+                let mut server_creator =
+                    ServerCreator::new(self.vault, self.config, &self.encryption_key);
 
-                            if server_creator.run(&mut terminal)? {
-                                self.refresh_serverlist();
+                if server_creator.run(terminal)? {
+                    self.refresh_serverlist();
+                }
+            }
+            Char('m') => {
+                // Manage keys
+                let mut key_manager =
+                    KeyManager::new(self.vault, self.config, &self.encryption_key)?;
+                key_manager.run(terminal)?;
+                self.refresh_serverlist();
+                terminal.clear()?;
+            }
+            Char('?') => {
+                self.render_popup(HELP.to_string(), PopupType::Info)?;
+            }
+            Char('u') => {
+                // Put config and vault back as they were before the last save
+                self.undo(terminal)?;
+            }
+            Char('o') => {
+                // Import servers from an OpenSSH client config
+                let mut input_box = PopupInputBox::new_plain(
+                    " Import ssh config (empty for ~/.ssh/config): ".to_string(),
+                );
+                if let Some(path) = input_box.run(terminal)? {
+                    let summary = self.import_ssh_config(path.trim())?;
+                    self.render_popup(summary, PopupType::Info)?;
+                }
+            }
+            Char('c') => {
+                // Install a public key on the server, like ssh-copy-id
+                if let Some(selected_index) = self.server_list.selected_server() {
+                    self.copy_key(selected_index, terminal).await?;
+                }
+            }
+            Char('e') => {
+                // Edit server
+                if let Some(selected_index) = self.server_list.selected_server() {
+                    let server = &self.server_list.items[selected_index];
+                    let server_id = server.id.clone();
+                    let mut server_creator = ServerCreator::new_edit(
+                        self.vault,
+                        self.config,
+                        &self.encryption_key,
+                        server_id.as_str(),
+                    )?;
+                    if server_creator.run(terminal)? {
+                        self.refresh_serverlist();
+                    }
+                }
+            }
+            Char('d') => {
+                if let Some(selected_index) = self.server_list.selected_server() {
+                    let server = &self.server_list.items[selected_index];
+                    let server_id = server.id.clone();
+                    let encryption_key = convert_to_array(&self.encryption_key)?;
+                    store::update(self.config, self.vault, &encryption_key, |config, vault| {
+                        vault.delete_server(server_id.as_str());
+                        config.delete_server(server_id.as_str())
+                    })?;
+                    self.refresh_serverlist();
+                }
+            }
+            Enter => {
+                self.server_list.toggle();
+                if let Some(selected_index) = self.server_list.selected_server() {
+                    let command = {
+                        let server = &self.server_list.items[selected_index];
+                        match &server.multiplexer {
+                            Some(multiplexer) => {
+                                attach_command(multiplexer.kind, &multiplexer.session)
                             }
+                            None => server.shell.clone(),
                         }
-                        Char('e') => {
-                            // Edit server
-                            if let Some(selected_index) = self.server_list.state.selected() {
-                                let server = &self.server_list.items[selected_index];
-                                let server_id = server.id.clone();
-                                let mut server_creator = ServerCreator::new_edit(
-                                    self.vault,
-                                    self.config,
-                                    &self.encryption_key,
-                                    server_id.as_str(),
-                                )?;
-                                if server_creator.run(&mut terminal)? {
-                                    self.refresh_serverlist();
-                                }
-                            }
+                    };
+                    if let Some(ssh) = self.open_session(selected_index, terminal).await? {
+                        self.run_session(ssh, &command, terminal).await?;
+                    }
+                }
+            }
+            Char('i') => {
+                // Show server details
+                if let Some(selected_index) = self.server_list.selected_server() {
+                    let details = self.server_details(selected_index)?;
+                    self.render_popup(details, PopupType::Info)?;
+                }
+            }
+            Char('t') => {
+                // Pick a remote tmux/screen session to join
+                if let Some(selected_index) = self.server_list.selected_server() {
+                    let kind = self.server_list.items[selected_index]
+                        .multiplexer
+                        .as_ref()
+                        .map_or(MultiplexerKind::Tmux, |multiplexer| multiplexer.kind);
+                    let Some(mut ssh) = self.open_session(selected_index, terminal).await?
+                    else {
+                        return Ok(false);
+                    };
+                    let sessions = match Arc::get_mut(&mut ssh)
+                        .unwrap()
+                        .exec(list_command(kind))
+                        .await
+                    {
+                        // a non-zero exit code usually means no server is running yet
+                        Ok((0, output)) => parse_sessions(kind, &output),
+                        Ok(_) => Vec::new(),
+                        Err(e) => {
+                            self.render_popup(e.to_string(), PopupType::Error)?;
+                            self.is_connecting = false;
+                            return Ok(false);
                         }
-                        Char('d') => {
-                            if let Some(selected_index) = self.server_list.state.selected() {
-                                let server = &self.server_list.items[selected_index];
-                                let server_id = server.id.clone();
-                                self.config.delete_server(server_id.as_str())?;
-                                self.server_list.items.remove(selected_index);
-                                self.vault.delete_server(
-                                    server_id.as_str(),
-                                    &convert_to_array(&self.encryption_key)?,
-                                )?;
-                            }
+                    };
+                    self.is_connecting = false;
+                    self.show_popup = false;
+                    let mut session_picker =
+                        SessionPicker::new(" Sessions: ".to_string(), sessions);
+                    match session_picker.run(terminal)? {
+                        Some(session) => {
+                            self.is_connecting = true;
+                            let command = attach_command(kind, &session);
+                            self.run_session(ssh, &command, terminal).await?;
                         }
-                        Enter => {
-                            if let Some(selected_index) = self.server_list.state.selected() {
-                                let server = &self.server_list.items[selected_index];
-                                let server_id = server.id.clone();
-                                let server_address = server.address.clone();
-                                let server_username = server.username.clone();
-                                let server_shell = server.shell.clone();
-                                let server_port = server.port.clone();
-                                if let Some(password) = self.vault.servers.iter().find_map(|s| {
-                                    (s.id == server_id).then(|| {
-                                        decrypt_password(
-                                            &s.id,
-                                            &s.password,
-                                            &convert_to_array(&self.encryption_key).map_err(
-                                                |e| anyhow::anyhow!("encryption key convert failed: {}", e),
-                                            )?,
-                                        )
-                                        .map_err(|e| anyhow::anyhow!("password decrypt failed: {}", e))
-                                    })
-                                }).transpose()? {
-                                    if cfg!(debug_assertions) {
-                                        debug_log!("debug.log", "IP: {}", server.address);
-                                        debug_log!("debug.log", "Port: {}", server.port);
-                                        debug_log!("debug.log", "User: {}", server.username);
-                                        debug_log!("debug.log", "Shell: {}", server.shell);
-                                    }
-                                    self.is_connecting = true;
-                                    self.render_popup(
-                                        "Connecting...".to_string(),
-                                        PopupType::Info,
-                                    )?;
-                                    self.draw(&mut terminal)?;
-
-                                    let is_password_empty = password.is_empty();
-                                    let result: Result<Arc<dyn SshSession>, anyhow::Error> =
-                                        if is_password_empty {
-                                            // result 1
-                                            let key_path: Option<PathBuf> = find_best_key();
-                                            if key_path.is_none() {
-                                                self.render_popup(
-                                                    "No suitable SSH key found".to_string(),
-                                                    PopupType::Error,
-                                                )?;
-                                                self.is_connecting = false;
-                                                continue;
-                                            }
-                                            let key_path = key_path.unwrap(); // unwrap is safe here
-                                            let key_pair: Result<KeyPair, anyhow::Error> =
-                                                load_key_with_passphrase(key_path, &mut terminal);
-                                            let key_pair = match key_pair {
-                                                Ok(key_pair) => key_pair,
-                                                Err(_) => {
-                                                    self.render_popup(
-                                                        "Wrong passphrase.".to_string(),
-                                                        PopupType::Error,
-                                                    )?;
-                                                    self.is_connecting = false;
-                                                    continue;
-                                                }
-                                            };
-                                            KeySession::connect(
-                                                server_username.clone(),
-                                                AuthMethod::Key(key_pair),
-                                                (server_address.clone(), server_port),
-                                            )
-                                            .await
-                                            .and_then(|session| Ok(session))
-                                            .map(|session| Arc::new(session) as Arc<dyn SshSession>)
-                                        } else {
-                                            // result 2
-                                            PasswordSession::connect(
-                                                server_username.clone(),
-                                                AuthMethod::Password(password.clone()),
-                                                (server_address.clone(), server_port),
-                                            )
-                                            .await
-                                            .map(|session| Arc::new(session) as Arc<dyn SshSession>)
-                                        };
-
-                                    match result {
-                                        Ok(mut ssh) => {
-                                            self.render_popup(
-                                                "Connected!".to_string(),
-                                                PopupType::Info,
-                                            )?;
-                                            self.draw(&mut terminal)?;
-                                            sleep(Duration::from_millis(1500)).await;
-
-                                            // 处理 SSH 会话
-                                            let code = {
-                                                terminal.clear()?;
-                                                execute!(
-                                                    stdout(),
-                                                    RestorePosition,
-                                                    Clear(ClearType::FromCursorDown),
-                                                    crossterm::cursor::Show
-                                                )?;
-                                                match Arc::get_mut(&mut ssh)
-                                                    .unwrap()
-                                                    .call(&server_shell)
-                                                    .await
-                                                {
-                                                    Ok(code) => code,
-                                                    Err(e) => {
-                                                        self.render_popup(
-                                                            e.to_string(),
-                                                            PopupType::Error,
-                                                        )?;
-                                                        self.is_connecting = false;
-                                                        1 // error occurred
-                                                    }
-                                                }
-                                            };
-                                            match Arc::get_mut(&mut ssh).unwrap().close().await {
-                                                Ok(_) => {}
-                                                Err(e) => {
-                                                    self.render_popup(
-                                                        e.to_string(),
-                                                        PopupType::Error,
-                                                    )?;
-                                                    self.is_connecting = false;
-                                                    debug_log!("debug.log", "Close error: {:?}", e);
-                                                }
-                                            }
-                                            terminal.clear()?;
-                                            debug_log!("debug.log", "Exitcode: {:?}", code);
-                                            self.is_connecting = false;
-                                            if code == 0 {
-                                                self.show_popup = false;
-                                            }
-                                        }
-                                        Err(e) => {
-                                            self.show_popup = true;
-                                            let error_message = if e.to_string().is_empty() {
-                                                "Connection error occurred".to_string()
-                                            } else {
-                                                e.to_string()
-                                            };
-                                            debug_log!("debug.log", "{}", error_message);
-                                            self.render_popup(error_message, PopupType::Error)?;
-                                            self.is_connecting = false;
-                                        }
-                                    }
-                                } else {
-                                    self.render_popup(
-                                        format!("Cannot find password of server {}", server.name),
-                                        PopupType::Error,
-                                    )?;
-                                }
-                            }
+                        None => {
+                            let _ = Arc::get_mut(&mut ssh).unwrap().close().await;
+                            terminal.clear()?;
                         }
-                        _ => {}
                     }
                 }
             }
+            _ => {}
+        }
+        Ok(false)
+    }
+
+    /**
+        when a save was refused because another ssh-utils changed the files,
+        load what it saved and tell the user instead of failing
+    */
+    fn reload_if_changed<T>(&mut self, result: Result<T>) -> Result<Option<T>> {
+        match result {
+            Err(e) if e.downcast_ref::<ChangedOnDisk>().is_some() => {
+                self.is_connecting = false;
+                let vault = match read_vault(&convert_to_array(&self.encryption_key)?) {
+                    Ok((vault, _)) => vault,
+                    // saves keep being refused, the vault is never written with the old key
+                    Err(e) => {
+                        let message = if e.downcast_ref::<VaultError>() == Some(&VaultError::BadMac) {
+                            "The vault passphrase was changed by another ssh-utils.".to_string()
+                        } else {
+                            format!("Unable to load the vault: {}.", e)
+                        };
+                        self.render_popup(
+                            format!("{}\nPlease restart ssh-utils, your change was not saved.", message),
+                            PopupType::Error,
+                        )?;
+                        return Ok(None);
+                    }
+                };
+                *self.config = read_config()?;
+                *self.vault = vault;
+                self.refresh_serverlist();
+                self.render_popup(
+                    format!(
+                        "{}.\nLoaded the latest servers, please make your change again.",
+                        e.root_cause()
+                    ),
+                    PopupType::Error,
+                )?;
+                Ok(None)
+            }
+            result => result.map(Some),
+        }
+    }
+
+    /**
+        restore the latest backup and drop it, so undoing again goes further back
+    */
+    fn undo(&mut self, terminal: &mut Terminal<impl Backend>) -> Result<()> {
+        let paths = ConfigPaths::get()?;
+        let Some(latest) = backup::list(paths)?.pop() else {
+            return self.render_popup("Nothing to undo.".to_string(), PopupType::Info);
+        };
+        let encryption_key = convert_to_array(&self.encryption_key)?;
+        // the vault may have been saved with another passphrase
+        if let Ok(content) = fs::read(latest.vault_file(paths)) {
+            if decrypt_vault(&content, &encryption_key).is_err() {
+                return self.render_popup(
+                    format!(
+                        "The vault of backup {} can't be opened with the current passphrase, use \"ssh-utils backup restore\".",
+                        latest.name
+                    ),
+                    PopupType::Error,
+                );
+            }
+        }
+        let mut confirm_box = ConfirmBox::new(
+            " Undo ".to_string(),
+            format!(
+                "Put the servers and the vault back as they were before the save at {} (UTC)?",
+                latest.name
+            ),
+        );
+        let confirmed = confirm_box.run(terminal)?;
+        terminal.clear()?;
+        if !confirmed {
+            return Ok(());
         }
+        {
+            let _lock = storage::lock(paths.dir())?;
+            backup::restore(paths, &latest, false)?;
+            backup::remove(&latest)?;
+        }
+        *self.config = read_config()?;
+        *self.vault = read_vault(&encryption_key)?.0;
+        self.refresh_serverlist();
+        self.render_popup(format!("Restored backup {}.", latest.name), PopupType::Info)
+    }
+
+    /**
+        Decrypt the stored password of a server.
+        Returns None if the vault has no record of it.
+    */
+    fn server_password(&self, server_id: &str) -> Result<Option<String>> {
+        self.vault
+            .servers
+            .iter()
+            .find_map(|s| {
+                (s.id == server_id).then(|| {
+                    decrypt_password(
+                        &s.id,
+                        &s.password,
+                        &convert_to_array(&self.encryption_key)
+                            .map_err(|e| anyhow::anyhow!("encryption key convert failed: {}", e))?,
+                    )
+                    .map_err(|e| anyhow::anyhow!("password decrypt failed: {}", e))
+                })
+            })
+            .transpose()
+    }
+
+    /**
+        Text of the server detail view.
+    */
+    fn server_details(&self, selected_index: usize) -> Result<String> {
+        let server = &self.server_list.items[selected_index];
+        let mut details = format!(
+            "name: {}\naddress: {}@{}:{}\nshell: {}",
+            server.name, server.username, server.address, server.port, server.shell
+        );
+        if let Some(proxy_jump) = &server.proxy_jump {
+            details.push_str(&format!("\njump: {}", proxy_jump));
+        }
+        if !server.group.is_empty() {
+            details.push_str(&format!("\ngroup: {}", server.group));
+        }
+        if !server.tags.is_empty() {
+            details.push_str(&format!("\ntags: {}", server.tags.join(", ")));
+        }
+        if !server.notes.is_empty() {
+            details.push_str(&format!("\nnotes:\n{}", server.notes));
+        }
+        if let Some(multiplexer) = &server.multiplexer {
+            details.push_str(&format!(
+                "\nmux: {} ({})",
+                multiplexer.kind,
+                attach_command(multiplexer.kind, &multiplexer.session)
+            ));
+        }
+        match self.server_password(&server.id)? {
+            Some(password) if !password.is_empty() => details.push_str("\nauth: password"),
+            _ => {
+                let identities = identity_candidates(&server.identity_files, &server.name, &server.address);
+                if identities.is_empty() {
+                    details.push_str("\nauth: no suitable SSH key found");
+                }
+                for identity in identities {
+                    details.push_str(&format!("\nkey: {}", identity));
+                    let Identity::File(key_path) = identity else {
+                        continue;
+                    };
+                    match load_certificate(&key_path) {
+                        Ok(Some(cert)) => details.push_str(&format!("\n{}", describe(&cert))),
+                        Ok(None) => {}
+                        Err(e) => details.push_str(&format!("\ncertificate: {}", e)),
+                    }
+                }
+            }
+        }
+        Ok(details)
+    }
+
+    /**
+        Load a key file and the certificate next to it.
+        Returns None if the user declined to use an expired certificate.
+    */
+    fn key_file_auth(
+        &mut self,
+        key_path: &Path,
+        terminal: &mut Terminal<impl Backend>,
+    ) -> Result<Option<AuthMethod>> {
+        let (key_pair, warning) = load_key_with_passphrase(
+            key_path.to_path_buf(),
+            self.vault,
+            &convert_to_array(&self.encryption_key)?,
+            terminal,
+        )?;
+        if warning.is_some() {
+            self.warning = warning;
+        }
+        let Some(cert) = load_certificate(key_path)? else {
+            return Ok(Some(AuthMethod::Key(key_pair)));
+        };
+        if is_expired(&cert) {
+            let mut confirm_box = ConfirmBox::new(
+                " Certificate expired ".to_string(),
+                format!(
+                    "The certificate {} of {} expired at {}.",
+                    cert.key_id(),
+                    display_path(key_path),
+                    expiry(&cert)
+                ),
+            );
+            if !confirm_box.run(terminal)? {
+                return Ok(None);
+            }
+        }
+        Ok(Some(AuthMethod::Certificate(key_pair, cert)))
+    }
+
+    /// build the key pair from a key embedded in the vault, `~/.ssh` is not touched
+    fn vault_key_auth(&self, name: &str) -> Result<AuthMethod> {
+        let private_key = self
+            .vault
+            .private_key(name, &convert_to_array(&self.encryption_key)?)?;
+        let key_pair = decode_secret_key(&private_key, None)?;
+        Ok(AuthMethod::Key(key_pair))
+    }
+
+    /**
+        Look up the credentials of the server and connect to it.
+        Returns None if the connection failed, the error popup is already set.
+    */
+    async fn open_session(
+        &mut self,
+        selected_index: usize,
+        terminal: &mut Terminal<impl Backend>,
+    ) -> Result<Option<Arc<dyn SshSession>>> {
+        let server = &self.server_list.items[selected_index];
+        let server_id = server.id.clone();
+        let server_name = server.name.clone();
+        let server_address = server.address.clone();
+        let server_username = server.username.clone();
+        let server_port = server.port;
+        let ssh_options = server.ssh_options.clone();
+        let identity_files = server.identity_files.clone();
+        // jump hosts are kept from imports, but not supported when connecting
+        let jump_notice = server.proxy_jump.as_ref().map(|proxy_jump| {
+            format!("\nJump host {} is not supported, connecting directly.", proxy_jump)
+        });
+        let Some(password) = self.server_password(&server_id)?
+        else {
+            self.render_popup(
+                format!(
+                    "Cannot find password of server {}, run \"ssh-utils check\" to repair",
+                    server_name
+                ),
+                PopupType::Error,
+            )?;
+            return Ok(None);
+        };
+        if cfg!(debug_assertions) {
+            debug_log!("debug.log", "IP: {}", server.address);
+            debug_log!("debug.log", "Port: {}", server.port);
+            debug_log!("debug.log", "User: {}", server.username);
+            debug_log!("debug.log", "Shell: {}", server.shell);
+        }
+        self.is_connecting = true;
+        self.render_popup(
+            format!("Connecting...{}", jump_notice.as_deref().unwrap_or("")),
+            PopupType::Info,
+        )?;
+        self.draw(terminal)?;
+
+        let is_password_empty = password.is_empty();
+        let result: Result<Arc<dyn SshSession>, anyhow::Error> = if is_password_empty {
+            // result 1: try every candidate key until the server accepts one
+            let identities = identity_candidates(&identity_files, &server_name, &server_address);
+            if identities.is_empty() {
+                self.render_popup("No suitable SSH key found".to_string(), PopupType::Error)?;
+                self.is_connecting = false;
+                return Ok(None);
+            }
+            let mut attempts: Vec<String> = Vec::new();
+            let mut result: Result<Arc<dyn SshSession>, anyhow::Error> = Err(AuthRejected.into());
+            for identity in identities {
+                let auth = match &identity {
+                    Identity::File(key_path) => self.key_file_auth(key_path, terminal),
+                    Identity::Vault(name) => self.vault_key_auth(name).map(Some),
+                };
+                let auth = match auth {
+                    Ok(Some(auth)) => auth,
+                    Ok(None) => {
+                        // the user declined to use an expired certificate
+                        self.show_popup = false;
+                        self.is_connecting = false;
+                        return Ok(None);
+                    }
+                    Err(e) => {
+                        attempts.push(format!("{} ({})", identity, e));
+                        continue;
+                    }
+                };
+                result = KeySession::connect(
+                    &ssh_options,
+                    server_username.clone(),
+                    auth,
+                    (server_address.clone(), server_port),
+                )
+                .await
+                .map(|session| Arc::new(session) as Arc<dyn SshSession>);
+                match &result {
+                    Err(e) if e.downcast_ref::<AuthRejected>().is_some() => {
+                        attempts.push(format!("{} (rejected)", identity));
+                    }
+                    // connected, or failed for a reason another key won't fix
+                    _ => break,
+                }
+            }
+            result.map_err(|e| {
+                if e.downcast_ref::<AuthRejected>().is_some() {
+                    anyhow::anyhow!("{}, tried keys:\n{}", e, attempts.join("\n"))
+                } else {
+                    e
+                }
+            })
+        } else {
+            // result 2
+            PasswordSession::connect(
+                &ssh_options,
+                server_username,
+                AuthMethod::Password(password),
+                (server_address, server_port),
+            )
+            .await
+            .map(|session| Arc::new(session) as Arc<dyn SshSession>)
+        };
+
+        match result {
+            Ok(ssh) => Ok(Some(ssh)),
+            Err(e) => {
+                self.show_popup = true;
+                let mut error_message = if e.to_string().is_empty() {
+                    "Connection error occurred".to_string()
+                } else {
+                    e.to_string()
+                };
+                error_message.push_str(jump_notice.as_deref().unwrap_or(""));
+                debug_log!("debug.log", "{}", error_message);
+                self.render_popup(error_message, PopupType::Error)?;
+                self.is_connecting = false;
+                Ok(None)
+            }
+        }
+    }
+
+    /**
+        Import the hosts of an OpenSSH client config, returns the report to show.
+    */
+    fn import_ssh_config(&mut self, path: &str) -> Result<String> {
+        let path = if path.is_empty() {
+            ssh_config::user_config_path()
+                .ok_or_else(|| anyhow::anyhow!("Unable to reach user's home directory."))?
+        } else {
+            expand_tilde(path)
+        };
+        let ssh_config = match ssh_config::read_from_path(&path) {
+            Ok(ssh_config) => ssh_config,
+            Err(e) => return Ok(e.to_string()),
+        };
+        let report = ssh_config::import_servers(&ssh_config, &self.config.servers, &local_user());
+        let summary = report.summary();
+        save_imported(
+            self.config,
+            self.vault,
+            report,
+            &convert_to_array(&self.encryption_key)?,
+        )?;
+        self.refresh_serverlist();
+        Ok(summary)
+    }
+
+    /**
+        Log in with the stored password, append a public key to the server's
+        authorized_keys, check that the key works and offer to forget the password.
+    */
+    async fn copy_key(
+        &mut self,
+        selected_index: usize,
+        terminal: &mut Terminal<impl Backend>,
+    ) -> Result<()> {
+        let server = &self.server_list.items[selected_index];
+        let server_id = server.id.clone();
+        let server_name = server.name.clone();
+        let server_address = server.address.clone();
+        let server_username = server.username.clone();
+        let server_port = server.port;
+        let ssh_options = server.ssh_options.clone();
+        let identity_files = server.identity_files.clone();
+        let encryption_key = convert_to_array(&self.encryption_key)?;
+        let password = self.server_password(&server_id)?.unwrap_or_default();
+        if password.is_empty() {
+            return self.render_popup(
+                format!("{} has no stored password to log in with", server_name),
+                PopupType::Error,
+            );
+        }
+
+        // every key we know of that has a public half
+        let mut identities = identity_candidates(&[], &server_name, &server_address);
+        identities.extend(identity_files.iter().map(|entry| Identity::parse(entry)));
+        identities.extend(self.vault.keys.iter().map(|key| Identity::Vault(key.name.clone())));
+        let mut keys = Vec::new();
+        for identity in identities {
+            if keys.iter().any(|(known, _)| *known == identity) {
+                continue;
+            }
+            if let Ok(public_key) = read_public_key(&identity, self.vault, &encryption_key) {
+                keys.push((identity, public_key));
+            }
+        }
+        if keys.is_empty() {
+            return self.render_popup(
+                "No public key found, generate one in Manage keys (M), then G".to_string(),
+                PopupType::Error,
+            );
+        }
+        let labels = keys
+            .iter()
+            .map(|(identity, public_key)| format!("{} {}", identity, fingerprint(public_key)))
+            .collect();
+        let mut list_picker = ListPicker::new(" Key to install: ".to_string(), labels);
+        let Some(choice) = list_picker.run(terminal)? else {
+            terminal.clear()?;
+            return Ok(());
+        };
+        let (identity, public_key) = keys.swap_remove(choice);
+
+        self.is_connecting = true;
+        self.render_popup("Installing key...".to_string(), PopupType::Info)?;
+        self.draw(terminal)?;
+        let installed = async {
+            let mut session = PasswordSession::connect(
+                &ssh_options,
+                server_username.clone(),
+                AuthMethod::Password(password),
+                (server_address.clone(), server_port),
+            )
+            .await?;
+            let result = session.exec(&install_command(&public_key.to_openssh()?)).await;
+            let _ = session.close().await;
+            match result? {
+                (0, _) => Ok(()),
+                (code, output) => Err(anyhow::anyhow!(
+                    "installing the key failed with exit code {}\n{}",
+                    code,
+                    output
+                )),
+            }
+        }
+        .await;
+        self.is_connecting = false;
+        if let Err(e) = installed {
+            return self.render_popup(e.to_string(), PopupType::Error);
+        }
+
+        // log in again with the key to make sure it is accepted
+        let auth = match &identity {
+            Identity::File(key_path) => self.key_file_auth(key_path, terminal),
+            Identity::Vault(name) => self.vault_key_auth(name).map(Some),
+        };
+        let verified = async {
+            let auth = auth?.ok_or_else(|| anyhow::anyhow!("certificate expired"))?;
+            let mut session = KeySession::connect(
+                &ssh_options,
+                server_username,
+                auth,
+                (server_address.clone(), server_port),
+            )
+            .await?;
+            let _ = session.close().await;
+            Ok::<(), anyhow::Error>(())
+        }
+        .await;
+        if let Err(e) = verified {
+            return self.render_popup(
+                format!("Key {} installed, but logging in with it failed: {}", identity, e),
+                PopupType::Error,
+            );
+        }
+
+        let mut confirm_box = ConfirmBox::new(
+            " Key installed ".to_string(),
+            format!(
+                "Logging in to {} with {} works. Clear the stored password so that it uses key authentication?",
+                server_name, identity
+            ),
+        );
+        if confirm_box.run(terminal)? {
+            let password = encrypt_password(&server_id, "", &encryption_key)?;
+            store::update(self.config, self.vault, &encryption_key, |config, vault| {
+                vault.set_server(app_vault::Server::new(server_id.clone(), password));
+                // make sure the key is tried once the password is gone
+                if !identity_candidates(&identity_files, &server_name, &server_address).contains(&identity) {
+                    if let Some(config_server) =
+                        config.servers.iter_mut().find(|s| s.id == server_id)
+                    {
+                        config_server.identity_files.push(identity.to_string());
+                    }
+                }
+                Ok(())
+            })?;
+            self.refresh_serverlist();
+        }
+        terminal.clear()?;
+        self.render_popup(format!("Key {} installed on {}", identity, server_name), PopupType::Info)
+    }
+
+    /**
+        Hand the terminal over to an interactive command on the connected server,
+        then close the session.
+    */
+    async fn run_session(
+        &mut self,
+        mut ssh: Arc<dyn SshSession>,
+        command: &str,
+        terminal: &mut Terminal<impl Backend>,
+    ) -> Result<()> {
+        self.render_popup("Connected!".to_string(), PopupType::Info)?;
+        self.draw(terminal)?;
+        sleep(Duration::from_millis(1500)).await;
+
+        // 处理 SSH 会话
+        let code = {
+            terminal.clear()?;
+            execute!(
+                stdout(),
+                RestorePosition,
+                Clear(ClearType::FromCursorDown),
+                crossterm::cursor::Show
+            )?;
+            match Arc::get_mut(&mut ssh).unwrap().call(command).await {
+                Ok(code) => code,
+                Err(e) => {
+                    self.render_popup(e.to_string(), PopupType::Error)?;
+                    self.is_connecting = false;
+                    1 // error occurred
+                }
+            }
+        };
+        match Arc::get_mut(&mut ssh).unwrap().close().await {
+            Ok(_) => {}
+            Err(e) => {
+                self.render_popup(e.to_string(), PopupType::Error)?;
+                self.is_connecting = false;
+                debug_log!("debug.log", "Close error: {:?}", e);
+            }
+        }
+        terminal.clear()?;
+        debug_log!("debug.log", "Exitcode: {:?}", code);
+        self.is_connecting = false;
+        if code == 0 {
+            self.show_popup = false;
+        }
+        Ok(())
     }
 
     fn refresh_serverlist(&mut self) {
@@ -502,9 +1306,25 @@ impl<'a> App<'a> {
                 username: server.user,
                 shell: server.shell,
                 port: server.port,
+                identity_files: server.identity_files,
+                multiplexer: server.multiplexer,
+                ssh_options: server.ssh_options,
+                proxy_jump: server.proxy_jump,
+                group: server.group,
+                tags: server.tags,
+                notes: server.notes,
             })
             .collect();
-        self.server_list = ServerList::with_items(server_items);
+        self.server_list.set_items(server_items);
+    }
+
+    /// add a warning below the popup that is shown, if any
+    fn render_warning(&mut self, warning: String) -> Result<()> {
+        let message = match self.popup_info.take() {
+            Some(info) if self.show_popup => format!("{}\n{}", info.message, warning),
+            _ => warning,
+        };
+        self.render_popup(message, PopupType::Error)
     }
 
     fn render_popup(&mut self, message: String, popup_type: PopupType) -> Result<()> {
@@ -517,78 +1337,49 @@ impl<'a> App<'a> {
     }
 }
 
-fn find_best_key() -> Option<PathBuf> {
-    let home_dir = dirs::home_dir()?;
-    let ssh_dir = home_dir.join(".ssh");
-
-    let key_priorities = [
-        "id_ecdsa",     // ecdsa-sha2-nistp256
-        "id_ecdsa_384", // ecdsa-sha2-nistp384
-        "id_ecdsa_521", // ecdsa-sha2-nistp521
-        "id_ed25519",   // ssh-ed25519
-        "id_rsa",       // rsa-sha2-256, rsa-sha2-512, ssh-rsa
-    ];
-
-    for key_name in key_priorities.iter() {
-        let key_path = ssh_dir.join(key_name);
-        if key_path.exists() {
-            return Some(key_path);
-        }
-    }
-
-    None
-}
-
 fn load_key_with_passphrase(
     key_path: PathBuf,
+    vault: &mut Vault,
+    encryption_key: &[u8; 32],
     terminal: &mut Terminal<impl Backend>,
-) -> Result<russh_keys::key::KeyPair> {
-    load_secret_key(key_path.clone(), None).or_else(|e| {
-        if let russh_keys::Error::KeyIsEncrypted = e {
-            let mut input_box = PopupInputBox::new(" Input key's passphrase: ".to_string());
-            let passphrase = input_box
-                .run(terminal)?
-                .ok_or_else(|| anyhow::anyhow!("Input is empty"))?;
-            load_secret_key(key_path, Some(passphrase.as_str())).map_err(|e| e.into())
-        } else {
-            Err(e.into())
+) -> Result<(russh_keys::key::KeyPair, Option<String>)> {
+    match load_secret_key(key_path.clone(), None) {
+        Err(russh_keys::Error::KeyIsEncrypted) => {}
+        result => return Ok((result?, None)),
+    }
+    let vault_path = display_path(&key_path);
+    // try the remembered passphrase first, ask again if it no longer works
+    let stored = vault.key_passphrase(&vault_path, encryption_key)?;
+    if let Some(passphrase) = &stored {
+        if let Ok(key_pair) = load_secret_key(key_path.clone(), Some(passphrase.as_str())) {
+            return Ok((key_pair, None));
         }
-    })
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::fs::File;
-    use tempfile::TempDir;
-
-    #[test]
-    fn test_find_best_key() {
-        // Create a temporary directory to simulate the home directory
-        let temp_dir = TempDir::new().unwrap();
-        let home_dir = temp_dir.path();
-        let ssh_dir = home_dir.join(".ssh");
-        std::fs::create_dir(&ssh_dir).unwrap();
-
-        // Simulate environment variable
-        std::env::set_var("HOME", home_dir.to_str().unwrap());
-
-        // Test scenario 1: No key files present
-        assert_eq!(find_best_key(), None);
-
-        // Test scenario 2: Only id_rsa present
-        File::create(ssh_dir.join("id_rsa")).unwrap();
-        assert_eq!(find_best_key(), Some(ssh_dir.join("id_rsa")));
-
-        // Test scenario 3: Both id_rsa and id_ed25519 present
-        File::create(ssh_dir.join("id_ed25519")).unwrap();
-        assert_eq!(find_best_key(), Some(ssh_dir.join("id_ed25519")));
-
-        // Test scenario 4: Multiple keys present, should select the highest priority one
-        File::create(ssh_dir.join("id_ecdsa")).unwrap();
-        assert_eq!(find_best_key(), Some(ssh_dir.join("id_ecdsa")));
-
-        // Cleanup
-        temp_dir.close().unwrap();
     }
+    let title = if stored.is_some() {
+        format!(" Remembered passphrase of {} is wrong, input again: ", vault_path)
+    } else {
+        format!(" Input passphrase of {}: ", vault_path)
+    };
+    let mut input_box = PopupInputBox::new(title).with_remember(stored.is_some());
+    let passphrase = input_box
+        .run(terminal)?
+        .ok_or_else(|| anyhow::anyhow!("no passphrase entered"))?;
+    let key_pair = load_secret_key(key_path, Some(passphrase.as_str()))
+        .map_err(|_| anyhow::anyhow!("wrong passphrase"))?;
+    let saved = if input_box.remember() {
+        vault.set_key_passphrase(&vault_path, &passphrase, encryption_key)
+    } else if stored.is_some() {
+        // the user chose to stop remembering the outdated passphrase
+        vault.remove_key_passphrase(&vault_path, encryption_key)
+    } else {
+        Ok(())
+    };
+    // the key is good even if the vault isn't, don't fail the login over it
+    let warning = saved.err().map(|e| {
+        format!(
+            "The passphrase of {} was not remembered, saving the vault failed: {:#}",
+            vault_path, e
+        )
+    });
+    Ok((key_pair, warning))
 }
@@ -0,0 +1,652 @@
+use anyhow::{Context, Result};
+use serde_yaml::{Mapping, Value};
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::config::app_config::Server;
+use crate::config::import::ImportReport;
+use crate::config::inventory::{import_records, ServerRecord};
+use crate::config::ssh_config::host_alias;
+use crate::ssh::identity::VAULT_KEY_PREFIX;
+
+/// groups every inventory has, they don't become tags
+const IMPLICIT_GROUPS: [&str; 2] = ["all", "ungrouped"];
+
+#[derive(Debug, Default)]
+struct Group {
+    hosts: Vec<String>,
+    vars: BTreeMap<String, String>,
+    children: Vec<String>,
+}
+
+/// hosts and groups of an Ansible inventory
+#[derive(Debug, Default)]
+pub struct Inventory {
+    /// host names in the order they first appear
+    hosts: Vec<String>,
+    host_vars: BTreeMap<String, BTreeMap<String, String>>,
+    groups: BTreeMap<String, Group>,
+    pub warnings: Vec<String>,
+}
+
+impl Inventory {
+    fn group(&mut self, name: &str) -> &mut Group {
+        self.groups.entry(name.to_string()).or_default()
+    }
+
+    fn add_host(&mut self, host: &str, group: &str, vars: Vec<(String, String)>) {
+        if !self.host_vars.contains_key(host) {
+            self.hosts.push(host.to_string());
+        }
+        self.host_vars.entry(host.to_string()).or_default().extend(vars);
+        let group = self.group(group);
+        if !group.hosts.iter().any(|h| h == host) {
+            group.hosts.push(host.to_string());
+        }
+    }
+
+    /**
+        groups the host belongs to, directly or through `children`
+        nearest first and `all` last, which is also the order variables apply in
+    */
+    fn groups_of(&self, host: &str) -> Vec<String> {
+        let mut level: Vec<String> = self
+            .groups
+            .iter()
+            .filter(|(_, group)| group.hosts.iter().any(|h| h == host))
+            .map(|(name, _)| name.clone())
+            .collect();
+        let mut seen = BTreeSet::new();
+        let mut groups = Vec::new();
+        while !level.is_empty() {
+            let mut parents = Vec::new();
+            for name in level {
+                if !seen.insert(name.clone()) {
+                    continue;
+                }
+                parents.extend(
+                    self.groups
+                        .iter()
+                        .filter(|(_, group)| group.children.contains(&name))
+                        .map(|(parent, _)| parent.clone()),
+                );
+                groups.push(name);
+            }
+            level = parents;
+        }
+        groups.retain(|name| name != "all");
+        groups.push("all".to_string());
+        groups
+    }
+
+    /// first of the variables set for the host, its groups, then `all`
+    fn var(&self, host: &str, groups: &[String], names: &[&str]) -> Option<String> {
+        let scopes = self
+            .host_vars
+            .get(host)
+            .into_iter()
+            .chain(groups.iter().filter_map(|name| self.groups.get(name).map(|g| &g.vars)));
+        for vars in scopes {
+            if let Some(value) = names.iter().find_map(|name| vars.get(*name)) {
+                return Some(value.clone());
+            }
+        }
+        None
+    }
+
+    /**
+        merge the hosts into the saved servers, by name
+        the groups of a host become its tags
+    */
+    pub fn import(self, existing: &[Server], user: &str) -> ImportReport {
+        let mut warnings = self.warnings.clone();
+        let records = self
+            .hosts
+            .iter()
+            .map(|host| {
+                let groups = self.groups_of(host);
+                let port = self
+                    .var(host, &groups, &["ansible_port", "ansible_ssh_port"])
+                    .and_then(|port| match port.parse::<i64>() {
+                        Ok(port) => Some(port),
+                        Err(_) => {
+                            warnings.push(format!("{}: invalid ansible_port {}", host, port));
+                            None
+                        }
+                    });
+                let tags: Vec<&str> = groups
+                    .iter()
+                    .map(String::as_str)
+                    .filter(|name| !IMPLICIT_GROUPS.contains(name))
+                    .collect();
+                ServerRecord {
+                    name: host.clone(),
+                    ip: self
+                        .var(host, &groups, &["ansible_host", "ansible_ssh_host"])
+                        .unwrap_or_else(|| host.clone()),
+                    user: self.var(host, &groups, &["ansible_user", "ansible_ssh_user"]),
+                    port,
+                    identity: self.var(
+                        host,
+                        &groups,
+                        &["ansible_ssh_private_key_file", "ansible_private_key_file"],
+                    ),
+                    proxy_jump: self
+                        .var(host, &groups, &["ansible_ssh_common_args"])
+                        .and_then(|args| jump_host(&args)),
+                    tags: Some(tags.join(",")),
+                    ..Default::default()
+                }
+            })
+            .collect();
+        let mut report = import_records(records, existing, user);
+        warnings.append(&mut report.warnings);
+        report.warnings = warnings;
+        report
+    }
+}
+
+/**
+    the jump host in `ansible_ssh_common_args`, given as `-J host`
+    or `-o ProxyJump=host`
+*/
+fn jump_host(args: &str) -> Option<String> {
+    let mut words = args.split_whitespace();
+    while let Some(word) = words.next() {
+        let option = match word {
+            "-J" => return words.next().map(str::to_string),
+            "-o" => words.next()?,
+            _ => word.strip_prefix("-o").unwrap_or(""),
+        };
+        if let Some((key, value)) = option.split_once('=') {
+            if key.eq_ignore_ascii_case("proxyjump") {
+                return Some(value.to_string());
+            }
+        }
+    }
+    None
+}
+
+/**
+    expand host ranges like `web[01:10].example.com` or `db-[a:c]`,
+    with an optional step `[1:10:2]`
+*/
+fn expand_hosts(pattern: &str) -> Result<Vec<String>, String> {
+    let Some((prefix, rest)) = pattern.split_once('[') else {
+        return Ok(vec![pattern.to_string()]);
+    };
+    let (range, suffix) = rest
+        .split_once(']')
+        .ok_or_else(|| format!("unclosed range in {}", pattern))?;
+    let bounds: Vec<&str> = range.split(':').collect();
+    let invalid = || format!("invalid range [{}] in {}", range, pattern);
+    let (start, end) = match bounds[..] {
+        [start, end] | [start, end, _] => (start, end),
+        _ => return Err(invalid()),
+    };
+    let step = match bounds.get(2) {
+        Some(step) => step.parse::<usize>().ok().filter(|s| *s > 0).ok_or_else(invalid)?,
+        None => 1,
+    };
+    let items: Vec<String> = if let (Ok(first), Ok(last)) = (start.parse::<u64>(), end.parse::<u64>()) {
+        // a leading zero pads every number to the same width
+        let width = if start.len() > 1 && start.starts_with('0') { start.len() } else { 0 };
+        (first..=last)
+            .step_by(step)
+            .map(|n| format!("{:0width$}", n, width = width))
+            .collect()
+    } else {
+        let (Some(first), Some(last)) = (single_letter(start), single_letter(end)) else {
+            return Err(invalid());
+        };
+        (first..=last).step_by(step).map(String::from).collect()
+    };
+    let mut hosts = Vec::new();
+    for item in items {
+        for tail in expand_hosts(suffix)? {
+            hosts.push(format!("{}{}{}", prefix, item, tail));
+        }
+    }
+    Ok(hosts)
+}
+
+fn single_letter(bound: &str) -> Option<char> {
+    let mut chars = bound.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_ascii_alphabetic() => Some(c),
+        _ => None,
+    }
+}
+
+/// split a line on whitespace, keeping quoted parts together
+fn split_words(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quote = None;
+    for c in line.chars() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => word.push(c),
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c.is_whitespace() => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            None => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+fn key_value(word: &str) -> Option<(String, String)> {
+    let (key, value) = word.split_once('=')?;
+    Some((key.trim().to_string(), value.trim().to_string()))
+}
+
+#[derive(Clone, Copy)]
+enum Section {
+    Hosts,
+    Vars,
+    Children,
+    Unknown,
+}
+
+/**
+    read an INI inventory
+    hosts before the first section are in `ungrouped`
+*/
+pub fn parse_ini(content: &str) -> Inventory {
+    let mut inventory = Inventory::default();
+    let mut group = "ungrouped".to_string();
+    let mut section = Section::Hosts;
+    for (index, line) in content.lines().enumerate() {
+        let number = index + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            (group, section) = match header.split_once(':') {
+                None => (header.to_string(), Section::Hosts),
+                Some((name, "vars")) => (name.to_string(), Section::Vars),
+                Some((name, "children")) => (name.to_string(), Section::Children),
+                Some(_) => {
+                    inventory
+                        .warnings
+                        .push(format!("line {}: unknown section [{}] ignored", number, header));
+                    (header.to_string(), Section::Unknown)
+                }
+            };
+            if !matches!(section, Section::Unknown) {
+                inventory.group(&group);
+            }
+            continue;
+        }
+        match section {
+            Section::Hosts => {
+                let words = split_words(line);
+                let Some((pattern, words)) = words.split_first() else {
+                    continue;
+                };
+                let mut vars = Vec::new();
+                for word in words {
+                    match key_value(word) {
+                        Some(var) => vars.push(var),
+                        None => inventory
+                            .warnings
+                            .push(format!("line {}: {} is not a key=value pair", number, word)),
+                    }
+                }
+                let hosts = match expand_hosts(pattern) {
+                    Ok(hosts) => hosts,
+                    Err(e) => {
+                        inventory.warnings.push(format!("line {}: {}", number, e));
+                        continue;
+                    }
+                };
+                for host in hosts {
+                    let mut vars = vars.clone();
+                    // `host:port`, but not an IPv6 address
+                    let host = match host.split_once(':') {
+                        Some((name, port)) if !port.contains(':') => {
+                            vars.insert(0, ("ansible_port".to_string(), port.to_string()));
+                            name.to_string()
+                        }
+                        _ => host,
+                    };
+                    inventory.add_host(&host, &group, vars);
+                }
+            }
+            Section::Vars => match key_value(line) {
+                Some((key, value)) => {
+                    let value = split_words(&value).join(" ");
+                    inventory.group(&group).vars.insert(key, value);
+                }
+                None => inventory
+                    .warnings
+                    .push(format!("line {}: {} is not a key=value pair", number, line)),
+            },
+            Section::Children => {
+                inventory.group(line);
+                inventory.group(&group).children.push(line.to_string());
+            }
+            Section::Unknown => {}
+        }
+    }
+    inventory
+}
+
+/// variables are strings to us, lists and maps are left out
+fn scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+fn vars_of(value: &Value) -> Vec<(String, String)> {
+    value
+        .as_mapping()
+        .into_iter()
+        .flatten()
+        .filter_map(|(key, value)| Some((key.as_str()?.to_string(), scalar(value)?)))
+        .collect()
+}
+
+impl Inventory {
+    fn read_yaml_group(&mut self, name: &str, value: &Value) {
+        self.group(name);
+        let Some(group) = value.as_mapping() else {
+            return;
+        };
+        if let Some(hosts) = group.get("hosts").and_then(Value::as_mapping) {
+            for (host, vars) in hosts {
+                let Some(pattern) = scalar(host) else {
+                    continue;
+                };
+                match expand_hosts(&pattern) {
+                    Ok(expanded) => {
+                        for host in expanded {
+                            self.add_host(&host, name, vars_of(vars));
+                        }
+                    }
+                    Err(e) => self.warnings.push(format!("group {}: {}", name, e)),
+                }
+            }
+        }
+        let vars = group.get("vars").map(vars_of).unwrap_or_default();
+        self.group(name).vars.extend(vars);
+        if let Some(children) = group.get("children").and_then(Value::as_mapping) {
+            for (child, value) in children {
+                let Some(child) = scalar(child) else {
+                    continue;
+                };
+                self.group(name).children.push(child.clone());
+                self.read_yaml_group(&child, value);
+            }
+        }
+    }
+}
+
+/**
+    read a YAML inventory, groups at the top level like `all:`
+*/
+pub fn parse_yaml(content: &str) -> Result<Inventory> {
+    let root: Value =
+        serde_yaml::from_str(content).context("Failed to parse Ansible YAML inventory.")?;
+    let mut inventory = Inventory::default();
+    match &root {
+        Value::Null => {}
+        Value::Mapping(groups) => {
+            for (name, group) in groups {
+                if let Some(name) = scalar(name) {
+                    inventory.read_yaml_group(&name, group);
+                }
+            }
+        }
+        _ => anyhow::bail!("An Ansible inventory starts with its groups, like all:"),
+    }
+    Ok(inventory)
+}
+
+/**
+    connection variables of a server
+    Ansible takes a single key file, keys in the vault are left out
+*/
+fn host_vars(server: &Server) -> Vec<(&'static str, String)> {
+    let mut vars = vec![
+        ("ansible_host", server.ip.clone()),
+        ("ansible_user", server.user.clone()),
+        ("ansible_port", server.port.to_string()),
+    ];
+    if let Some(key) = server
+        .identity_files
+        .iter()
+        .find(|entry| !entry.starts_with(VAULT_KEY_PREFIX))
+    {
+        vars.push(("ansible_ssh_private_key_file", key.clone()));
+    }
+    if let Some(proxy_jump) = &server.proxy_jump {
+        vars.push(("ansible_ssh_common_args", format!("-J {}", proxy_jump)));
+    }
+    vars
+}
+
+/// servers by tag, untagged ones under `ungrouped`
+fn groups(servers: &[Server]) -> BTreeMap<&str, Vec<&Server>> {
+    let mut groups: BTreeMap<&str, Vec<&Server>> = BTreeMap::new();
+    for server in servers {
+        if server.tags.is_empty() {
+            groups.entry("ungrouped").or_default().push(server);
+        }
+        for tag in &server.tags {
+            groups.entry(tag.as_str()).or_default().push(server);
+        }
+    }
+    groups
+}
+
+/**
+    INI inventory with a section per tag
+    variables are written where a host first appears
+*/
+pub fn export_ini(servers: &[Server]) -> String {
+    let mut written = BTreeSet::new();
+    let mut sections = Vec::new();
+    for (group, members) in groups(servers) {
+        let mut lines = vec![format!("[{}]", group)];
+        for server in members {
+            let name = host_alias(&server.name);
+            if !written.insert(server.id.as_str()) {
+                lines.push(name);
+                continue;
+            }
+            let vars: Vec<String> = host_vars(server)
+                .into_iter()
+                .map(|(key, value)| {
+                    if value.contains(char::is_whitespace) {
+                        format!("{}=\"{}\"", key, value)
+                    } else {
+                        format!("{}={}", key, value)
+                    }
+                })
+                .collect();
+            lines.push(format!("{} {}", name, vars.join(" ")));
+        }
+        sections.push(lines.join("\n"));
+    }
+    let mut ini = sections.join("\n\n");
+    ini.push('\n');
+    ini
+}
+
+/**
+    YAML inventory with a child of `all` per tag
+*/
+pub fn export_yaml(servers: &[Server]) -> Result<String> {
+    let mut written = BTreeSet::new();
+    let mut children = Mapping::new();
+    for (group, members) in groups(servers) {
+        let mut hosts = Mapping::new();
+        for server in members {
+            let vars = if written.insert(server.id.as_str()) {
+                let mut vars = Mapping::new();
+                for (key, value) in host_vars(server) {
+                    let value = match key {
+                        "ansible_port" => Value::from(server.port),
+                        _ => Value::from(value),
+                    };
+                    vars.insert(Value::from(key), value);
+                }
+                Value::Mapping(vars)
+            } else {
+                Value::Null
+            };
+            hosts.insert(Value::from(host_alias(&server.name)), vars);
+        }
+        let mut group_value = Mapping::new();
+        group_value.insert(Value::from("hosts"), Value::Mapping(hosts));
+        children.insert(Value::from(group), Value::Mapping(group_value));
+    }
+    let mut all = Mapping::new();
+    all.insert(Value::from("children"), Value::Mapping(children));
+    let mut root = Mapping::new();
+    root.insert(Value::from("all"), Value::Mapping(all));
+    Ok(serde_yaml::to_string(&root)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_hosts() {
+        assert_eq!(expand_hosts("web").unwrap(), vec!["web"]);
+        assert_eq!(
+            expand_hosts("web[08:10].example.com").unwrap(),
+            vec!["web08.example.com", "web09.example.com", "web10.example.com"]
+        );
+        assert_eq!(expand_hosts("db-[a:c]").unwrap(), vec!["db-a", "db-b", "db-c"]);
+        assert_eq!(expand_hosts("n[1:5:2]").unwrap(), vec!["n1", "n3", "n5"]);
+        assert_eq!(expand_hosts("r[1:2]-[a:b]").unwrap().len(), 4);
+        assert!(expand_hosts("web[1-3]").is_err());
+    }
+
+    #[test]
+    fn test_parse_ini() {
+        let ini = r#"
+            # hosts outside any group
+            jump.example.com ansible_user=admin
+
+            [web]
+            web[1:2].example.com
+            legacy:2200 ansible_ssh_private_key_file="~/.ssh/id legacy"
+
+            [db]
+            db1 ansible_host=10.0.0.10 ansible_ssh_common_args='-o ProxyJump=jump.example.com'
+
+            [web:vars]
+            ansible_user=deploy
+
+            [prod:children]
+            web
+            db
+
+            [all:vars]
+            ansible_user=ops
+            ansible_port=22
+        "#;
+        let inventory = parse_ini(ini);
+        assert!(inventory.warnings.is_empty());
+        let report = inventory.import(&[], "alice");
+        let servers = &report.servers;
+        let names: Vec<&str> = servers.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec!["jump.example.com", "web1.example.com", "web2.example.com", "legacy", "db1"]
+        );
+        assert_eq!(servers[0].user, "admin");
+        assert!(servers[0].tags.is_empty());
+        assert_eq!(servers[1].user, "deploy");
+        assert_eq!(servers[1].tags, vec!["web", "prod"]);
+        assert_eq!((servers[3].ip.as_str(), servers[3].port), ("legacy", 2200));
+        assert_eq!(servers[3].identity_files, vec!["~/.ssh/id legacy"]);
+        assert_eq!((servers[4].ip.as_str(), servers[4].user.as_str()), ("10.0.0.10", "ops"));
+        assert_eq!(servers[4].proxy_jump.as_deref(), Some("jump.example.com"));
+        assert_eq!(servers[4].tags, vec!["db", "prod"]);
+    }
+
+    #[test]
+    fn test_parse_yaml() {
+        let yaml = r#"
+all:
+  vars:
+    ansible_user: ops
+  children:
+    prod:
+      children:
+        db:
+          hosts:
+            db[1:2]:
+              ansible_port: 2222
+          vars:
+            ansible_user: dba
+    staging:
+      hosts:
+        db1:
+"#;
+        let report = parse_yaml(yaml).unwrap().import(&[], "alice");
+        let db1 = &report.servers[0];
+        assert_eq!(report.servers.len(), 2);
+        assert_eq!((db1.name.as_str(), db1.user.as_str(), db1.port), ("db1", "dba", 2222));
+        assert_eq!(db1.tags, vec!["db", "staging", "prod"]);
+    }
+
+    #[test]
+    fn test_export() {
+        let mut web = Server::new(
+            "web 1".to_string(),
+            "10.0.0.5".to_string(),
+            "deploy".to_string(),
+            "bash".to_string(),
+            2222,
+        );
+        web.identity_files = vec!["vault:deploy".to_string(), "~/.ssh/id_web".to_string()];
+        web.tags = vec!["prod".to_string(), "web".to_string()];
+        let mut jump = Server::new(
+            "jump".to_string(),
+            "jump.example.com".to_string(),
+            "admin".to_string(),
+            "bash".to_string(),
+            22,
+        );
+        jump.proxy_jump = Some("gw.example.com".to_string());
+        let servers = vec![web, jump];
+
+        let ini = export_ini(&servers);
+        assert!(ini.starts_with(
+            "[prod]\nweb-1 ansible_host=10.0.0.5 ansible_user=deploy ansible_port=2222 \
+             ansible_ssh_private_key_file=~/.ssh/id_web\n\n[ungrouped]\n"
+        ));
+        assert!(ini.contains("\n[web]\nweb-1\n"));
+
+        // both formats come back as the same servers
+        let yaml = export_yaml(&servers).unwrap();
+        for inventory in [parse_ini(&ini), parse_yaml(&yaml).unwrap()] {
+            let report = inventory.import(&[], "alice");
+            assert!(report.warnings.is_empty());
+            let web = &report.servers[0];
+            assert_eq!((web.name.as_str(), web.ip.as_str(), web.port), ("web-1", "10.0.0.5", 2222));
+            assert_eq!(web.identity_files, vec!["~/.ssh/id_web"]);
+            assert_eq!(web.tags, vec!["prod", "web"]);
+            let jump = &report.servers[1];
+            assert_eq!(jump.proxy_jump.as_deref(), Some("gw.example.com"));
+            assert!(jump.tags.is_empty());
+        }
+    }
+}
@@ -1,9 +1,14 @@
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
-use std::{fs, path::{Path, PathBuf}};
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
-use crate::helper::{get_file_path, CONFIG_FILE};
+use crate::config::{backup, migrate, ssh_config, storage};
+use crate::config::paths::ConfigPaths;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Server {
@@ -13,6 +18,89 @@ pub struct Server {
     pub user: String,
     pub shell: String,
     pub port: u16,
+    /// private keys to try, in order, when no password is stored
+    /// empty means the default keys
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub identity_files: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub multiplexer: Option<Multiplexer>,
+    #[serde(default, skip_serializing_if = "SshOptions::is_default")]
+    pub ssh_options: SshOptions,
+    /// jump host as `[user@]host[:port]`, kept for exporting to ssh config
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy_jump: Option<String>,
+    /// folder in the server list, levels separated by `/` like `prod/db`
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub group: String,
+    /// labels to group and find servers, like the Ansible groups it came from
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// free text like "owner: storage team", may span lines
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub notes: String,
+}
+
+/// per-server overrides of the SSH transport settings
+/// empty lists and None mean "use the default"
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct SshOptions {
+    /// key exchange algorithms in order of preference
+    pub kex: Vec<String>,
+    /// symmetric ciphers in order of preference
+    pub ciphers: Vec<String>,
+    /// MAC algorithms in order of preference
+    pub macs: Vec<String>,
+    /// host key algorithms in order of preference
+    pub host_key_algorithms: Vec<String>,
+    pub compression: bool,
+    /// seconds to wait for the connection to be established, 0 waits forever
+    pub connect_timeout: Option<u64>,
+    /// seconds without any traffic before the connection is closed, 0 never closes it
+    pub inactivity_timeout: Option<u64>,
+    /// seconds between keepalive messages, 0 sends none
+    pub keepalive_interval: Option<u64>,
+}
+
+impl SshOptions {
+    pub fn is_default(&self) -> bool {
+        *self == SshOptions::default()
+    }
+}
+
+/// terminal multiplexer to attach to (or create) on connect
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Multiplexer {
+    pub kind: MultiplexerKind,
+    pub session: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MultiplexerKind {
+    Tmux,
+    Screen,
+}
+
+impl fmt::Display for MultiplexerKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MultiplexerKind::Tmux => write!(f, "tmux"),
+            MultiplexerKind::Screen => write!(f, "screen"),
+        }
+    }
+}
+
+impl FromStr for MultiplexerKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "tmux" => Ok(MultiplexerKind::Tmux),
+            "screen" => Ok(MultiplexerKind::Screen),
+            other => Err(anyhow::anyhow!("Unknown multiplexer {}", other)),
+        }
+    }
 }
 
 impl Server {
@@ -24,12 +112,59 @@ impl Server {
             user,
             shell,
             port,
+            identity_files: Vec::new(),
+            multiplexer: None,
+            ssh_options: SshOptions::default(),
+            proxy_jump: None,
+            group: String::new(),
+            tags: Vec::new(),
+            notes: String::new(),
         }
     }
+
+    /**
+        check if the server is in the group or one of its subgroups
+    */
+    pub fn in_group(&self, group: &str) -> bool {
+        group_contains(&self.group, group)
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug, Default)]
+/**
+    check if a server group is the group or one of its subgroups
+*/
+pub fn group_contains(server_group: &str, group: &str) -> bool {
+    let group = normalize_group(group);
+    group.is_empty()
+        || server_group == group
+        || server_group
+            .strip_prefix(&group)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/**
+    tidy a group path typed by the user: `/ prod//db ` -> `prod/db`
+*/
+pub fn normalize_group(group: &str) -> String {
+    group
+        .split('/')
+        .map(str::trim)
+        .filter(|level| !level.is_empty())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Config {
+    /// ssh config file rewritten with the servers on every save
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub managed_ssh_config: Option<String>,
+    /// backups kept of config and vault, 10 if unset, 0 turns them off
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backup_count: Option<usize>,
+    /// prints the vault passphrase instead of asking for it, like `pass show ssh-utils`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub passphrase_command: Option<String>,
     pub servers: Vec<Server>,
 }
 
@@ -38,29 +173,37 @@ impl Config {
         Save the current config to the specified file.
     */
     pub fn save(&self) -> Result<()> {
-        let file_path = get_file_path(CONFIG_FILE)?;
-        let config_str = toml::to_string(self).context("Failed to serialize config to TOML.")?;
-        
-        // Ensure the directory exists
-        let path = PathBuf::from(&file_path);
-        if let Some(parent) = path.parent() {
-            if !parent.exists() {
-                fs::create_dir_all(parent).context(format!(
-                    "Failed to create config directory at {:?}",
-                    parent
-                ))?;
-            }
-        }
+        let paths = ConfigPaths::get()?;
+        let config_str = self.to_toml()?;
 
-        // Write the config to the file
-        fs::write(&file_path, config_str)
-            .context(format!("Failed to write config to file at {:?}", file_path))?;
+        // Replace the file unless another instance changed it meanwhile
+        let _lock = storage::lock(paths.dir())?;
+        backup::snapshot(paths)?;
+        storage::write(&paths.config_file(), config_str.as_bytes(), 0o644)?;
+        self.write_managed_ssh_config()
+    }
 
+    /**
+        rewrite the managed ssh config file, if there is one
+    */
+    pub fn write_managed_ssh_config(&self) -> Result<()> {
+        if let Some(managed) = &self.managed_ssh_config {
+            ssh_config::write_managed_config(managed, &self.servers)?;
+        }
         Ok(())
     }
 
     /**
-        Modify a server's information and save the config.
+        the file content, in the current format
+    */
+    pub fn to_toml(&self) -> Result<String> {
+        let body = toml::to_string(self).context("Failed to serialize config to TOML.")?;
+        Ok(migrate::stamp(&migrate::CONFIG, &body))
+    }
+
+    /**
+        Modify a server's information.
+        saved together with the vault by `store::update`
     */
     pub fn modify_server(&mut self, id: &str, new_server: Server) -> Result<()> {
         if let Some(server) = self.servers.iter_mut().find(|server| server.id == id) {
@@ -69,7 +212,13 @@ impl Config {
             server.user = new_server.user.clone();
             server.shell = new_server.shell.clone();
             server.port = new_server.port;
-            self.save()?;
+            server.identity_files = new_server.identity_files.clone();
+            server.multiplexer = new_server.multiplexer.clone();
+            server.ssh_options = new_server.ssh_options.clone();
+            server.proxy_jump = new_server.proxy_jump.clone();
+            server.group = new_server.group.clone();
+            server.tags = new_server.tags.clone();
+            server.notes = new_server.notes.clone();
         } else {
             return Err(anyhow::anyhow!("Server with id {} not found", id));
         }
@@ -77,21 +226,18 @@ impl Config {
     }
 
     /**
-        Add a new server and save the config.
+        Add a new server.
     */
-    pub fn add_server(&mut self, new_server: Server) -> Result<()> {
+    pub fn add_server(&mut self, new_server: Server) {
         self.servers.push(new_server);
-        self.save()?;
-        Ok(())
     }
 
     /**
-        Delete a server by id and save the config.
+        Delete a server by id.
     */
     pub fn delete_server(&mut self, id: &str) -> Result<()> {
         if let Some(pos) = self.servers.iter().position(|server| server.id == id) {
             self.servers.remove(pos);
-            self.save()?;
         } else {
             return Err(anyhow::anyhow!("Server with id {} not found", id));
         }
@@ -104,23 +250,19 @@ impl Config {
     if not exists, create them
 */
 pub fn ensure_config_exists() -> Result<()> {
-    let mut config_dir: PathBuf = if cfg!(debug_assertions) {
-        ".".into() // current running dir
-    } else {
-        dirs::home_dir().context("Unable to reach user's home directory.")?
-    };
-    config_dir.push(".config/ssh-utils");
+    let paths = ConfigPaths::get()?;
+    let config_dir = paths.dir();
 
     // Ensure the config directory exists
     if !config_dir.exists() {
-        fs::create_dir_all(&config_dir).context(format!(
+        fs::create_dir_all(config_dir).context(format!(
             "Failed to create config directory at {:?}",
             config_dir
         ))?;
     }
 
     // Ensure the config file exists
-    let config_file_path = config_dir.join("config.toml");
+    let config_file_path = paths.config_file();
     if !config_file_path.exists() {
         fs::File::create(&config_file_path).context(format!(
             "Failed to create config file at {:?}",
@@ -133,35 +275,45 @@ pub fn ensure_config_exists() -> Result<()> {
 
 /**
     read toml format config
-    from "~/.config/ssh-utils/config.toml"
+    from "config.toml" in the config directory
 */
 pub fn read_config() -> Result<Config> {
-    let config_path = get_config_path()?;
-    read_config_from_path(&config_path)
+    Ok(read_config_from_path(ConfigPaths::get()?.config_file())?.0)
 }
 
-fn get_config_path() -> Result<PathBuf> {
-    if cfg!(debug_assertions) {
-        Ok(".config/ssh-utils/config.toml".into())
-    } else {
-        let mut path = dirs::home_dir().context("Unable to reach user's home directory.")?;
-        path.push(".config/ssh-utils/config.toml");
-        Ok(path)
-    }
+/**
+    upgrade a config file written by an older ssh-utils
+    returns where the original is kept, None if it was up to date
+*/
+pub fn upgrade_config() -> Result<Option<PathBuf>> {
+    Ok(read_config_from_path(ConfigPaths::get()?.config_file())?.1)
 }
 
-fn read_config_from_path<P: AsRef<Path>>(config_path: P) -> Result<Config> {
-    let config_str = fs::read_to_string(&config_path)
-        .with_context(|| format!("Unable to read ssh-utils' config file at {:?}", config_path.as_ref()))?;
+/// the backup of the original is returned with the config if the file was upgraded
+fn read_config_from_path<P: AsRef<Path>>(config_path: P) -> Result<(Config, Option<PathBuf>)> {
+    let config_path = config_path.as_ref();
+    let config_str = String::from_utf8(storage::read(config_path)?)
+        .with_context(|| format!("Unable to read ssh-utils' config file at {:?}", config_path))?;
 
     if config_str.trim().is_empty() {
-        return Ok(Config::default());
+        return Ok((Config::default(), None));
     }
 
-    let config: Config = toml::from_str(&config_str)
+    let mut table: toml::Table = toml::from_str(&config_str)
+        .context("Failed to parse ssh-utils' config file.")?;
+    let version = migrate::upgrade(&mut table, &migrate::CONFIG)?;
+    let config: Config = table
+        .try_into()
         .context("Failed to parse ssh-utils' config file.")?;
 
-    Ok(config)
+    if version < migrate::CONFIG.version() {
+        // keep the original, then write the upgraded file so this happens once
+        let _lock = storage::lock(config_path.parent().unwrap_or(Path::new(".")))?;
+        let backup = migrate::backup(config_path, version)?;
+        storage::write(config_path, config.to_toml()?.as_bytes(), 0o644)?;
+        return Ok((config, Some(backup)));
+    }
+    Ok((config, None))
 }
 
 #[cfg(test)]
@@ -175,7 +327,7 @@ mod tests {
         let temp_dir = TempDir::new().unwrap();
         let config_path = temp_dir.path().join("config.toml");
         fs::write(&config_path, "").unwrap();
-        let config = read_config_from_path(&config_path).unwrap();
+        let (config, _) = read_config_from_path(&config_path).unwrap();
         assert!(config.servers.is_empty());
     }
 
@@ -202,9 +354,167 @@ mod tests {
         "#;
         fs::write(&config_path, config_content).unwrap();
 
-        let config = read_config_from_path(&config_path).unwrap();
+        let (config, _) = read_config_from_path(&config_path).unwrap();
         assert_eq!(config.servers.len(), 2);
         assert_eq!(config.servers[0].name, "Server1");
         assert_eq!(config.servers[1].port, 2222);
+        assert!(config.servers[0].multiplexer.is_none());
+        assert!(config.servers[0].ssh_options.is_default());
+    }
+
+    #[test]
+    fn test_read_config_with_ssh_options() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join("config.toml");
+        let config_content = r#"
+            [[servers]]
+            id = "1"
+            name = "Switch"
+            ip = "10.0.0.1"
+            user = "admin"
+            shell = "bash"
+            port = 22
+
+            [servers.ssh_options]
+            kex = ["diffie-hellman-group14-sha1"]
+            compression = true
+            keepalive_interval = 30
+        "#;
+        fs::write(&config_path, config_content).unwrap();
+
+        let (config, _) = read_config_from_path(&config_path).unwrap();
+        let options = &config.servers[0].ssh_options;
+        assert_eq!(options.kex, vec!["diffie-hellman-group14-sha1"]);
+        assert!(options.ciphers.is_empty());
+        assert!(options.compression);
+        assert_eq!(options.keepalive_interval, Some(30));
+        assert_eq!(options.connect_timeout, None);
+    }
+
+    #[test]
+    fn test_read_config_with_multiplexer() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join("config.toml");
+        let config_content = r#"
+            [[servers]]
+            id = "1"
+            name = "Server1"
+            ip = "192.168.1.1"
+            user = "user1"
+            shell = "/bin/bash"
+            port = 22
+
+            [servers.multiplexer]
+            kind = "tmux"
+            session = "work"
+        "#;
+        fs::write(&config_path, config_content).unwrap();
+
+        let (config, _) = read_config_from_path(&config_path).unwrap();
+        assert_eq!(
+            config.servers[0].multiplexer,
+            Some(Multiplexer {
+                kind: MultiplexerKind::Tmux,
+                session: "work".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_groups() {
+        assert_eq!(normalize_group(" / prod//db "), "prod/db");
+        assert_eq!(normalize_group(""), "");
+
+        let mut server = Server::new(
+            "db1".to_string(),
+            "10.0.0.1".to_string(),
+            "dba".to_string(),
+            "bash".to_string(),
+            22,
+        );
+        server.group = "prod/db".to_string();
+        assert!(server.in_group("prod"));
+        assert!(server.in_group("prod/db/"));
+        assert!(server.in_group(""));
+        assert!(!server.in_group("prod/d"));
+        assert!(!server.in_group("staging"));
+    }
+
+    /// every config.toml format ever written, see tests/fixtures/config
+    fn config_fixtures() -> Vec<PathBuf> {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/config");
+        let mut fixtures: Vec<PathBuf> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        fixtures.sort();
+        fixtures
+    }
+
+    #[test]
+    fn test_read_config_fixtures() {
+        let fixtures = config_fixtures();
+        assert!(fixtures.len() >= 8);
+        for fixture in fixtures {
+            let temp_dir = TempDir::new().unwrap();
+            let config_path = temp_dir.path().join("config.toml");
+            let original = fs::read_to_string(&fixture).unwrap();
+            fs::write(&config_path, &original).unwrap();
+
+            let (config, kept) = read_config_from_path(&config_path)
+                .unwrap_or_else(|e| panic!("{:?}: {:#}", fixture, e));
+            assert!(!config.servers.is_empty(), "{:?}", fixture);
+            let upgraded = fs::read_to_string(&config_path).unwrap();
+            assert!(upgraded.starts_with("version = 1\n"), "{:?}", fixture);
+            let backup = temp_dir.path().join("config.toml.v0.bak");
+            if original.starts_with("version") {
+                assert_eq!(upgraded, original, "{:?}", fixture);
+                assert!(!backup.exists());
+                assert_eq!(kept, None);
+            } else {
+                assert_eq!(fs::read_to_string(&backup).unwrap(), original);
+                assert_eq!(kept, Some(backup));
+            }
+
+            // the upgraded file reads back the same
+            let (reread, kept) = read_config_from_path(&config_path).unwrap();
+            assert_eq!(kept, None);
+            assert_eq!(toml::to_string(&reread).unwrap(), toml::to_string(&config).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_read_config_fixture_fields() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/config");
+        let temp_dir = TempDir::new().unwrap();
+        let read = |name: &str| {
+            let config_path = temp_dir.path().join(name);
+            fs::copy(dir.join(name), &config_path).unwrap();
+            read_config_from_path(&config_path).unwrap().0
+        };
+        assert_eq!(read("v0-baseline.toml").servers[1].port, 2222);
+        assert_eq!(
+            read("v0-managed-ssh-config.toml").managed_ssh_config.as_deref(),
+            Some("~/.ssh/ssh-utils.conf")
+        );
+        let server = &read("v0-groups-notes.toml").servers[0];
+        assert_eq!(server.group, "prod/db");
+        assert_eq!(server.notes, "owner: storage team\nmaintenance window Sunday");
+        let server = &read("v1.toml").servers[0];
+        assert_eq!(server.proxy_jump.as_deref(), Some("bastion@10.0.0.1"));
+        assert_eq!(server.multiplexer.as_ref().unwrap().kind, MultiplexerKind::Screen);
+    }
+
+    #[test]
+    fn test_read_config_from_newer_version() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join("config.toml");
+        fs::write(&config_path, "version = 99\nservers = []\n").unwrap();
+        assert!(read_config_from_path(&config_path).is_err());
+        // left alone for the newer ssh-utils
+        assert_eq!(
+            fs::read_to_string(&config_path).unwrap(),
+            "version = 99\nservers = []\n"
+        );
     }
 }
\ No newline at end of file
@@ -2,20 +2,18 @@ use anyhow::Result;
 use anyhow::Context;
 use serde::{Deserialize, Serialize};
 use sha2::Digest;
+use std::fmt;
 use std::fs;
-use std::fs::Permissions;
-use std::os::unix::fs::PermissionsExt;
-use std::path::PathBuf;
 use hmac::{Hmac, Mac};
 use sha2::Sha256;
 use crate::config::crypto::*;
-use crate::helper::get_file_path;
-use crate::helper::ENCRYPTED_FILE;
+use crate::config::{backup, migrate, storage};
+use crate::config::paths::ConfigPaths;
 
 type HmacSha256 = Hmac<Sha256>;
 pub type EncryptionKey = Vec<u8>;
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct Server {
     pub id: String,
     pub password: String,
@@ -27,148 +25,552 @@ impl Server {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+/// remembered passphrase of an encrypted private key
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct KeyPassphrase {
+    /// key path as shown to the user, e.g. `~/.ssh/id_ed25519`
+    pub path: String,
+    pub passphrase: String,
+}
+
+/// private key embedded in the vault, in OpenSSH format without a passphrase
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct StoredKey {
+    pub name: String,
+    pub private_key: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
 pub struct Vault {
     pub servers: Vec<Server>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub key_passphrases: Vec<KeyPassphrase>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keys: Vec<StoredKey>,
 }
 
 impl Vault {
     pub fn save(&self, encryption_key: &[u8; 32]) -> Result<()> {
-        let encrypt_data = encrypt_vault(self, encryption_key)?;
-        let file_path = get_file_path(ENCRYPTED_FILE)?;
-        
-        // Ensure the directory exists
-        let path = PathBuf::from(&file_path);
-        if let Some(parent) = path.parent() {
-            if !parent.exists() {
-                fs::create_dir_all(parent).context(format!(
-                    "Failed to create config directory at {:?}",
-                    parent
-                ))?;
-            }
+        let paths = ConfigPaths::get()?;
+        let _lock = storage::lock(paths.dir())?;
+        let encrypt_data = encrypt_vault(self, encryption_key, &current_kdf(paths)?)?;
+        backup::snapshot(paths)?;
+        write_vault_file(paths, &encrypt_data)
+    }
+
+    /**
+        replace the password of a server, adding the record if it is missing
+        saved together with the config by `store::update`
+    */
+    pub fn set_server(&mut self, new_server: Server) {
+        if let Some(server) = self.servers.iter_mut().find(|server| server.id == new_server.id) {
+            server.password = new_server.password;
+        } else {
+            self.servers.push(new_server);
         }
+    }
 
-        // Write the encrypted data to the file
-        fs::write(&file_path, encrypt_data)
-            .context(format!("Failed to write encrypted data to file at {:?}", file_path))?;
-        
-        // Set file permissions to 0600
-        // Only owner have permissions to read and write 
-        let permissions = Permissions::from_mode(0o600);
-        fs::set_permissions(&file_path, permissions)
-            .context(format!("Failed to set permissions for file at {:?}", file_path))?;
+    /**
+        the remembered passphrase of a key, if any
+    */
+    pub fn key_passphrase(&self, path: &str, encryption_key: &[u8; 32]) -> Result<Option<String>> {
+        self.key_passphrases
+            .iter()
+            .find(|key| key.path == path)
+            .map(|key| decrypt_password(path, &key.passphrase, encryption_key))
+            .transpose()
+    }
 
+    /**
+        remember the passphrase of a key, replacing the previous one
+        if the vault can't be saved, the previous one is kept in memory as well
+    */
+    pub fn set_key_passphrase(&mut self, path: &str, passphrase: &str, encryption_key: &[u8; 32]) -> Result<()> {
+        let passphrase = encrypt_password(path, passphrase, encryption_key)?;
+        let saved = self.key_passphrases.clone();
+        if let Some(key) = self.key_passphrases.iter_mut().find(|key| key.path == path) {
+            key.passphrase = passphrase;
+        } else {
+            self.key_passphrases.push(KeyPassphrase {
+                path: path.to_string(),
+                passphrase,
+            });
+        }
+        let result = self.save(encryption_key);
+        if result.is_err() {
+            self.key_passphrases = saved;
+        }
+        result
+    }
+
+    /**
+        forget the passphrase of a key, kept in memory if the vault can't be saved
+    */
+    pub fn remove_key_passphrase(&mut self, path: &str, encryption_key: &[u8; 32]) -> Result<()> {
+        if let Some(pos) = self.key_passphrases.iter().position(|key| key.path == path) {
+            let removed = self.key_passphrases.remove(pos);
+            if let Err(e) = self.save(encryption_key) {
+                self.key_passphrases.insert(pos, removed);
+                return Err(e);
+            }
+        }
         Ok(())
     }
 
-    pub fn modify_server(&mut self, id: &str, new_server: Server, encryption_key: &[u8; 32]) -> Result<()> {
-        if let Some(server) = self.servers.iter_mut().find(|server| server.id == id) {
-            server.password = new_server.password.clone();
-            self.save(encryption_key)?;
-        } else {
-            return Err(anyhow::anyhow!("Server with id {} not found", id));
+    /**
+        embed a private key, names must be unique
+        the key is dropped again if the vault can't be saved
+    */
+    pub fn add_key(&mut self, name: &str, private_key: &str, encryption_key: &[u8; 32]) -> Result<()> {
+        if self.keys.iter().any(|key| key.name == name) {
+            return Err(anyhow::anyhow!("Key {} already exists in the vault", name));
+        }
+        self.keys.push(StoredKey {
+            name: name.to_string(),
+            private_key: encrypt_password(name, private_key, encryption_key)?,
+        });
+        let result = self.save(encryption_key);
+        if result.is_err() {
+            self.keys.pop();
+        }
+        result
+    }
+
+    /**
+        the OpenSSH private key embedded under this name
+    */
+    pub fn private_key(&self, name: &str, encryption_key: &[u8; 32]) -> Result<String> {
+        let key = self
+            .keys
+            .iter()
+            .find(|key| key.name == name)
+            .ok_or_else(|| anyhow::anyhow!("Key {} not found in the vault", name))?;
+        decrypt_password(name, &key.private_key, encryption_key)
+    }
+
+    /**
+        rename an embedded key
+        saved together with the config by `store::update`
+    */
+    pub fn rename_key(&mut self, name: &str, new_name: &str, encryption_key: &[u8; 32]) -> Result<()> {
+        if self.keys.iter().any(|key| key.name == new_name) {
+            return Err(anyhow::anyhow!("Key {} already exists in the vault", new_name));
+        }
+        // the name is part of the encryption, so the key is encrypted again
+        let private_key = self.private_key(name, encryption_key)?;
+        let private_key = encrypt_password(new_name, &private_key, encryption_key)?;
+        if let Some(key) = self.keys.iter_mut().find(|key| key.name == name) {
+            key.name = new_name.to_string();
+            key.private_key = private_key;
         }
         Ok(())
     }
 
-    pub fn add_server(&mut self, new_server: Server, encryption_key: &[u8; 32]) -> Result<()> {
-        self.servers.push(new_server);
-        self.save(encryption_key)?;
+    /**
+        remove an embedded key
+        saved together with the config by `store::update`
+    */
+    pub fn delete_key(&mut self, name: &str) -> Result<()> {
+        let Some(pos) = self.keys.iter().position(|key| key.name == name) else {
+            return Err(anyhow::anyhow!("Key {} not found in the vault", name));
+        };
+        self.keys.remove(pos);
         Ok(())
     }
 
-    pub fn delete_server(&mut self, id: &str, encryption_key: &[u8; 32]) -> Result<()> {
-        if let Some(pos) = self.servers.iter().position(|server| server.id == id) {
-            self.servers.remove(pos);
-            self.save(encryption_key)?;
-        } else {
-            return Err(anyhow::anyhow!("Server with id {} not found", id));
+    /**
+        move the remembered passphrase of a key to its new path, if there is one
+        saved together with the config by `store::update`
+    */
+    pub fn rename_key_passphrase(&mut self, path: &str, new_path: &str, encryption_key: &[u8; 32]) -> Result<()> {
+        let Some(passphrase) = self.key_passphrase(path, encryption_key)? else {
+            return Ok(());
+        };
+        // the path is part of the encryption as well
+        let passphrase = encrypt_password(new_path, &zeroize::Zeroizing::new(passphrase), encryption_key)?;
+        self.key_passphrases.retain(|key| key.path != path && key.path != new_path);
+        self.key_passphrases.push(KeyPassphrase {
+            path: new_path.to_string(),
+            passphrase,
+        });
+        Ok(())
+    }
+
+    /**
+        forget the remembered passphrase of a key that is gone
+        saved together with the config by `store::update`
+    */
+    pub fn forget_key_passphrase(&mut self, path: &str) {
+        self.key_passphrases.retain(|key| key.path != path);
+    }
+
+    /**
+        forget the password of a server, a missing record is not an error
+        saved together with the config by `store::update`
+    */
+    pub fn delete_server(&mut self, id: &str) {
+        self.servers.retain(|server| server.id != id);
+    }
+}
+
+impl Vault {
+    /**
+        the vault with every secret encrypted again under another key
+    */
+    pub fn reencrypt(&self, old_key: &[u8; 32], new_key: &[u8; 32]) -> Result<Vault> {
+        let reencrypt = |id: &str, secret: &str| -> Result<String> {
+            let secret = zeroize::Zeroizing::new(decrypt_password(id, secret, old_key)?);
+            encrypt_password(id, &secret, new_key)
+        };
+        let mut vault = self.clone();
+        for server in vault.servers.iter_mut() {
+            server.password = reencrypt(&server.id, &server.password)?;
+        }
+        for key in vault.key_passphrases.iter_mut() {
+            key.passphrase = reencrypt(&key.path, &key.passphrase)?;
         }
+        for key in vault.keys.iter_mut() {
+            key.private_key = reencrypt(&key.name, &key.private_key)?;
+        }
+        Ok(vault)
+    }
+
+    /**
+        whether any secret still has to be encrypted again, see `is_legacy_secret`
+    */
+    pub fn has_legacy_secrets(&self) -> bool {
+        let servers = self.servers.iter().map(|server| &server.password);
+        let passphrases = self.key_passphrases.iter().map(|key| &key.passphrase);
+        let keys = self.keys.iter().map(|key| &key.private_key);
+        servers.chain(passphrases).chain(keys).any(|secret| is_legacy_secret(secret))
+    }
+}
+
+/**
+    replace the vault file with one under a new key, derived as `kdf` says
+    an existing file is kept next to it until the new one reads back with the new key
+*/
+pub fn save_with_new_key(vault: &Vault, new_key: &[u8; 32], kdf: &Kdf) -> Result<()> {
+    let encrypt_data = encrypt_vault(vault, new_key, kdf)?;
+    let paths = ConfigPaths::get()?;
+    let vault_file = paths.vault_file();
+    let mut kept = vault_file.clone().into_os_string();
+    kept.push(".old");
+    let kept = std::path::PathBuf::from(kept);
+
+    let _lock = storage::lock(paths.dir())?;
+    let previous = match fs::read(&vault_file) {
+        Ok(previous) => Some(previous),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e).with_context(|| format!("Unable to read {:?}", vault_file)),
+    };
+    if let Some(previous) = &previous {
+        storage::write_atomic(&kept, previous, 0o600)?;
+    }
+    let result = write_vault_file(paths, &encrypt_data).and_then(|_| {
+        let (saved, _) = decrypt_vault(&storage::read(&vault_file)?, new_key)?;
+        anyhow::ensure!(saved == *vault, "The vault read back differs from the one saved");
         Ok(())
+    });
+    let Some(previous) = previous else {
+        return result;
+    };
+    if let Err(e) = result {
+        storage::write_secret(&vault_file, &previous).with_context(|| {
+            format!("{}, and putting back the old vault failed, it is kept at {:?}", e, kept)
+        })?;
+        fs::remove_file(&kept).ok();
+        return Err(e);
     }
+    fs::remove_file(&kept).with_context(|| format!("Failed to remove {:?}", kept))
+}
+
+/**
+    replace the vault file with encrypted data,
+    the caller holds the lock of the config directory
+*/
+pub fn write_vault_file(paths: &ConfigPaths, encrypt_data: &[u8]) -> Result<()> {
+    // Replace the file unless another instance changed it meanwhile,
+    // only the owner may read and write it
+    storage::write_secret(&paths.vault_file(), encrypt_data)
+}
+
+/**
+    read and decrypt the vault file
+    returns the format it was written in as well
+*/
+pub fn read_vault(encryption_key: &[u8; 32]) -> Result<(Vault, u32)> {
+    storage::read_with(&ConfigPaths::get()?.vault_file(), |content| {
+        decrypt_vault(content, encryption_key)
+    })
 }
 
 /**
-    check if config file and its directory exists
+    check if the vault file exists
 */
 pub fn check_if_vault_bin_exists() -> Result<bool> {
-    let mut config_dir: PathBuf = if cfg!(debug_assertions) {
-        ".".into() // current running dir
-    } else {
-        dirs::home_dir().context("Unable to reach user's home directory.")?
+    Ok(ConfigPaths::get()?.vault_file().exists())
+}
+
+/// start of vault files with a header, older files start right with the IV
+const MAGIC: &[u8; 8] = b"SSHUTILS";
+/**
+    layout of the vault file, bumped when the encryption changes
+    0: no header, AES-256-CTR and HMAC-SHA256 both with the vault key
+    1: the header, then as 0 with the header covered by the HMAC
+    2: the header, then AES-256-GCM under a subkey with the header as associated data
+*/
+pub const FILE_FORMAT: u8 = 2;
+/// HKDF info of the subkey encrypting the vault file
+const VAULT_SUBKEY: &[u8] = b"ssh-utils vault file";
+/// HKDF info of the subkey encrypting the secrets inside the vault
+const SECRET_SUBKEY: &[u8] = b"ssh-utils secret";
+/// start of secrets encrypted with AES-256-GCM, older ones are bare hex
+const SECRET_PREFIX: &str = "gcm:";
+/// magic, format, variant, memory, iterations, lanes and salt
+const HEADER_LEN: usize = MAGIC.len() + 2 + 3 * 4 + SALT_LEN;
+
+/**
+    the header of a vault file, all numbers little endian:
+    magic (8) | format (1) | argon2 variant (1) | memory in KiB (4) | iterations (4) | lanes (4) | salt (16)
+*/
+fn write_header(kdf: &Kdf) -> Vec<u8> {
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    header.extend_from_slice(MAGIC);
+    header.push(FILE_FORMAT);
+    header.push(kdf.variant.as_u32() as u8);
+    header.extend_from_slice(&kdf.memory.to_le_bytes());
+    header.extend_from_slice(&kdf.iterations.to_le_bytes());
+    header.extend_from_slice(&kdf.lanes.to_le_bytes());
+    header.extend_from_slice(&kdf.salt);
+    header
+}
+
+/**
+    why a vault file can't be read
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VaultError {
+    /// shorter than its header and encryption need
+    Truncated,
+    /// a header this ssh-utils never writes
+    BadHeader(String),
+    /// the passphrase is wrong or the file was changed
+    BadMac,
+    /// decrypted, but not text
+    BadUtf8,
+    /// decrypted, but not a vault
+    BadToml(String),
+    /// written by a newer ssh-utils
+    UnknownVersion(String),
+}
+
+impl VaultError {
+    /**
+        broken whatever the passphrase, rather than locked with another one
+        or written by a newer ssh-utils
+    */
+    pub fn is_corrupt(&self) -> bool {
+        matches!(
+            self,
+            Self::Truncated | Self::BadHeader(_) | Self::BadUtf8 | Self::BadToml(_)
+        )
+    }
+}
+
+impl fmt::Display for VaultError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Truncated => write!(f, "The vault file is cut off"),
+            Self::BadHeader(reason) => write!(f, "Invalid vault header: {}", reason),
+            Self::BadMac => write!(f, "The passphrase is wrong or the vault file was changed"),
+            Self::BadUtf8 => write!(f, "The decrypted vault is not text"),
+            Self::BadToml(reason) => write!(f, "The decrypted vault can't be read: {}", reason),
+            Self::UnknownVersion(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for VaultError {}
+
+/**
+    how the key of a vault file is derived and the format of the file
+    None -> format 0, written before the header, see `derive_key_from_password`
+*/
+pub fn read_header(data: &[u8]) -> Result<Option<(Kdf, u8)>, VaultError> {
+    if !data.starts_with(MAGIC) {
+        return Ok(None);
+    }
+    if data.len() < HEADER_LEN {
+        return Err(VaultError::Truncated);
+    }
+    let format = data[MAGIC.len()];
+    if format == 0 {
+        return Err(VaultError::BadHeader("format 0 has no header".to_string()));
+    }
+    if format > FILE_FORMAT {
+        return Err(VaultError::UnknownVersion(format!(
+            "The vault file is in format {} but this ssh-utils only reads up to {}, please update ssh-utils.",
+            format, FILE_FORMAT
+        )));
+    }
+    let number = |at: usize| u32::from_le_bytes(data[at..at + 4].try_into().unwrap_or_default());
+    let at = MAGIC.len() + 2;
+    let mut salt = [0u8; SALT_LEN];
+    salt.copy_from_slice(&data[at + 12..HEADER_LEN]);
+    let variant = data[MAGIC.len() + 1];
+    let kdf = Kdf {
+        variant: argon2::Variant::from_u32(u32::from(variant))
+            .map_err(|_| VaultError::BadHeader(format!("unknown Argon2 variant {}", variant)))?,
+        memory: number(at),
+        iterations: number(at + 4),
+        lanes: number(at + 8),
+        salt,
     };
-    config_dir.push(".config/ssh-utils");
+    kdf.validate().map_err(|e| VaultError::BadHeader(e.to_string()))?;
+    Ok(Some((kdf, format)))
+}
 
-    // check if the encrypted_data file exists
-    let config_file_path = config_dir.join("encrypted_data.bin");
-    if !config_file_path.exists() {
-        return Ok(false);
+/**
+    a vault file split into its parts, checked for length but not decrypted yet
+*/
+#[derive(Debug)]
+pub struct VaultFile<'a> {
+    /// None -> format 0
+    pub kdf: Option<Kdf>,
+    pub format: u8,
+    /// authenticated together with the body
+    header: &'a [u8],
+    body: &'a [u8],
+}
+
+impl<'a> VaultFile<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, VaultError> {
+        let (kdf, format, header_len) = match read_header(data)? {
+            Some((kdf, format)) => (Some(kdf), format, HEADER_LEN),
+            None => (None, 0, 0),
+        };
+        let (header, body) = data.split_at(header_len);
+        // nonce and tag, or IV and HMAC
+        let overhead = if format == FILE_FORMAT { NONCE_LEN + TAG_LEN } else { 16 + 32 };
+        if body.len() < overhead {
+            return Err(VaultError::Truncated);
+        }
+        Ok(Self {
+            kdf,
+            format,
+            header,
+            body,
+        })
     }
 
-    Ok(true)
+    /**
+        decrypt and read the vault, see `decrypt_vault`
+    */
+    pub fn decrypt(&self, encryption_key: &[u8; 32]) -> Result<(Vault, u32)> {
+        let decrypted_data = if self.format == FILE_FORMAT {
+            let key = subkey(encryption_key, VAULT_SUBKEY)?;
+            aead_decrypt(key.as_ref(), self.header, self.body).map_err(|_| VaultError::BadMac)?
+        } else {
+            self.decrypt_legacy(encryption_key)?
+        };
+        Ok(parse_vault(&decrypted_data)?)
+    }
+
+    /**
+        format 0 and 1, AES-256-CTR with an HMAC
+    */
+    fn decrypt_legacy(&self, encryption_key: &[u8; 32]) -> Result<Vec<u8>> {
+        // Extract the IV, encrypted data, and HMAC.
+        let (iv, rest) = self.body.split_at(16);
+        let (encrypted_data, hmac) = rest.split_at(rest.len() - 32);
+
+        // Verify HMAC
+        let mut mac = HmacSha256::new_from_slice(encryption_key)
+            .context("Failed to create HMAC instance")?;
+        mac.update(self.header);
+        mac.update(iv);
+        mac.update(encrypted_data);
+        mac.verify_slice(hmac).map_err(|_| VaultError::BadMac)?;
+
+        // Decrypt the data.
+        aes_decrypt(encryption_key, iv, encrypted_data)
+    }
+}
+
+/**
+    how the key of the vault file is derived and the format it is in
+    None -> the file is in format 0, both are upgraded on unlock
+    a file that is cut off fails here already, before asking for the passphrase
+*/
+pub fn vault_header() -> Result<Option<(Kdf, u8)>> {
+    let content = storage::read(&ConfigPaths::get()?.vault_file())?;
+    let file = VaultFile::parse(&content)?;
+    Ok(file.kdf.map(|kdf| (kdf, file.format)))
+}
+
+/**
+    how the key of the vault file is derived, for saving it with the same key
+    the caller holds the lock of the config directory
+*/
+pub fn current_kdf(paths: &ConfigPaths) -> Result<Kdf> {
+    let vault_file = paths.vault_file();
+    let content =
+        fs::read(&vault_file).with_context(|| format!("Unable to read {:?}", vault_file))?;
+    match read_header(&content)? {
+        Some((kdf, _)) => Ok(kdf),
+        None => Err(anyhow::anyhow!("The vault has to be unlocked once to upgrade it before saving")),
+    }
 }
 
 /**
     encrypt vault
+    the header tells how `encryption_key` was derived from the passphrase
 */
-pub fn encrypt_vault(vault: &Vault, encryption_key: &[u8; 32]) -> Result<Vec<u8>> {
+pub fn encrypt_vault(vault: &Vault, encryption_key: &[u8; 32], kdf: &Kdf) -> Result<Vec<u8>> {
     // Serialize the Vault object to a string.
     let unencrypt_data = toml::to_string(vault).context("Unable to serialize vault to string.")?;
+    let unencrypt_data = migrate::stamp(&migrate::VAULT, &unencrypt_data);
 
-    // Step 3: Generate a 16-byte IV (initialization vector).
-    let iv = generate_iv();
+    let mut result = write_header(kdf);
 
-    // Step 4: Encrypt the serialized Vault data.
-    let data = unencrypt_data.as_bytes();
-    let encrypted_data = aes_encrypt(encryption_key, &iv, data)?;
-
-    // Step 5: Compute HMAC for the IV and encrypted data
-    let mut mac = HmacSha256::new_from_slice(encryption_key)
-        .context("Failed to create HMAC instance")?;
-    mac.update(&iv);
-    mac.update(&encrypted_data);
-    let hmac = mac.finalize().into_bytes();
-
-    // Concatenate the IV, encrypted data, and HMAC and return the result.
-    let mut result = Vec::with_capacity(iv.len() + encrypted_data.len() + hmac.len());
-    result.extend_from_slice(&iv);
+    // Encrypt the serialized Vault data, the header can't be changed either
+    let key = subkey(encryption_key, VAULT_SUBKEY)?;
+    let encrypted_data = aead_encrypt(key.as_ref(), &result, unencrypt_data.as_bytes())?;
     result.extend_from_slice(&encrypted_data);
-    result.extend_from_slice(&hmac);
 
     Ok(result)
 }
 
 /**
     decrypt vault
+    older formats are upgraded, the format the vault was written in is returned with it
+    fails with a `VaultError` if the file can't be read with this key
+*/
+pub fn decrypt_vault(vault: &[u8], encryption_key: &[u8; 32]) -> Result<(Vault, u32)> {
+    VaultFile::parse(vault)?.decrypt(encryption_key)
+}
+
+/**
+    read the decrypted TOML of a vault in any known format
 */
-pub fn decrypt_vault(vault: &[u8], encryption_key: &[u8; 32]) -> Result<Vault> {
-    // Extract the IV, encrypted data, and HMAC.
-    let (iv, rest) = vault.split_at(16);
-    let (encrypted_data, hmac) = rest.split_at(rest.len() - 32);
-
-    // Verify HMAC
-    let mut mac = HmacSha256::new_from_slice(encryption_key)
-        .context("Failed to create HMAC instance")?;
-    mac.update(iv);
-    mac.update(encrypted_data);
-    mac.verify_slice(hmac).context("HMAC verification failed")?;
-
-    // Decrypt the data.
-    let decrypted_data = aes_decrypt(encryption_key, iv, encrypted_data)?;
-
-    // Convert the decrypted data to a string and parse it into a Vault object.
-    let decrypted_str =
-        String::from_utf8(decrypted_data).context("Failed to convert decrypted data to string")?;
-    
+pub fn parse_vault(decrypted_data: &[u8]) -> Result<(Vault, u32), VaultError> {
+    let decrypted_str = std::str::from_utf8(decrypted_data).map_err(|_| VaultError::BadUtf8)?;
+
     // If decrypted_str is blank, return a default Vault.
     if decrypted_str.trim().is_empty() {
-        return Ok(Vault::default());
+        return Ok((Vault::default(), migrate::VAULT.version()));
     }
 
-    let vault: Vault =
-        toml::from_str(&decrypted_str).context("Failed to parse decrypted data as Vault")?;
+    let mut table: toml::Table =
+        toml::from_str(decrypted_str).map_err(|e| VaultError::BadToml(e.message().to_string()))?;
+    let version = migrate::upgrade(&mut table, &migrate::VAULT)
+        .map_err(|e| VaultError::UnknownVersion(format!("{:#}", e)))?;
+    let vault: Vault = table
+        .try_into()
+        .map_err(|e: toml::de::Error| VaultError::BadToml(e.message().to_string()))?;
 
-    Ok(vault)
+    Ok((vault, version))
 }
 
 fn derive_iv_from_id(id: &str) -> [u8; 16] {
@@ -185,24 +587,35 @@ fn derive_iv_from_id(id: &str) -> [u8; 16] {
 
 /**
     encrypt password to string
+    the id is authenticated with it, so it only decrypts under the same id
 */
 pub fn encrypt_password(id: &str, password: &str, encryption_key: &[u8; 32]) -> Result<String> {
-    // Derive IV from id.
-    let iv = derive_iv_from_id(id);
-
-    // Encrypt the password using the provided aes_encrypt function.
-    let encrypted_data = aes_encrypt(encryption_key, &iv, password.as_bytes())?;
+    let key = subkey(encryption_key, SECRET_SUBKEY)?;
+    let encrypted_data = aead_encrypt(key.as_ref(), id.as_bytes(), password.as_bytes())?;
 
     // Encode the result as a hex string.
-    let encrypted_hex = hex::encode(encrypted_data);
+    Ok(format!("{}{}", SECRET_PREFIX, hex::encode(encrypted_data)))
+}
 
-    Ok(encrypted_hex)
+/**
+    whether a secret was encrypted by an older version with a fixed IV
+*/
+pub fn is_legacy_secret(encrypted_password: &str) -> bool {
+    !encrypted_password.starts_with(SECRET_PREFIX)
 }
 
 /**
     decrypt password to string
 */
 pub fn decrypt_password(id: &str, encrypted_password: &str, encryption_key: &[u8; 32]) -> Result<String> {
+    if let Some(encrypted_hex) = encrypted_password.strip_prefix(SECRET_PREFIX) {
+        let encrypted_data = hex::decode(encrypted_hex).context("Failed to decode hex string")?;
+        let key = subkey(encryption_key, SECRET_SUBKEY)?;
+        let decrypted_data = aead_decrypt(key.as_ref(), id.as_bytes(), &encrypted_data)?;
+        return String::from_utf8(decrypted_data)
+            .context("Failed to convert decrypted data to string");
+    }
+
     // Derive IV from id.
     let iv = derive_iv_from_id(id);
 
@@ -236,6 +649,11 @@ fn test_encryption_decryption_password() -> Result<()> {
     println!("Decrypted password: {}", decrypted_password);
 
     assert_eq!(password,decrypted_password.as_str());
+
+    // a random nonce each time, and bound to the id
+    assert_ne!(encrypt_password(id, password, &encryption_key)?, encrypted_password);
+    assert!(decrypt_password("another id", &encrypted_password, &encryption_key).is_err());
+    assert!(!is_legacy_secret(&encrypted_password));
     Ok(())
 }
 
@@ -252,13 +670,22 @@ password = "secret_password1"
 [[servers]]
 id = "server2"
 password = "secret_password2"
+
+[[key_passphrases]]
+path = "~/.ssh/id_ed25519"
+passphrase = "encrypted_passphrase"
+
+[[keys]]
+name = "deploy"
+private_key = "encrypted_private_key"
     "#;
     let origin_vault: Vault = toml::from_str(pass_data)?;
-    let encryption_key = derive_key_from_password("123")?;
-    let encrypt_data = encrypt_vault(&origin_vault, &encryption_key)?;
-    let decrypt_vault = match decrypt_vault(&encrypt_data, &encryption_key) {
+    let kdf = Kdf::new(argon2::Variant::Argon2id, 1024, 1, 1);
+    let encryption_key = kdf.derive("123")?;
+    let encrypt_data = encrypt_vault(&origin_vault, &encryption_key, &kdf)?;
+    let (decrypt_vault, version) = match decrypt_vault(&encrypt_data, &encryption_key) {
         Err(e) => {
-            if let Some(_) = e.downcast_ref::<hmac::digest::MacError>() {
+            if e.downcast_ref::<VaultError>() == Some(&VaultError::BadMac) {
                 println!("wrong password");
                 return Err(e);
             } else {
@@ -269,5 +696,296 @@ password = "secret_password2"
         Ok(o) => o
     };
     assert_eq!(origin_vault, decrypt_vault);
+    assert_eq!(version, migrate::VAULT.version());
+    Ok(())
+}
+
+/**
+    vaults written before key passphrases were remembered still load
+*/
+#[test]
+fn test_vault_without_key_passphrases() -> Result<()> {
+    let vault: Vault = toml::from_str("[[servers]]\nid = \"server1\"\npassword = \"secret\"\n")?;
+    assert!(vault.key_passphrases.is_empty());
+    assert!(vault.keys.is_empty());
+    let encryption_key = derive_key_from_password("123")?;
+    assert_eq!(vault.key_passphrase("~/.ssh/id_rsa", &encryption_key)?, None);
+    assert!(!toml::to_string(&vault)?.contains("key_passphrases"));
+    assert!(vault.private_key("deploy", &encryption_key).is_err());
     Ok(())
 }
+
+/**
+    renaming and deleting keys only changes the vault in memory
+*/
+#[test]
+fn test_rename_and_delete_key() -> Result<()> {
+    let encryption_key = [3u8; 32];
+    let mut vault = Vault::default();
+    vault.keys.push(StoredKey {
+        name: "deploy".to_string(),
+        private_key: encrypt_password("deploy", "private key", &encryption_key)?,
+    });
+    vault.key_passphrases.push(KeyPassphrase {
+        path: "vault:deploy".to_string(),
+        passphrase: encrypt_password("vault:deploy", "secret", &encryption_key)?,
+    });
+
+    vault.rename_key("deploy", "ci", &encryption_key)?;
+    vault.rename_key_passphrase("vault:deploy", "vault:ci", &encryption_key)?;
+    assert_eq!(vault.private_key("ci", &encryption_key)?, "private key");
+    assert!(vault.private_key("deploy", &encryption_key).is_err());
+    assert_eq!(vault.key_passphrase("vault:ci", &encryption_key)?.as_deref(), Some("secret"));
+    assert_eq!(vault.key_passphrase("vault:deploy", &encryption_key)?, None);
+    // nothing to move
+    vault.rename_key_passphrase("~/.ssh/id_rsa", "~/.ssh/old", &encryption_key)?;
+    assert_eq!(vault.key_passphrases.len(), 1);
+
+    vault.delete_key("ci")?;
+    vault.forget_key_passphrase("vault:ci");
+    assert!(vault.keys.is_empty());
+    assert!(vault.key_passphrases.is_empty());
+    assert!(vault.delete_key("ci").is_err());
+    Ok(())
+}
+
+/**
+    every vault format ever written reads, see tests/fixtures/vault
+*/
+#[test]
+fn test_vault_fixtures() -> Result<()> {
+    let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/vault");
+    let mut fixtures: Vec<std::path::PathBuf> = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_, _>>()?;
+    fixtures.sort();
+    assert!(fixtures.len() >= 4);
+    for fixture in fixtures {
+        let original = fs::read_to_string(&fixture)?;
+        let (vault, version) = parse_vault(original.as_bytes())?;
+        assert_eq!(version, if original.starts_with("version") { 1 } else { 0 }, "{:?}", fixture);
+        assert_eq!(vault.servers[0].password, "9c1b7e2f");
+        // saved in the current format and read back
+        let kdf = Kdf::new(argon2::Variant::Argon2id, 1024, 1, 1);
+        let encryption_key = kdf.derive("123")?;
+        let encrypted = encrypt_vault(&vault, &encryption_key, &kdf)?;
+        let (reread, version) = decrypt_vault(&encrypted, &encryption_key)?;
+        assert_eq!(version, migrate::VAULT.version());
+        assert_eq!(reread, vault);
+    }
+    let (vault, _) = parse_vault(include_bytes!("../../tests/fixtures/vault/v0-keys.toml"))?;
+    assert_eq!(vault.key_passphrases[0].path, "~/.ssh/id_ed25519");
+    assert_eq!(vault.keys[0].name, "deploy");
+    assert!(parse_vault(b"version = 2\nservers = []\n").is_err());
+    Ok(())
+}
+
+#[test]
+fn test_reencrypt() -> Result<()> {
+    let old_key = derive_key_from_password("old")?;
+    let new_key = derive_key_from_password("new")?;
+    let vault = Vault {
+        servers: vec![Server::new("server1".to_string(), encrypt_password("server1", "secret", &old_key)?)],
+        key_passphrases: vec![KeyPassphrase {
+            path: "~/.ssh/id_rsa".to_string(),
+            passphrase: encrypt_password("~/.ssh/id_rsa", "phrase", &old_key)?,
+        }],
+        keys: vec![StoredKey {
+            name: "deploy".to_string(),
+            private_key: encrypt_password("deploy", "PRIVATE KEY", &old_key)?,
+        }],
+    };
+    let reencrypted = vault.reencrypt(&old_key, &new_key)?;
+    assert_ne!(reencrypted, vault);
+    assert_eq!(decrypt_password("server1", &reencrypted.servers[0].password, &new_key)?, "secret");
+    assert_eq!(reencrypted.key_passphrase("~/.ssh/id_rsa", &new_key)?.as_deref(), Some("phrase"));
+    assert_eq!(reencrypted.private_key("deploy", &new_key)?, "PRIVATE KEY");
+    // and back, under new nonces
+    let back = reencrypted.reencrypt(&new_key, &old_key)?;
+    assert_eq!(decrypt_password("server1", &back.servers[0].password, &old_key)?, "secret");
+    assert_eq!(back.private_key("deploy", &old_key)?, "PRIVATE KEY");
+    assert!(decrypt_password("server1", &back.servers[0].password, &new_key).is_err());
+    Ok(())
+}
+
+/**
+    vault files with and without the header, see tests/fixtures/vault-file
+*/
+#[test]
+fn test_vault_file_formats() -> Result<()> {
+    // format 0, salted with the passphrase
+    let legacy = include_bytes!("../../tests/fixtures/vault-file/format0.bin");
+    assert!(read_header(legacy)?.is_none());
+    let legacy_key = derive_key_from_password("123")?;
+    let (vault, _) = decrypt_vault(legacy, &legacy_key)?;
+    let server = &vault.servers[0];
+    assert_eq!(server.id, "b6c953aa-becb-4ff7-aaf7-800540962785");
+    assert_eq!(decrypt_password(&server.id, &server.password, &legacy_key)?, "9c1b7e2f");
+    assert!(vault.has_legacy_secrets());
+
+    // format 1 keeps the key derivation in the header
+    let format1 = include_bytes!("../../tests/fixtures/vault-file/format1.bin");
+    let (kdf, format) = read_header(format1)?.unwrap();
+    assert_eq!(format, 1);
+    let encryption_key = kdf.derive("123")?;
+    let (vault, _) = decrypt_vault(format1, &encryption_key)?;
+    let server = &vault.servers[0];
+    assert_eq!(decrypt_password(&server.id, &server.password, &encryption_key)?, "9c1b7e2f");
+
+    // format 2 encrypts file and secrets with AES-256-GCM
+    let vault = vault.reencrypt(&encryption_key, &encryption_key)?;
+    assert!(!vault.has_legacy_secrets());
+    let server = &vault.servers[0];
+    assert_eq!(decrypt_password(&server.id, &server.password, &encryption_key)?, "9c1b7e2f");
+    let encrypted = encrypt_vault(&vault, &encryption_key, &kdf)?;
+    assert_eq!(read_header(&encrypted)?, Some((kdf.clone(), FILE_FORMAT)));
+    assert_eq!(decrypt_vault(&encrypted, &encryption_key)?.0, vault);
+    let error = decrypt_vault(&encrypted, &legacy_key).unwrap_err();
+    assert_eq!(error.downcast_ref::<VaultError>(), Some(&VaultError::BadMac));
+
+    // the header is authenticated
+    let mut tampered = encrypted.clone();
+    tampered[MAGIC.len() + 6] ^= 1;
+    assert!(decrypt_vault(&tampered, &encryption_key).is_err());
+    let mut newer = encrypted.clone();
+    newer[MAGIC.len()] = FILE_FORMAT + 1;
+    assert!(read_header(&newer).unwrap_err().to_string().contains("please update"));
+    assert!(read_header(&encrypted[..HEADER_LEN - 1]).is_err());
+    assert!(decrypt_vault(&encrypted[..HEADER_LEN + 20], &encryption_key).is_err());
+    Ok(())
+}
+
+/**
+    every way a vault file can't be read has its own error
+*/
+#[test]
+fn test_vault_errors() -> Result<()> {
+    let kdf = Kdf::new(argon2::Variant::Argon2id, 1024, 1, 1);
+    let encryption_key = [7u8; 32];
+    let vault_error = |data: &[u8]| -> VaultError {
+        let error = decrypt_vault(data, &encryption_key).unwrap_err();
+        error.downcast_ref::<VaultError>().cloned().expect("a VaultError")
+    };
+    // a header and whatever AES-256-GCM gives for these bytes
+    let seal = |plain: &[u8]| -> Result<Vec<u8>> {
+        let mut data = write_header(&kdf);
+        let key = subkey(&encryption_key, VAULT_SUBKEY)?;
+        let sealed = aead_encrypt(key.as_ref(), &data, plain)?;
+        data.extend_from_slice(&sealed);
+        Ok(data)
+    };
+
+    assert_eq!(vault_error(b""), VaultError::Truncated);
+    assert_eq!(vault_error(&MAGIC[..4]), VaultError::Truncated);
+    let encrypted = encrypt_vault(&Vault::default(), &encryption_key, &kdf)?;
+    assert_eq!(vault_error(&encrypted[..HEADER_LEN - 1]), VaultError::Truncated);
+    assert_eq!(vault_error(&encrypted[..HEADER_LEN + NONCE_LEN]), VaultError::Truncated);
+    assert_eq!(vault_error(&encrypted[..encrypted.len() - 1]), VaultError::BadMac);
+
+    let mut header = encrypted.clone();
+    header[MAGIC.len() + 1] = 9;
+    assert!(matches!(vault_error(&header), VaultError::BadHeader(_)));
+    header[MAGIC.len()] = 0;
+    assert!(matches!(vault_error(&header), VaultError::BadHeader(_)));
+    let mut newer = encrypted.clone();
+    newer[MAGIC.len()] = FILE_FORMAT + 1;
+    assert!(matches!(vault_error(&newer), VaultError::UnknownVersion(_)));
+
+    assert_eq!(vault_error(&seal(b"servers = \xff")?), VaultError::BadUtf8);
+    assert!(matches!(vault_error(&seal(b"servers = [")?), VaultError::BadToml(_)));
+    assert!(matches!(vault_error(&seal(b"servers = 1")?), VaultError::BadToml(_)));
+    assert!(matches!(vault_error(&seal(b"version = 9")?), VaultError::UnknownVersion(_)));
+    assert_eq!(decrypt_vault(&seal(b"")?, &encryption_key)?.0, Vault::default());
+
+    assert!(VaultError::Truncated.is_corrupt());
+    assert!(!VaultError::BadMac.is_corrupt());
+    assert!(!VaultError::UnknownVersion(String::new()).is_corrupt());
+    Ok(())
+}
+
+#[cfg(test)]
+mod codec_properties {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn kdf() -> Kdf {
+        Kdf {
+            salt: [1u8; SALT_LEN],
+            ..Kdf::new(argon2::Variant::Argon2id, 1024, 1, 1)
+        }
+    }
+
+    fn vault() -> impl Strategy<Value = Vault> {
+        let servers = prop::collection::vec(
+            ("\\PC{0,40}", "\\PC{0,80}").prop_map(|(id, password)| Server::new(id, password)),
+            0..5,
+        );
+        let key_passphrases = prop::collection::vec(
+            ("\\PC{0,40}", "\\PC{0,80}").prop_map(|(path, passphrase)| KeyPassphrase { path, passphrase }),
+            0..3,
+        );
+        let keys = prop::collection::vec(
+            ("\\PC{0,40}", "\\PC{0,80}").prop_map(|(name, private_key)| StoredKey { name, private_key }),
+            0..3,
+        );
+        (servers, key_passphrases, keys).prop_map(|(servers, key_passphrases, keys)| Vault {
+            servers,
+            key_passphrases,
+            keys,
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn vault_round_trips(vault in vault(), key in any::<[u8; 32]>()) {
+            let encrypted = encrypt_vault(&vault, &key, &kdf()).unwrap();
+            let (decrypted, version) = decrypt_vault(&encrypted, &key).unwrap();
+            prop_assert_eq!(decrypted, vault);
+            prop_assert_eq!(version, migrate::VAULT.version());
+        }
+
+        #[test]
+        fn cut_or_changed_vault_is_refused(
+            vault in vault(),
+            cut in any::<prop::sample::Index>(),
+            at in any::<prop::sample::Index>(),
+            bit in 0..8u32,
+        ) {
+            let key = [3u8; 32];
+            let encrypted = encrypt_vault(&vault, &key, &kdf()).unwrap();
+            prop_assert!(decrypt_vault(&encrypted[..cut.index(encrypted.len())], &key).is_err());
+            let mut changed = encrypted.clone();
+            changed[at.index(encrypted.len())] ^= 1 << bit;
+            prop_assert!(decrypt_vault(&changed, &key).is_err());
+        }
+
+        #[test]
+        fn any_bytes_are_refused_without_panic(
+            data in prop::collection::vec(any::<u8>(), 0..512),
+            key in any::<[u8; 32]>(),
+        ) {
+            let _ = decrypt_vault(&data, &key);
+            let _ = parse_vault(&data);
+            // past the magic of a current file too
+            let mut with_magic = MAGIC.to_vec();
+            with_magic.push(FILE_FORMAT);
+            with_magic.extend_from_slice(&data);
+            let _ = decrypt_vault(&with_magic, &key);
+        }
+
+        #[test]
+        fn password_round_trips(id in "\\PC*", password in "\\PC*", key in any::<[u8; 32]>()) {
+            let encrypted = encrypt_password(&id, &password, &key).unwrap();
+            prop_assert!(!is_legacy_secret(&encrypted));
+            prop_assert_eq!(decrypt_password(&id, &encrypted, &key).unwrap(), password);
+        }
+
+        #[test]
+        fn any_secret_is_refused_without_panic(id in "\\PC*", secret in "\\PC*") {
+            let key = [5u8; 32];
+            let _ = decrypt_password(&id, &secret, &key);
+            let _ = decrypt_password(&id, &format!("{}{}", SECRET_PREFIX, secret), &key);
+            let _ = decrypt_password(&id, &format!("{}{}", SECRET_PREFIX, hex::encode(&secret)), &key);
+        }
+    }
+}
@@ -0,0 +1,396 @@
+use anyhow::{Context, Result};
+use std::{
+    fs::{self, OpenOptions},
+    io,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::config::paths::ConfigPaths;
+use crate::config::storage;
+use crate::helper::format_compact_timestamp;
+
+/// directory in the config directory holding one directory per backup
+const BACKUP_DIR: &str = "backups";
+/// key of config.toml with the number of backups to keep
+const COUNT_KEY: &str = "backup_count";
+/// backups kept when config.toml doesn't say
+pub const DEFAULT_COUNT: usize = 10;
+
+/**
+    config.toml and encrypted_data.bin as they were before a save
+    named after the UTC time it was taken, like 20261018-111456.123
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Backup {
+    pub name: String,
+    dir: PathBuf,
+}
+
+impl Backup {
+    /// the files of the config directory, paired with their copy in the backup
+    fn files(&self, paths: &ConfigPaths) -> [(PathBuf, PathBuf); 2] {
+        [paths.config_file(), paths.vault_file()].map(|file| {
+            let copy = self.dir.join(file.file_name().unwrap_or_default());
+            (file, copy)
+        })
+    }
+
+    pub fn config_file(&self, paths: &ConfigPaths) -> PathBuf {
+        self.files(paths)[0].1.clone()
+    }
+
+    pub fn vault_file(&self, paths: &ConfigPaths) -> PathBuf {
+        self.files(paths)[1].1.clone()
+    }
+
+    /**
+        number of servers in the backed up config.toml, if it can be read
+    */
+    pub fn server_count(&self, paths: &ConfigPaths) -> Option<usize> {
+        let content = fs::read_to_string(self.config_file(paths)).ok()?;
+        let table: toml::Table = toml::from_str(&content).ok()?;
+        match table.get("servers") {
+            Some(toml::Value::Array(servers)) => Some(servers.len()),
+            _ => Some(0),
+        }
+    }
+}
+
+fn backup_dir(paths: &ConfigPaths) -> PathBuf {
+    paths.dir().join(BACKUP_DIR)
+}
+
+fn read_if_exists(path: &Path) -> Result<Option<Vec<u8>>> {
+    match fs::read(path) {
+        Ok(content) => Ok(Some(content)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("Unable to read {:?}", path)),
+    }
+}
+
+/**
+    how many backups to keep, `backup_count` in config.toml, 0 turns them off
+*/
+fn configured_count(paths: &ConfigPaths) -> usize {
+    fs::read_to_string(paths.config_file())
+        .ok()
+        .and_then(|content| toml::from_str::<toml::Table>(&content).ok())
+        .and_then(|table| table.get(COUNT_KEY)?.as_integer())
+        .map_or(DEFAULT_COUNT, |count| count.max(0) as usize)
+}
+
+/**
+    the backups, oldest first
+*/
+pub fn list(paths: &ConfigPaths) -> Result<Vec<Backup>> {
+    let dir = backup_dir(paths);
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Unable to read {:?}", dir)),
+    };
+    let mut backups = Vec::new();
+    for entry in entries {
+        let entry = entry.with_context(|| format!("Unable to read {:?}", dir))?;
+        if entry.file_type()?.is_dir() {
+            backups.push(Backup {
+                name: entry.file_name().to_string_lossy().into_owned(),
+                dir: entry.path(),
+            });
+        }
+    }
+    // names are fixed width timestamps
+    backups.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(backups)
+}
+
+/**
+    the backup with this name, or the only one starting with it
+*/
+pub fn find(paths: &ConfigPaths, name: &str) -> Result<Backup> {
+    let backups = list(paths)?;
+    if let Some(backup) = backups.iter().find(|backup| backup.name == name) {
+        return Ok(backup.clone());
+    }
+    let mut matches = backups.into_iter().filter(|backup| backup.name.starts_with(name));
+    match (matches.next(), matches.next()) {
+        (Some(backup), None) => Ok(backup),
+        (Some(_), Some(_)) => Err(anyhow::anyhow!(
+            "More than one backup starts with {}, use the full name",
+            name
+        )),
+        (None, _) => Err(anyhow::anyhow!("No backup named {}, see \"ssh-utils backup list\"", name)),
+    }
+}
+
+/**
+    copy config and vault into a new backup before they are replaced,
+    then drop the oldest backups beyond the configured count
+    nothing is copied if they didn't change since the latest backup
+    the caller holds the lock of the config directory
+*/
+pub fn snapshot(paths: &ConfigPaths) -> Result<Option<Backup>> {
+    let count = configured_count(paths);
+    if count == 0 {
+        return Ok(None);
+    }
+    let mut current = Vec::new();
+    for file in [paths.config_file(), paths.vault_file()] {
+        current.push(read_if_exists(&file)?);
+    }
+    if current.iter().all(Option::is_none) {
+        return Ok(None);
+    }
+    let mut backups = list(paths)?;
+    if let Some(latest) = backups.last() {
+        let mut same = true;
+        for ((_, copy), content) in latest.files(paths).iter().zip(&current) {
+            same &= read_if_exists(copy)? == *content;
+        }
+        if same {
+            return Ok(None);
+        }
+    }
+
+    let backup = create(paths)?;
+    for ((_, copy), content) in backup.files(paths).iter().zip(&current) {
+        if let Some(content) = content {
+            storage::write_atomic(copy, content, 0o600)?;
+        }
+    }
+    backups.push(backup.clone());
+    let excess = backups.len().saturating_sub(count);
+    for old in &backups[..excess] {
+        remove(old)?;
+    }
+    Ok(Some(backup))
+}
+
+/**
+    an empty backup directory named after the current time
+*/
+fn create(paths: &ConfigPaths) -> Result<Backup> {
+    let dir = backup_dir(paths);
+    fs::create_dir_all(&dir).with_context(|| format!("Failed to create {:?}", dir))?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+    let mut millis = now.subsec_millis();
+    loop {
+        let name = format!("{}.{:03}", format_compact_timestamp(now.as_secs()), millis);
+        let path = dir.join(&name);
+        match fs::create_dir(&path) {
+            Ok(()) => return Ok(Backup { name, dir: path }),
+            // saved twice within a millisecond
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && millis < 999 => millis += 1,
+            Err(e) => return Err(e).with_context(|| format!("Failed to create {:?}", path)),
+        }
+    }
+}
+
+pub fn remove(backup: &Backup) -> Result<()> {
+    fs::remove_dir_all(&backup.dir)
+        .with_context(|| format!("Failed to remove backup {:?}", backup.dir))
+}
+
+/**
+    every copy of the vault besides the vault itself: the backups and the
+    `.bak` files left by format upgrades, they open with the passphrase they were taken under
+*/
+pub fn vault_copies(paths: &ConfigPaths) -> Result<Vec<PathBuf>> {
+    let mut copies: Vec<PathBuf> = list(paths)?.into_iter().map(|backup| backup.dir).collect();
+    let vault_file = paths.vault_file();
+    let prefix = format!("{}.", vault_file.file_name().unwrap_or_default().to_string_lossy());
+    let dir = paths.dir();
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(copies),
+        Err(e) => return Err(e).with_context(|| format!("Unable to read {:?}", dir)),
+    };
+    for entry in entries {
+        let entry = entry.with_context(|| format!("Unable to read {:?}", dir))?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with(&prefix) && name.ends_with(".bak") {
+            copies.push(entry.path());
+        }
+    }
+    copies.sort();
+    Ok(copies)
+}
+
+/**
+    delete the copies `vault_copies` finds, after the passphrase changed
+    the caller holds the lock of the config directory
+*/
+pub fn remove_vault_copies(paths: &ConfigPaths) -> Result<usize> {
+    let copies = vault_copies(paths)?;
+    for copy in &copies {
+        let result = if copy.is_dir() {
+            fs::remove_dir_all(copy)
+        } else {
+            fs::remove_file(copy)
+        };
+        result.with_context(|| format!("Failed to remove {:?}", copy))?;
+    }
+    Ok(copies.len())
+}
+
+/**
+    put the files of a backup back in place
+    keep_current -> back up the files being replaced first, returns that backup
+    fails with `ChangedOnDisk` if this process read a file that was changed since
+    the caller holds the lock of the config directory
+*/
+pub fn restore(paths: &ConfigPaths, backup: &Backup, keep_current: bool) -> Result<Option<Backup>> {
+    // read first, taking a backup may rotate this one away
+    let mut contents = Vec::new();
+    for (file, copy) in backup.files(paths) {
+        if let Some(content) = read_if_exists(&copy)? {
+            contents.push((file, content));
+        }
+    }
+    let kept = if keep_current { snapshot(paths)? } else { None };
+    for (file, content) in contents {
+        if file == paths.vault_file() {
+            storage::write_secret(&file, &content)?;
+        } else {
+            storage::write(&file, &content, 0o644)?;
+        }
+    }
+    Ok(kept)
+}
+
+/**
+    pack the whole config directory, backups included, into a tar file next to it
+*/
+pub fn archive(paths: &ConfigPaths) -> Result<PathBuf> {
+    let dir = paths.dir();
+    let name = dir
+        .file_name()
+        .with_context(|| format!("Invalid config directory {:?}", dir))?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+    let mut file_name = name.to_owned();
+    file_name.push(format!("-{}.tar", format_compact_timestamp(now.as_secs())));
+    let path = dir.with_file_name(file_name);
+
+    // the vault is in there, only the owner may read it
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&path)
+        .with_context(|| format!("Failed to create {:?}", path))?;
+    let mut builder = tar::Builder::new(file);
+    builder
+        .append_dir_all(name, dir)
+        .and_then(|_| builder.into_inner())
+        .and_then(|file| file.sync_all())
+        .with_context(|| format!("Failed to write {:?}", path))?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn names(paths: &ConfigPaths) -> Vec<String> {
+        list(paths).unwrap().into_iter().map(|backup| backup.name).collect()
+    }
+
+    #[test]
+    fn test_snapshot_and_restore() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let paths = ConfigPaths::new(temp_dir.path().join("ssh-utils"));
+        fs::create_dir_all(paths.dir())?;
+        assert_eq!(snapshot(&paths)?, None);
+
+        fs::write(paths.config_file(), "version = 1\nbackup_count = 2\nservers = []\n")?;
+        fs::write(paths.vault_file(), "first")?;
+        let first = snapshot(&paths)?.unwrap();
+        assert_eq!(first.server_count(&paths), Some(0));
+        assert_eq!(fs::read(first.vault_file(&paths))?, b"first");
+        // unchanged files are not copied again
+        assert_eq!(snapshot(&paths)?, None);
+
+        fs::write(paths.vault_file(), "second")?;
+        let second = snapshot(&paths)?.unwrap();
+        fs::write(paths.vault_file(), "third")?;
+        let third = snapshot(&paths)?.unwrap();
+        // only backup_count are kept
+        assert_eq!(names(&paths), vec![second.name.clone(), third.name.clone()]);
+        assert!(second.name < third.name);
+
+        // the oldest one can be restored though keeping the current files rotates it away
+        fs::write(paths.vault_file(), "fourth")?;
+        let kept = restore(&paths, &find(&paths, &second.name)?, true)?.unwrap();
+        assert_eq!(fs::read(paths.vault_file())?, b"second");
+        assert_eq!(names(&paths), vec![third.name.clone(), kept.name.clone()]);
+        restore(&paths, &kept, false)?;
+        assert_eq!(fs::read(paths.vault_file())?, b"fourth");
+        assert_eq!(names(&paths).len(), 2);
+        // both start with the year
+        assert!(find(&paths, &third.name[..4]).is_err());
+        assert!(find(&paths, "1999").is_err());
+
+        fs::write(paths.config_file(), "backup_count = 0\n")?;
+        assert_eq!(snapshot(&paths)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_remove_vault_copies() -> Result<()> {
+        use crate::config::app_vault::{decrypt_vault, encrypt_vault, Vault};
+        use crate::config::crypto::Kdf;
+        use crate::config::migrate;
+
+        let temp_dir = TempDir::new()?;
+        let paths = ConfigPaths::new(temp_dir.path().join("ssh-utils"));
+        fs::create_dir_all(paths.dir())?;
+        let kdf = Kdf::default();
+        let old_key = kdf.derive("old")?;
+        let new_key = kdf.derive("new")?;
+        fs::write(paths.config_file(), "version = 1\nservers = []\n")?;
+        fs::write(paths.vault_file(), encrypt_vault(&Vault::default(), &old_key, &kdf)?)?;
+        snapshot(&paths)?.unwrap();
+        migrate::backup_as(&paths.vault_file(), "format0")?;
+        migrate::backup(&paths.vault_file(), 0)?;
+        migrate::backup(&paths.config_file(), 0)?;
+        fs::write(paths.vault_file(), encrypt_vault(&Vault::default(), &new_key, &kdf)?)?;
+        snapshot(&paths)?.unwrap();
+        assert_eq!(vault_copies(&paths)?.len(), 4);
+
+        assert_eq!(remove_vault_copies(&paths)?, 4);
+        assert!(list(&paths)?.is_empty());
+        // nothing left opens with the old passphrase, the config backup stays
+        let mut left = Vec::new();
+        for entry in fs::read_dir(paths.dir())? {
+            let path = entry?.path();
+            if path.is_file() {
+                assert!(decrypt_vault(&fs::read(&path)?, &old_key).is_err(), "{:?}", path);
+                left.push(path);
+            }
+        }
+        assert!(left.contains(&paths.config_file().with_extension("toml.v0.bak")));
+        assert!(decrypt_vault(&fs::read(paths.vault_file())?, &new_key).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_archive() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let paths = ConfigPaths::new(temp_dir.path().join("ssh-utils"));
+        fs::create_dir_all(paths.dir())?;
+        fs::write(paths.config_file(), "servers = []\n")?;
+        let path = archive(&paths)?;
+        assert!(path.file_name().unwrap().to_string_lossy().starts_with("ssh-utils-"));
+
+        let mut archive = tar::Archive::new(fs::File::open(&path)?);
+        let entries: Vec<PathBuf> = archive
+            .entries()?
+            .map(|entry| entry.unwrap().path().unwrap().into_owned())
+            .collect();
+        assert!(entries.contains(&PathBuf::from("ssh-utils/config.toml")));
+        Ok(())
+    }
+}
\ No newline at end of file
@@ -1,8 +1,11 @@
 use anyhow::{Context, Result};
-use argon2::Config;
-use openssl::symm::{Cipher, Crypter, Mode};
+use argon2::{Config, Variant};
+use hkdf::Hkdf;
+use hmac::digest::MacError;
+use openssl::symm::{decrypt_aead, encrypt_aead, Cipher, Crypter, Mode};
 use rand::{thread_rng, Rng};
 use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
 
 /**
     derive 16 bytes digest from password
@@ -16,8 +19,113 @@ pub fn derive_sha256_digest(password: &str) -> [u8; 16] {
     salt
 }
 
+/**
+    how the vault key is derived from the passphrase, kept in the vault header
+    memory is in KiB, as argon2 counts it
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Kdf {
+    pub variant: Variant,
+    pub memory: u32,
+    pub iterations: u32,
+    pub lanes: u32,
+    pub salt: [u8; SALT_LEN],
+}
+
+pub const SALT_LEN: usize = 16;
+/// the most memory a vault header may ask for, 4 GiB
+pub const MAX_MEMORY: u32 = 4 * 1024 * 1024;
+/// the most iterations and lanes a vault header may ask for
+pub const MAX_ITERATIONS: u32 = 1024;
+pub const MAX_LANES: u32 = 64;
+
+impl Default for Kdf {
+    /// Argon2id with 19 MiB and 2 iterations, as OWASP recommends, and a random salt
+    fn default() -> Self {
+        let config = Config::owasp2();
+        Self::new(config.variant, config.mem_cost, config.time_cost, config.lanes)
+    }
+}
+
+impl Kdf {
+    /**
+        the given parameters with a new random salt
+    */
+    pub fn new(variant: Variant, memory: u32, iterations: u32, lanes: u32) -> Self {
+        let mut salt = [0u8; SALT_LEN];
+        thread_rng().fill(&mut salt);
+        Self {
+            variant,
+            memory,
+            iterations,
+            lanes,
+            salt,
+        }
+    }
+
+    /**
+        the same parameters with a new random salt
+    */
+    pub fn renew(&self) -> Self {
+        Self::new(self.variant, self.memory, self.iterations, self.lanes)
+    }
+
+    /**
+        refuse parameters argon2 can't use or that would take unreasonably long
+    */
+    pub fn validate(&self) -> Result<()> {
+        if self.lanes == 0 || self.lanes > MAX_LANES {
+            anyhow::bail!("Argon2 lanes must be between 1 and {}", MAX_LANES);
+        }
+        if self.iterations == 0 || self.iterations > MAX_ITERATIONS {
+            anyhow::bail!("Argon2 iterations must be between 1 and {}", MAX_ITERATIONS);
+        }
+        if self.memory < 8 * self.lanes || self.memory > MAX_MEMORY {
+            anyhow::bail!(
+                "Argon2 memory must be between {} KiB and {} KiB",
+                8 * self.lanes,
+                MAX_MEMORY
+            );
+        }
+        Ok(())
+    }
+
+    /**
+        derive the 32 bytes vault key from a passphrase
+    */
+    pub fn derive(&self, password: &str) -> Result<[u8; 32]> {
+        self.validate()?;
+        let config = Config {
+            variant: self.variant,
+            mem_cost: self.memory,
+            time_cost: self.iterations,
+            lanes: self.lanes,
+            ..Config::owasp3()
+        };
+        let key = argon2::hash_raw(password.as_bytes(), &self.salt, &config)
+            .context("Failed to derive key using Argon2")?;
+        let mut key_arr = [0u8; 32];
+        key_arr.copy_from_slice(&key);
+        Ok(key_arr)
+    }
+}
+
+impl std::fmt::Display for Kdf {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}, {} KiB, {} iterations, {} lanes",
+            self.variant.as_lowercase_str(),
+            self.memory,
+            self.iterations,
+            self.lanes
+        )
+    }
+}
+
 /**
     derive 32 bytes hash key from password by argon2
+    the key of vaults written before the header, salted with the password itself
 */
 pub fn derive_key_from_password(password: &str) -> Result<[u8; 32]> {
     // Step 1: Derive a 16-byte SHA-256 digest from the password.
@@ -32,15 +140,55 @@ pub fn derive_key_from_password(password: &str) -> Result<[u8; 32]> {
 }
 
 /**
-    generate random iv
+    derive a key for one purpose from the vault key with HKDF-SHA256
+    so the vault file and the secrets in it never share a key
+*/
+pub fn subkey(encryption_key: &[u8; 32], purpose: &[u8]) -> Result<Zeroizing<[u8; 32]>> {
+    let mut key = Zeroizing::new([0u8; 32]);
+    Hkdf::<Sha256>::new(None, encryption_key)
+        .expand(purpose, key.as_mut())
+        .map_err(|_| anyhow::anyhow!("Failed to derive a subkey"))?;
+    Ok(key)
+}
+
+pub const NONCE_LEN: usize = 12;
+pub const TAG_LEN: usize = 16;
+
+/**
+    encrypt with AES-256-GCM under a random nonce
+    returns nonce | ciphertext | tag, `aad` is authenticated but not included
 */
-pub fn generate_iv() -> [u8; 16] {
-    let mut iv = [0u8; 16];
-    let mut rng = thread_rng();
-    rng.fill(&mut iv);
-    iv
+pub fn aead_encrypt(key: &[u8], aad: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    let mut nonce = [0u8; NONCE_LEN];
+    thread_rng().fill(&mut nonce);
+    let mut tag = [0u8; TAG_LEN];
+    let ciphertext = encrypt_aead(Cipher::aes_256_gcm(), key, Some(&nonce), aad, data, &mut tag)
+        .context("Failed to encrypt data")?;
+    let mut sealed = Vec::with_capacity(NONCE_LEN + ciphertext.len() + TAG_LEN);
+    sealed.extend_from_slice(&nonce);
+    sealed.extend_from_slice(&ciphertext);
+    sealed.extend_from_slice(&tag);
+    Ok(sealed)
 }
 
+/**
+    decrypt the output of `aead_encrypt`
+    fails with `MacError` if the key or `aad` is wrong or the data was changed
+*/
+pub fn aead_decrypt(key: &[u8], aad: &[u8], sealed: &[u8]) -> Result<Vec<u8>> {
+    if sealed.len() < NONCE_LEN + TAG_LEN {
+        anyhow::bail!("The encrypted data is cut off");
+    }
+    let (nonce, rest) = sealed.split_at(NONCE_LEN);
+    let (ciphertext, tag) = rest.split_at(rest.len() - TAG_LEN);
+    decrypt_aead(Cipher::aes_256_gcm(), key, Some(nonce), aad, ciphertext, tag)
+        .map_err(|_| MacError.into())
+}
+
+/**
+    AES-256-CTR, only older vaults were written with it
+*/
+#[cfg(test)]
 pub fn aes_encrypt(key: &[u8], iv: &[u8], data: &[u8]) -> Result<Vec<u8>> {
     let cipher = Cipher::aes_256_ctr();
     let mut crypter =
@@ -111,6 +259,58 @@ mod tests {
         assert_eq!(key, expected_key);
     }
 
+    #[test]
+    fn test_kdf() -> Result<()> {
+        let kdf = Kdf::new(Variant::Argon2id, 1024, 1, 1);
+        let key = kdf.derive("pw")?;
+        assert_eq!(key, kdf.derive("pw")?);
+        assert_ne!(key, kdf.derive("other")?);
+        // the salt is random, so equal passphrases give different keys
+        let renewed = kdf.renew();
+        assert_ne!(renewed.salt, kdf.salt);
+        assert_ne!(renewed.derive("pw")?, key);
+
+        let config = Config {
+            mem_cost: 1024,
+            time_cost: 1,
+            lanes: 1,
+            ..Config::owasp3()
+        };
+        assert_eq!(key.to_vec(), argon2::hash_raw(b"pw", &kdf.salt, &config)?);
+
+        assert!(Kdf::new(Variant::Argon2id, 4, 1, 1).derive("pw").is_err());
+        assert!(Kdf::new(Variant::Argon2id, 1024, 0, 1).validate().is_err());
+        assert!(Kdf::new(Variant::Argon2id, MAX_MEMORY + 1, 1, 1).validate().is_err());
+        assert!(Kdf::new(Variant::Argon2id, 1024, 1, MAX_LANES + 1).validate().is_err());
+        assert!(Kdf::default().validate().is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_aead() -> Result<()> {
+        let key = [7u8; 32];
+        let sealed = aead_encrypt(&key, b"id", b"secret")?;
+        assert_eq!(sealed.len(), NONCE_LEN + 6 + TAG_LEN);
+        assert_eq!(aead_decrypt(&key, b"id", &sealed)?, b"secret");
+        // the nonce is random
+        assert_ne!(aead_encrypt(&key, b"id", b"secret")?, sealed);
+
+        let mac_error = |result: Result<Vec<u8>>| result.unwrap_err().downcast_ref::<MacError>().is_some();
+        assert!(mac_error(aead_decrypt(&[8u8; 32], b"id", &sealed)));
+        assert!(mac_error(aead_decrypt(&key, b"other", &sealed)));
+        let mut tampered = sealed.clone();
+        tampered[NONCE_LEN] ^= 1;
+        assert!(mac_error(aead_decrypt(&key, b"id", &tampered)));
+        assert!(aead_decrypt(&key, b"id", &sealed[..NONCE_LEN + TAG_LEN - 1]).is_err());
+
+        // subkeys differ by purpose and from the key itself
+        let vault_key = subkey(&key, b"vault")?;
+        assert_ne!(*vault_key, *subkey(&key, b"secret")?);
+        assert_ne!(*vault_key, key);
+        assert_eq!(*vault_key, *subkey(&key, b"vault")?);
+        Ok(())
+    }
+
     #[test]
     fn test_derive_sha256_digest() {
         let password = "super_secret_password";
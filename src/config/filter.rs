@@ -0,0 +1,103 @@
+use crate::config::app_config::{group_contains, Server};
+
+/// one word of a filter
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Term {
+    /// `tag:prod`, a tag equal to it
+    Tag(String),
+    /// `group:prod`, the group or one of its subgroups
+    Group(String),
+    /// anything else, found in the name, address, user or notes
+    Text(String),
+}
+
+/**
+    servers matching every word of a query like `tag:prod group:eu db`
+    case is ignored
+*/
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ServerFilter {
+    terms: Vec<Term>,
+}
+
+/// the parts of a server a filter looks at
+pub struct Fields<'a> {
+    pub name: &'a str,
+    pub ip: &'a str,
+    pub user: &'a str,
+    pub group: &'a str,
+    pub tags: &'a [String],
+    pub notes: &'a str,
+}
+
+impl ServerFilter {
+    pub fn parse(query: &str) -> Self {
+        let terms = query
+            .split_whitespace()
+            .map(|word| {
+                let word = word.to_lowercase();
+                if let Some(tag) = word.strip_prefix("tag:") {
+                    Term::Tag(tag.to_string())
+                } else if let Some(group) = word.strip_prefix("group:") {
+                    Term::Group(group.to_string())
+                } else {
+                    Term::Text(word)
+                }
+            })
+            .collect();
+        Self { terms }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    pub fn matches_fields(&self, fields: Fields) -> bool {
+        self.terms.iter().all(|term| match term {
+            Term::Tag(tag) => fields.tags.iter().any(|t| t.to_lowercase() == *tag),
+            Term::Group(group) => group_contains(&fields.group.to_lowercase(), group),
+            Term::Text(text) => [fields.name, fields.ip, fields.user, fields.notes]
+                .iter()
+                .any(|field| field.to_lowercase().contains(text.as_str())),
+        })
+    }
+
+    pub fn matches(&self, server: &Server) -> bool {
+        self.matches_fields(Fields {
+            name: &server.name,
+            ip: &server.ip,
+            user: &server.user,
+            group: &server.group,
+            tags: &server.tags,
+            notes: &server.notes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_server_filter() {
+        let mut server = Server::new(
+            "db1".to_string(),
+            "10.0.0.1".to_string(),
+            "dba".to_string(),
+            "bash".to_string(),
+            22,
+        );
+        server.group = "prod/db".to_string();
+        server.tags = vec!["Postgres".to_string(), "eu".to_string()];
+        server.notes = "owner: storage team\nmaintenance window Sunday".to_string();
+
+        assert!(ServerFilter::parse("").matches(&server));
+        assert!(ServerFilter::parse("tag:postgres").matches(&server));
+        assert!(!ServerFilter::parse("tag:post").matches(&server));
+        assert!(ServerFilter::parse("group:prod tag:eu").matches(&server));
+        assert!(!ServerFilter::parse("group:pro").matches(&server));
+        assert!(ServerFilter::parse("sunday").matches(&server));
+        assert!(ServerFilter::parse("10.0.0").matches(&server));
+        assert!(!ServerFilter::parse("tag:eu staging").matches(&server));
+    }
+}
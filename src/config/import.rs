@@ -0,0 +1,108 @@
+use anyhow::Result;
+
+use crate::config::app_config::{Config, Server};
+use crate::config::app_vault::{self, encrypt_password, Vault};
+use crate::config::store;
+
+/// servers read from another tool, before they are saved
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    /// servers to add
+    pub servers: Vec<Server>,
+    /// saved servers with new details, matched by id or name
+    pub updated: Vec<Server>,
+    /// (server id, password) to store in the vault
+    pub passwords: Vec<(String, String)>,
+    /// names of the hosts already saved with the same address, user and port
+    pub duplicates: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+impl ImportReport {
+    /**
+        keep the server unless one with the same address, user and port
+        is already saved or imported
+    */
+    pub fn add(&mut self, server: Server, existing: &[Server]) {
+        let is_duplicate = existing
+            .iter()
+            .chain(self.servers.iter())
+            .any(|s| s.ip == server.ip && s.user == server.user && s.port == server.port);
+        if is_duplicate {
+            self.duplicates.push(server.name);
+        } else {
+            self.servers.push(server);
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        let mut summary = format!("Imported {} servers", self.servers.len());
+        if !self.updated.is_empty() {
+            let names: Vec<&str> = self.updated.iter().map(|s| s.name.as_str()).collect();
+            summary.push_str(&format!(", updated {}: {}", names.len(), names.join(", ")));
+        }
+        if !self.duplicates.is_empty() {
+            summary.push_str(&format!(
+                ", skipped {} already saved: {}",
+                self.duplicates.len(),
+                self.duplicates.join(", ")
+            ));
+        }
+        for warning in &self.warnings {
+            summary.push('\n');
+            summary.push_str(warning);
+        }
+        let jumping: Vec<&str> = self
+            .servers
+            .iter()
+            .chain(self.updated.iter())
+            .filter(|s| s.proxy_jump.is_some())
+            .map(|s| s.name.as_str())
+            .collect();
+        if !jumping.is_empty() {
+            summary.push_str(&format!(
+                "\nunsupported directive proxyjump kept but not used to connect to {}",
+                jumping.join(", ")
+            ));
+        }
+        summary
+    }
+}
+
+/**
+    add and update the imported servers, new servers without a password get
+    an empty one in the vault so they authenticate with keys;
+    both files are written once
+*/
+pub fn save_imported(
+    config: &mut Config,
+    vault: &mut Vault,
+    report: ImportReport,
+    encryption_key: &[u8; 32],
+) -> Result<()> {
+    if report.servers.is_empty() && report.updated.is_empty() {
+        return Ok(());
+    }
+    store::update(config, vault, encryption_key, |config, vault| {
+        for server in report.updated {
+            if let Some(saved) = config.servers.iter_mut().find(|s| s.id == server.id) {
+                *saved = server;
+            }
+        }
+        for server in report.servers {
+            let password = encrypt_password(&server.id, "", encryption_key)?;
+            vault.set_server(app_vault::Server::new(server.id.clone(), password));
+            config.servers.push(server);
+        }
+        for (id, password) in report.passwords {
+            let password = encrypt_password(&id, &password, encryption_key)?;
+            vault.set_server(app_vault::Server::new(id, password));
+        }
+        Ok(())
+    })
+}
+
+/// the local user, which ssh logs in as when no user is given
+pub fn local_user() -> String {
+    std::env::var("USER").unwrap_or_else(|_| "root".to_string())
+}
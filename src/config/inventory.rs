@@ -0,0 +1,408 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+
+use crate::config::app_config::{normalize_group, Server};
+use crate::config::app_vault::{decrypt_password, Vault};
+use crate::config::import::ImportReport;
+
+/// columns of an exported CSV file, `password` only with secrets
+const CSV_COLUMNS: [&str; 12] = [
+    "id", "name", "ip", "user", "port", "shell", "identity", "proxy_jump", "group", "tags",
+    "notes", "password",
+];
+
+/// structured formats servers can be imported from and exported to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Yaml,
+    Csv,
+}
+
+/**
+    one server of an inventory, flat so it fits a CSV row
+    only `name` and `ip` are required on import
+*/
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ServerRecord {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub name: String,
+    #[serde(alias = "host")]
+    pub ip: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// wider than u16 so an out of range port is reported, not a parse error
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shell: Option<String>,
+    /// key files, comma separated like in the server form
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identity: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy_jump: Option<String>,
+    /// folder like `prod/db`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// comma separated as well
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<String>,
+    /// may span lines, quoted in CSV
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+}
+
+/**
+    check a host name against RFC 1123, or an IP address
+*/
+pub fn is_valid_host(host: &str) -> bool {
+    if host.parse::<IpAddr>().is_ok() {
+        return true;
+    }
+    let host = host.strip_suffix('.').unwrap_or(host);
+    !host.is_empty()
+        && host.len() <= 253
+        && host.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+/**
+    why a record can't be imported, all problems at once
+*/
+fn validate(record: &ServerRecord) -> Vec<String> {
+    let mut problems = Vec::new();
+    if record.name.trim().is_empty() {
+        problems.push("name is empty".to_string());
+    }
+    if !is_valid_host(record.ip.trim()) {
+        problems.push(format!("invalid host name {:?}", record.ip));
+    }
+    if let Some(port) = record.port {
+        if !(1..=65535).contains(&port) {
+            problems.push(format!("port {} out of range", port));
+        }
+    }
+    if record.user.as_deref().is_some_and(|user| user.trim().is_empty()) {
+        problems.push("user is empty".to_string());
+    }
+    problems
+}
+
+fn split_list(list: &str) -> Vec<String> {
+    list
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/**
+    read the records of an inventory file
+*/
+pub fn parse_records(format: Format, content: &str) -> Result<Vec<ServerRecord>> {
+    match format {
+        Format::Json => serde_json::from_str(content).context("Failed to parse JSON inventory."),
+        Format::Yaml => serde_yaml::from_str(content).context("Failed to parse YAML inventory."),
+        Format::Csv => {
+            let mut reader = csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .from_reader(content.as_bytes());
+            reader
+                .deserialize()
+                .collect::<Result<Vec<ServerRecord>, _>>()
+                .context("Failed to parse CSV inventory.")
+        }
+    }
+}
+
+/**
+    merge records into the saved servers
+    a record updates the server with the same id, else the same name;
+    a new server keeps the record's id unless it is empty or already taken;
+    invalid records are skipped and reported
+*/
+pub fn import_records(records: Vec<ServerRecord>, existing: &[Server], user: &str) -> ImportReport {
+    let mut report = ImportReport::default();
+    for (index, record) in records.into_iter().enumerate() {
+        let problems = validate(&record);
+        if !problems.is_empty() {
+            report.warnings.push(format!(
+                "record {} ({}): {}",
+                index + 1,
+                record.name,
+                problems.join(", ")
+            ));
+            continue;
+        }
+        let matched = record
+            .id
+            .as_ref()
+            .and_then(|id| existing.iter().find(|s| &s.id == id))
+            .or_else(|| existing.iter().find(|s| s.name == record.name));
+        let mut server = match matched {
+            Some(server) => server.clone(),
+            None => {
+                let mut server = Server::new(
+                    String::new(),
+                    String::new(),
+                    user.to_string(),
+                    "bash".to_string(),
+                    22,
+                );
+                // servers sharing an id would share their vault record
+                let id = record.id.as_deref().map_or("", str::trim);
+                if !id.is_empty() && !report.servers.iter().any(|s| s.id == id) {
+                    server.id = id.to_string();
+                }
+                server
+            }
+        };
+        server.name = record.name.trim().to_string();
+        server.ip = record.ip.trim().to_string();
+        if let Some(user) = record.user {
+            server.user = user.trim().to_string();
+        }
+        if let Some(port) = record.port {
+            server.port = port as u16;
+        }
+        if let Some(shell) = record.shell.filter(|shell| !shell.trim().is_empty()) {
+            server.shell = shell.trim().to_string();
+        }
+        if let Some(identity) = record.identity {
+            server.identity_files = split_list(&identity);
+        }
+        if let Some(group) = record.group {
+            server.group = normalize_group(&group);
+        }
+        if let Some(tags) = record.tags {
+            server.tags = split_list(&tags);
+        }
+        if let Some(notes) = record.notes {
+            server.notes = notes.trim_end().to_string();
+        }
+        if let Some(proxy_jump) = record.proxy_jump {
+            let proxy_jump = proxy_jump.trim();
+            server.proxy_jump = (!proxy_jump.is_empty()).then(|| proxy_jump.to_string());
+        }
+
+        let id = server.id.clone();
+        if matched.is_some() {
+            report.updated.retain(|s| s.id != id);
+            report.updated.push(server);
+        } else {
+            let added = report.servers.len();
+            report.add(server, existing);
+            if report.servers.len() == added {
+                continue;
+            }
+        }
+        if let Some(password) = record.password {
+            report.passwords.push((id, password));
+        }
+    }
+    report
+}
+
+/**
+    records of the servers
+    passwords are only read when the unlocked vault is given
+*/
+pub fn export_records(
+    servers: &[Server],
+    secrets: Option<(&Vault, &[u8; 32])>,
+) -> Result<Vec<ServerRecord>> {
+    servers
+        .iter()
+        .map(|server| {
+            let password = match secrets {
+                Some((vault, encryption_key)) => vault
+                    .servers
+                    .iter()
+                    .find(|s| s.id == server.id)
+                    .map(|s| decrypt_password(&s.id, &s.password, encryption_key))
+                    .transpose()?,
+                None => None,
+            };
+            Ok(ServerRecord {
+                id: Some(server.id.clone()),
+                name: server.name.clone(),
+                ip: server.ip.clone(),
+                user: Some(server.user.clone()),
+                port: Some(server.port.into()),
+                shell: Some(server.shell.clone()),
+                identity: (!server.identity_files.is_empty())
+                    .then(|| server.identity_files.join(",")),
+                proxy_jump: server.proxy_jump.clone(),
+                group: (!server.group.is_empty()).then(|| server.group.clone()),
+                tags: (!server.tags.is_empty()).then(|| server.tags.join(",")),
+                notes: (!server.notes.is_empty()).then(|| server.notes.clone()),
+                password,
+            })
+        })
+        .collect()
+}
+
+/**
+    write records in the format
+    CSV always has the same columns, so rows line up
+*/
+pub fn format_records(format: Format, records: &[ServerRecord], with_secrets: bool) -> Result<String> {
+    match format {
+        Format::Json => {
+            let mut json = serde_json::to_string_pretty(records)?;
+            json.push('\n');
+            Ok(json)
+        }
+        Format::Yaml => Ok(serde_yaml::to_string(records)?),
+        Format::Csv => {
+            let columns = if with_secrets {
+                &CSV_COLUMNS[..]
+            } else {
+                &CSV_COLUMNS[..CSV_COLUMNS.len() - 1]
+            };
+            let mut writer = csv::Writer::from_writer(Vec::new());
+            writer.write_record(columns)?;
+            for record in records {
+                let port = record.port.map(|port| port.to_string());
+                let row = [
+                    record.id.as_deref(),
+                    Some(record.name.as_str()),
+                    Some(record.ip.as_str()),
+                    record.user.as_deref(),
+                    port.as_deref(),
+                    record.shell.as_deref(),
+                    record.identity.as_deref(),
+                    record.proxy_jump.as_deref(),
+                    record.group.as_deref(),
+                    record.tags.as_deref(),
+                    record.notes.as_deref(),
+                    record.password.as_deref(),
+                ];
+                writer.write_record(row[..columns.len()].iter().map(|field| field.unwrap_or("")))?;
+            }
+            Ok(String::from_utf8(writer.into_inner()?)?)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_valid_host() {
+        assert!(is_valid_host("db-1.example.com"));
+        assert!(is_valid_host("10.0.0.1"));
+        assert!(is_valid_host("fe80::1"));
+        assert!(is_valid_host("localhost."));
+        assert!(!is_valid_host(""));
+        assert!(!is_valid_host("-web"));
+        assert!(!is_valid_host("web..example.com"));
+        assert!(!is_valid_host("web server"));
+        assert!(!is_valid_host(&"a".repeat(64)));
+    }
+
+    #[test]
+    fn test_import_records() {
+        let mut saved = Server::new(
+            "web".to_string(),
+            "10.0.0.5".to_string(),
+            "deploy".to_string(),
+            "zsh".to_string(),
+            22,
+        );
+        saved.identity_files = vec!["~/.ssh/id_web".to_string()];
+        let other = Server::new(
+            "db".to_string(),
+            "10.0.0.6".to_string(),
+            "dba".to_string(),
+            "bash".to_string(),
+            22,
+        );
+        let csv = format!(
+            "id,name,host,user,port,identity,password\n\
+             ,web,10.0.0.50,,2222,,\n\
+             {},renamed-db,10.0.0.6,,,,secret\n\
+             ,cache,10.0.0.7,,,\"~/.ssh/a, ~/.ssh/b\",\n\
+             ,copy,10.0.0.6,dba,22,,\n\
+             ,bad,bad host,,70000,,\n",
+            other.id
+        );
+        let records = parse_records(Format::Csv, &csv).unwrap();
+        let report = import_records(records, &[saved.clone(), other.clone()], "alice");
+
+        // merged by name, keeping what the record leaves out
+        let web = &report.updated[0];
+        assert_eq!(web.id, saved.id);
+        assert_eq!((web.ip.as_str(), web.port), ("10.0.0.50", 2222));
+        assert_eq!((web.user.as_str(), web.shell.as_str()), ("deploy", "zsh"));
+        assert_eq!(web.identity_files, saved.identity_files);
+        // merged by id
+        assert_eq!(report.updated[1].id, other.id);
+        assert_eq!(report.updated[1].name, "renamed-db");
+        assert_eq!(report.passwords, vec![(other.id.clone(), "secret".to_string())]);
+
+        assert_eq!(report.servers.len(), 1);
+        let cache = &report.servers[0];
+        assert_eq!(cache.user, "alice");
+        assert_eq!(cache.identity_files, vec!["~/.ssh/a", "~/.ssh/b"]);
+        assert_eq!(report.duplicates, vec!["copy"]);
+        assert_eq!(
+            report.warnings,
+            vec!["record 5 (bad): invalid host name \"bad host\", port 70000 out of range"]
+        );
+    }
+
+    #[test]
+    fn test_import_records_ids() {
+        let json = r#"[
+            {"id": "kept", "name": "a", "ip": "10.0.0.1"},
+            {"id": "kept", "name": "b", "ip": "10.0.0.2"},
+            {"id": "", "name": "c", "ip": "10.0.0.3"},
+            {"id": " ", "name": "d", "ip": "10.0.0.4"}
+        ]"#;
+        let records = parse_records(Format::Json, json).unwrap();
+        let report = import_records(records, &[], "alice");
+        assert_eq!(report.servers.len(), 4);
+        assert_eq!(report.servers[0].id, "kept");
+        let mut ids: Vec<&str> = report.servers.iter().map(|s| s.id.as_str()).collect();
+        assert!(ids.iter().all(|id| !id.trim().is_empty()));
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), 4);
+    }
+
+    #[test]
+    fn test_export_records() {
+        let mut server = Server::new(
+            "web".to_string(),
+            "10.0.0.5".to_string(),
+            "deploy".to_string(),
+            "bash".to_string(),
+            2222,
+        );
+        server.identity_files = vec!["~/.ssh/a".to_string(), "vault:b".to_string()];
+        server.tags = vec!["prod".to_string(), "web".to_string()];
+        server.group = "prod/web".to_string();
+        server.notes = "owner: web team\nmaintenance window Sunday".to_string();
+        let records = export_records(&[server], None).unwrap();
+        assert_eq!(records[0].password, None);
+        for format in [Format::Json, Format::Yaml, Format::Csv] {
+            let exported = format_records(format, &records, false).unwrap();
+            assert!(!exported.contains("password"));
+            assert_eq!(parse_records(format, &exported).unwrap(), records);
+        }
+        let csv = format_records(Format::Csv, &records, true).unwrap();
+        assert!(csv.starts_with("id,name,ip,user,port,shell,identity,proxy_jump,group,tags,notes,password\n"));
+    }
+}
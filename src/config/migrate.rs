@@ -0,0 +1,190 @@
+use anyhow::{Context, Result};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// top level key holding the format version of a file
+const VERSION_KEY: &str = "version";
+
+/// upgrade a file from the version at its index to the next one
+type Step = fn(&mut toml::Table) -> Result<()>;
+
+/**
+    the formats a kind of file went through
+    a file without a version key is version 0, `steps[n]` turns version n into n + 1
+*/
+pub struct Schema {
+    /// file name used in messages
+    pub name: &'static str,
+    pub steps: &'static [Step],
+}
+
+impl Schema {
+    /// the version written by this build
+    pub const fn version(&self) -> u32 {
+        self.steps.len() as u32
+    }
+}
+
+/**
+    config.toml
+    0: everything before the version key, fields were only ever added
+*/
+pub const CONFIG: Schema = Schema {
+    name: "config.toml",
+    steps: &[mark_versioned],
+};
+
+/**
+    the TOML inside the encrypted vault
+    0: everything before the version key, fields were only ever added
+*/
+pub const VAULT: Schema = Schema {
+    name: "vault",
+    steps: &[mark_versioned],
+};
+
+/// the unversioned formats read as they are, only the version key is new
+fn mark_versioned(_: &mut toml::Table) -> Result<()> {
+    Ok(())
+}
+
+/**
+    the version a file was written in
+*/
+fn version_of(table: &toml::Table, schema: &Schema) -> Result<u32> {
+    match table.get(VERSION_KEY) {
+        None => Ok(0),
+        Some(toml::Value::Integer(version)) => u32::try_from(*version)
+            .with_context(|| format!("Invalid {} version {}", schema.name, version)),
+        Some(other) => Err(anyhow::anyhow!(
+            "Invalid {} version {}, expected a number",
+            schema.name,
+            other
+        )),
+    }
+}
+
+/**
+    upgrade a parsed file to the current version one step at a time
+    returns the version it was in, files from a newer build are refused
+*/
+pub fn upgrade(table: &mut toml::Table, schema: &Schema) -> Result<u32> {
+    let from = version_of(table, schema)?;
+    if from > schema.version() {
+        return Err(anyhow::anyhow!(
+            "The {} is in format {} but this ssh-utils only reads up to {}, please update ssh-utils.",
+            schema.name,
+            from,
+            schema.version()
+        ));
+    }
+    for version in from..schema.version() {
+        (schema.steps[version as usize])(table).with_context(|| {
+            format!("Failed to upgrade {} from format {}", schema.name, version)
+        })?;
+        table.insert(VERSION_KEY.to_string(), toml::Value::Integer(i64::from(version) + 1));
+    }
+    Ok(from)
+}
+
+/**
+    serialized file with the current version on top
+*/
+pub fn stamp(schema: &Schema, body: &str) -> String {
+    // keep a blank line before the first table, like toml does
+    let separator = if body.starts_with('[') { "\n" } else { "" };
+    format!("{} = {}\n{}{}", VERSION_KEY, schema.version(), separator, body)
+}
+
+/**
+    copy a file about to be upgraded next to it as `<file>.v<version>.bak`
+    an existing backup is kept, it is the older one
+*/
+pub fn backup(path: &Path, version: u32) -> Result<PathBuf> {
+    backup_as(path, &format!("v{}", version))
+}
+
+/**
+    copy a file about to be upgraded next to it as `<file>.<label>.bak`
+    an existing backup is kept
+*/
+pub fn backup_as(path: &Path, label: &str) -> Result<PathBuf> {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(format!(".{}.bak", label));
+    let backup = PathBuf::from(backup);
+    if !backup.exists() {
+        fs::copy(path, &backup)
+            .with_context(|| format!("Failed to back up {:?} to {:?}", path, backup))?;
+    }
+    Ok(backup)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rename_host(table: &mut toml::Table) -> Result<()> {
+        if let Some(host) = table.remove("host") {
+            table.insert("ip".to_string(), host);
+        }
+        Ok(())
+    }
+
+    fn add_port(table: &mut toml::Table) -> Result<()> {
+        table.entry("port").or_insert(toml::Value::Integer(22));
+        Ok(())
+    }
+
+    const TEST: Schema = Schema {
+        name: "test",
+        steps: &[rename_host, add_port],
+    };
+
+    #[test]
+    fn test_upgrade() {
+        let mut table: toml::Table = toml::from_str("host = \"10.0.0.1\"").unwrap();
+        assert_eq!(upgrade(&mut table, &TEST).unwrap(), 0);
+        assert_eq!(
+            table,
+            toml::from_str("version = 2\nip = \"10.0.0.1\"\nport = 22").unwrap()
+        );
+
+        // only the missing steps run
+        let mut table: toml::Table = toml::from_str("version = 1\nhost = \"kept\"").unwrap();
+        assert_eq!(upgrade(&mut table, &TEST).unwrap(), 1);
+        assert_eq!(table["host"].as_str(), Some("kept"));
+        assert_eq!(table["port"].as_integer(), Some(22));
+
+        let mut table: toml::Table = toml::from_str("version = 2").unwrap();
+        assert_eq!(upgrade(&mut table, &TEST).unwrap(), 2);
+
+        let mut table: toml::Table = toml::from_str("version = 3").unwrap();
+        assert!(upgrade(&mut table, &TEST)
+            .unwrap_err()
+            .to_string()
+            .contains("only reads up to 2"));
+        let mut table: toml::Table = toml::from_str("version = \"2\"").unwrap();
+        assert!(upgrade(&mut table, &TEST).is_err());
+        let mut table: toml::Table = toml::from_str("version = -1").unwrap();
+        assert!(upgrade(&mut table, &TEST).is_err());
+    }
+
+    #[test]
+    fn test_stamp_and_backup() {
+        let stamped = stamp(&TEST, "ip = \"10.0.0.1\"\n");
+        assert_eq!(stamped, "version = 2\nip = \"10.0.0.1\"\n");
+        let stamped = stamp(&TEST, "[[servers]]\n");
+        assert_eq!(stamped, "version = 2\n\n[[servers]]\n");
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("config.toml");
+        fs::write(&path, "old").unwrap();
+        let backup_path = backup(&path, 0).unwrap();
+        assert_eq!(backup_path, temp_dir.path().join("config.toml.v0.bak"));
+        fs::write(&path, "newer").unwrap();
+        backup(&path, 0).unwrap();
+        assert_eq!(fs::read_to_string(&backup_path).unwrap(), "old");
+    }
+}
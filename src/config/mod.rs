@@ -1,3 +1,14 @@
 pub mod app_config;
 pub mod crypto;
-pub mod app_vault;
\ No newline at end of file
+pub mod app_vault;
+pub mod ssh_config;
+pub mod import;
+pub mod inventory;
+pub mod ansible;
+pub mod filter;
+pub mod migrate;
+pub mod paths;
+pub mod storage;
+pub mod store;
+pub mod backup;
+pub mod passphrase;
\ No newline at end of file
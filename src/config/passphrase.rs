@@ -0,0 +1,181 @@
+use anyhow::{Context, Result};
+use std::{
+    env,
+    ffi::OsString,
+    fmt, fs,
+    io::Read,
+    os::unix::fs::{MetadataExt, PermissionsExt},
+    path::PathBuf,
+    process::{Command, Stdio},
+};
+use zeroize::Zeroizing;
+
+/// file holding the vault passphrase, for scripts and cron jobs
+pub const PASSPHRASE_FILE_ENV: &str = "SSH_UTILS_PASSPHRASE_FILE";
+
+/**
+    where the vault passphrase comes from
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PassphraseSource {
+    /// asked on the terminal
+    Prompt,
+    /// `--passphrase-fd`
+    Fd(i32),
+    /// `$SSH_UTILS_PASSPHRASE_FILE`
+    File(PathBuf),
+    /// `passphrase_command` of config.toml, run by sh
+    Command(String),
+}
+
+impl PassphraseSource {
+    /**
+        `--passphrase-fd`, `$SSH_UTILS_PASSPHRASE_FILE`, `passphrase_command`, the first one set
+        the terminal if none is
+    */
+    pub fn resolve(fd: Option<i32>, command: Option<String>) -> Self {
+        Self::choose(fd, env::var_os(PASSPHRASE_FILE_ENV), command)
+    }
+
+    fn choose(fd: Option<i32>, file: Option<OsString>, command: Option<String>) -> Self {
+        if let Some(fd) = fd {
+            return Self::Fd(fd);
+        }
+        if let Some(file) = file.filter(|file| !file.is_empty()) {
+            return Self::File(PathBuf::from(file));
+        }
+        match command.filter(|command| !command.trim().is_empty()) {
+            Some(command) => Self::Command(command),
+            None => Self::Prompt,
+        }
+    }
+
+    /**
+        whether a wrong passphrase can be asked for again
+    */
+    pub fn is_interactive(&self) -> bool {
+        *self == Self::Prompt
+    }
+
+    /**
+        the first line of what the source gives, None -> ask on the terminal
+    */
+    pub fn read(&self) -> Result<Option<String>> {
+        let content = Zeroizing::new(match self {
+            Self::Prompt => return Ok(None),
+            // reopened, so it works for pipes and files alike
+            Self::Fd(fd) => {
+                let path = format!("/dev/fd/{}", fd);
+                fs::read(&path).with_context(|| format!("Unable to read the passphrase from {}", self))?
+            }
+            Self::File(path) => {
+                // checked and read through one handle, so the file can't be swapped in between
+                let context = || format!("Unable to read the passphrase file {:?}", path);
+                let mut file = fs::File::open(path).with_context(context)?;
+                let metadata = file.metadata().with_context(context)?;
+                // like ssh does for key files
+                // SAFETY: getuid(2) always succeeds
+                if metadata.uid() != unsafe { libc::getuid() } {
+                    anyhow::bail!("The passphrase file {:?} is not owned by you", path);
+                }
+                if metadata.permissions().mode() & 0o006 != 0 {
+                    anyhow::bail!(
+                        "The passphrase file {:?} is accessible by everyone, run \"chmod 600\" on it",
+                        path
+                    );
+                }
+                let mut content = Vec::new();
+                file.read_to_end(&mut content).with_context(context)?;
+                content
+            }
+            Self::Command(command) => {
+                // stdin and stderr stay with the terminal, for gpg or pass asking for their own
+                let output = Command::new("sh")
+                    .arg("-c")
+                    .arg(command)
+                    .stdin(Stdio::inherit())
+                    .stderr(Stdio::inherit())
+                    .output()
+                    .with_context(|| format!("Failed to run {}", self))?;
+                if !output.status.success() {
+                    anyhow::bail!("{} failed with {}", self, output.status);
+                }
+                output.stdout
+            }
+        });
+        let line = content.split(|&byte| byte == b'\n').next().unwrap_or_default();
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let passphrase = String::from_utf8(line.to_vec())
+            .map_err(|_| anyhow::anyhow!("The passphrase from {} is not UTF-8", self))?;
+        Ok(Some(passphrase))
+    }
+}
+
+impl fmt::Display for PassphraseSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Prompt => write!(f, "the terminal"),
+            Self::Fd(fd) => write!(f, "file descriptor {}", fd),
+            Self::File(path) => write!(f, "${} ({:?})", PASSPHRASE_FILE_ENV, path),
+            Self::Command(command) => write!(f, "passphrase_command \"{}\"", command),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::fd::AsRawFd;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_choose() {
+        let file = Some(OsString::from("/run/pass"));
+        let command = Some("pass show ssh-utils".to_string());
+        assert_eq!(
+            PassphraseSource::choose(Some(3), file.clone(), command.clone()),
+            PassphraseSource::Fd(3)
+        );
+        assert_eq!(
+            PassphraseSource::choose(None, file, command.clone()),
+            PassphraseSource::File(PathBuf::from("/run/pass"))
+        );
+        assert_eq!(
+            PassphraseSource::choose(None, Some(OsString::new()), command.clone()),
+            PassphraseSource::Command("pass show ssh-utils".to_string())
+        );
+        assert_eq!(
+            PassphraseSource::choose(None, None, Some(" ".to_string())),
+            PassphraseSource::Prompt
+        );
+        assert!(PassphraseSource::Prompt.is_interactive());
+        assert!(!PassphraseSource::Fd(0).is_interactive());
+    }
+
+    #[test]
+    fn test_read() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let path = temp_dir.path().join("pass");
+        fs::write(&path, "two words\r\nignored\n")?;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+        let file = PassphraseSource::File(path.clone());
+        assert_eq!(file.read()?.as_deref(), Some("two words"));
+
+        // the world may not read it, the group may
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644))?;
+        assert!(file.read().unwrap_err().to_string().contains("chmod 600"));
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640))?;
+        assert!(file.read().is_ok());
+
+        let opened = fs::File::open(&path)?;
+        let fd = PassphraseSource::Fd(opened.as_raw_fd());
+        assert_eq!(fd.read()?.as_deref(), Some("two words"));
+
+        let command = PassphraseSource::Command("printf 'from command\\n'".to_string());
+        assert_eq!(command.read()?.as_deref(), Some("from command"));
+        assert_eq!(PassphraseSource::Command("true".to_string()).read()?.as_deref(), Some(""));
+        assert!(PassphraseSource::Command("exit 3".to_string()).read().is_err());
+        assert_eq!(PassphraseSource::Prompt.read()?, None);
+        Ok(())
+    }
+}
@@ -0,0 +1,134 @@
+use anyhow::{Context, Result};
+use std::{
+    env,
+    ffi::OsString,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+/// environment variable naming the config directory
+pub const CONFIG_DIR_ENV: &str = "SSH_UTILS_CONFIG_DIR";
+const CONFIG_FILE: &str = "config.toml";
+const ENCRYPTED_FILE: &str = "encrypted_data.bin";
+
+static PATHS: OnceLock<ConfigPaths> = OnceLock::new();
+
+/**
+    where ssh-utils keeps its files, the first of
+    `--config-dir`, `$SSH_UTILS_CONFIG_DIR`, `$XDG_CONFIG_HOME/ssh-utils`
+    and `~/.config/ssh-utils`
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigPaths {
+    dir: PathBuf,
+}
+
+impl ConfigPaths {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /**
+        pick the directory from the flag, the environment or the home directory
+    */
+    fn resolve(
+        config_dir: Option<PathBuf>,
+        var: impl Fn(&str) -> Option<OsString>,
+        home_dir: Option<PathBuf>,
+    ) -> Result<Self> {
+        // empty variables count as unset
+        let var = |name: &str| var(name).filter(|value| !value.is_empty());
+        if let Some(dir) = config_dir.or_else(|| var(CONFIG_DIR_ENV).map(PathBuf::from)) {
+            return Ok(Self::new(dir));
+        }
+        // relative XDG paths are invalid and ignored
+        if let Some(config_home) = var("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .filter(|path| path.is_absolute())
+        {
+            return Ok(Self::new(config_home.join("ssh-utils")));
+        }
+        let home_dir = home_dir.context("Unable to reach user's home directory.")?;
+        Ok(Self::new(home_dir.join(".config/ssh-utils")))
+    }
+
+    /**
+        set the directory for the whole run, before any file is read
+        `config_dir` is the `--config-dir` flag
+    */
+    pub fn init(config_dir: Option<PathBuf>) -> Result<&'static Self> {
+        let paths = Self::resolve(config_dir, |name| env::var_os(name), dirs::home_dir())?;
+        Ok(PATHS.get_or_init(|| paths))
+    }
+
+    /**
+        the directory of this run, from the environment if `init` wasn't called
+    */
+    pub fn get() -> Result<&'static Self> {
+        match PATHS.get() {
+            Some(paths) => Ok(paths),
+            None => Self::init(None),
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn config_file(&self) -> PathBuf {
+        self.dir.join(CONFIG_FILE)
+    }
+
+    pub fn vault_file(&self) -> PathBuf {
+        self.dir.join(ENCRYPTED_FILE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn resolve(flag: Option<&str>, vars: &[(&str, &str)], home: Option<&str>) -> Result<PathBuf> {
+        let vars: HashMap<String, OsString> = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), OsString::from(value)))
+            .collect();
+        ConfigPaths::resolve(
+            flag.map(PathBuf::from),
+            |name| vars.get(name).cloned(),
+            home.map(PathBuf::from),
+        )
+        .map(|paths| paths.dir().to_path_buf())
+    }
+
+    #[test]
+    fn test_resolve() {
+        let home = Some("/home/alice");
+        assert_eq!(
+            resolve(None, &[], home).unwrap(),
+            Path::new("/home/alice/.config/ssh-utils")
+        );
+        let xdg = ("XDG_CONFIG_HOME", "/xdg");
+        assert_eq!(resolve(None, &[xdg], home).unwrap(), Path::new("/xdg/ssh-utils"));
+        assert_eq!(
+            resolve(None, &[("XDG_CONFIG_HOME", "relative")], home).unwrap(),
+            Path::new("/home/alice/.config/ssh-utils")
+        );
+        let env = (CONFIG_DIR_ENV, "/work/ssh-utils");
+        assert_eq!(resolve(None, &[xdg, env], home).unwrap(), Path::new("/work/ssh-utils"));
+        assert_eq!(
+            resolve(None, &[xdg, (CONFIG_DIR_ENV, "")], home).unwrap(),
+            Path::new("/xdg/ssh-utils")
+        );
+        assert_eq!(
+            resolve(Some("personal"), &[xdg, env], None).unwrap(),
+            Path::new("personal")
+        );
+        assert!(resolve(None, &[], None).is_err());
+
+        let paths = ConfigPaths::new(PathBuf::from("/xdg/ssh-utils"));
+        assert_eq!(paths.config_file(), Path::new("/xdg/ssh-utils/config.toml"));
+        assert_eq!(paths.vault_file(), Path::new("/xdg/ssh-utils/encrypted_data.bin"));
+    }
+}
@@ -0,0 +1,757 @@
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::app_config::{Server, SshOptions};
+use crate::config::import::ImportReport;
+use crate::config::storage;
+use crate::ssh::identity::{expand_tilde, VAULT_KEY_PREFIX};
+
+/// nested `Include`s deeper than this are ignored, like OpenSSH does
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// file kept in step with the saved servers, included from ~/.ssh/config
+pub const MANAGED_CONFIG: &str = "~/.ssh/ssh-utils.conf";
+
+/// first line of the managed file, it is only ever overwritten with this header
+const MANAGED_HEADER: &str = "# Generated by ssh-utils, changes are overwritten on every save";
+
+/**
+    one `Host` block of an OpenSSH client config
+    options before the first `Host` line belong to a block matching every host
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostBlock {
+    /// a `Match` block that can't be resolved has no patterns and matches nothing
+    pub patterns: Vec<String>,
+    /// (keyword in lowercase, value) in file order
+    pub options: Vec<(String, String)>,
+}
+
+impl HostBlock {
+    /**
+        check the host against the patterns, a matching negated pattern wins
+    */
+    pub fn matches(&self, host: &str) -> bool {
+        let mut matched = false;
+        for pattern in &self.patterns {
+            if let Some(negated) = pattern.strip_prefix('!') {
+                if glob_match(negated, host) {
+                    return false;
+                }
+            } else if glob_match(pattern, host) {
+                matched = true;
+            }
+        }
+        matched
+    }
+
+    pub fn values<'a>(&'a self, keyword: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.options
+            .iter()
+            .filter(move |(k, _)| k == keyword)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// a parsed client config, with what could not be understood
+#[derive(Debug, Default)]
+pub struct SshConfig {
+    pub blocks: Vec<HostBlock>,
+    pub warnings: Vec<String>,
+}
+
+impl SshConfig {
+    /**
+        options that apply to the host, the first value of a keyword wins like in ssh
+        `IdentityFile` is the exception, every value is kept
+    */
+    pub fn resolve(&self, host: &str) -> Vec<(String, String)> {
+        let mut options: Vec<(String, String)> = Vec::new();
+        for block in self.blocks.iter().filter(|block| block.matches(host)) {
+            for (keyword, value) in &block.options {
+                if keyword == "identityfile" || !options.iter().any(|(k, _)| k == keyword) {
+                    options.push((keyword.clone(), value.clone()));
+                }
+            }
+        }
+        options
+    }
+
+    /**
+        host names given in `Host` lines, wildcard patterns excluded
+    */
+    pub fn aliases(&self) -> Vec<String> {
+        let mut aliases: Vec<String> = Vec::new();
+        for block in &self.blocks {
+            for pattern in &block.patterns {
+                let is_pattern = pattern.contains(['*', '?', '!']);
+                if !is_pattern && !aliases.contains(pattern) {
+                    aliases.push(pattern.clone());
+                }
+            }
+        }
+        aliases
+    }
+}
+
+/**
+    match `*` and `?` wildcards like OpenSSH does, case-insensitively
+*/
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/**
+    split a config line into keyword and value
+    accepts both `Keyword value` and `Keyword=value`, and strips quotes
+*/
+fn split_line(line: &str) -> Option<(String, String)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let split_at = line.find(|c: char| c.is_whitespace() || c == '=')?;
+    let (keyword, rest) = line.split_at(split_at);
+    let value = rest
+        .trim_start_matches(|c: char| c.is_whitespace())
+        .trim_start_matches('=')
+        .trim();
+    let value = value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .unwrap_or(value);
+    Some((keyword.to_lowercase(), value.to_string()))
+}
+
+/**
+    host patterns equivalent to the criteria of a `Match` line
+    only `all`, `host` and `originalhost` can be known before connecting
+*/
+fn match_patterns(criteria: &str) -> Option<Vec<String>> {
+    let mut tokens = criteria.split_whitespace();
+    let mut patterns = Vec::new();
+    while let Some(criterion) = tokens.next() {
+        match criterion.to_lowercase().as_str() {
+            "all" => patterns.push("*".to_string()),
+            "host" | "originalhost" => {
+                let list = tokens.next()?;
+                patterns.extend(list.split(',').map(str::to_string));
+            }
+            _ => return None,
+        }
+    }
+    Some(patterns)
+}
+
+/**
+    files named by an `Include` argument, a `*` or `?` is allowed in the file name
+    relative paths are taken from `base_dir`, `~/.ssh` for the user's config
+*/
+fn include_paths(argument: &str, base_dir: &Path) -> Vec<PathBuf> {
+    let path = expand_tilde(argument);
+    let path = if path.is_absolute() {
+        path
+    } else {
+        base_dir.join(path)
+    };
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    if !file_name.contains(['*', '?']) {
+        return vec![path];
+    }
+    let Some(dir) = path.parent() else {
+        return Vec::new();
+    };
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| glob_match(&file_name, &entry.file_name().to_string_lossy()))
+                .map(|entry| entry.path())
+                .collect()
+        })
+        .unwrap_or_default();
+    paths.sort();
+    paths
+}
+
+struct Parser {
+    config: SshConfig,
+    /// where relative `Include`s are looked up, None when parsing a plain string
+    base_dir: Option<PathBuf>,
+}
+
+impl Parser {
+    fn parse(&mut self, content: &str, source: &str, depth: usize) {
+        // after a `Host` or `Match` line of this file, an `Include` only applies to that block
+        let mut in_block = false;
+        for (number, line) in content.lines().enumerate() {
+            let Some((keyword, value)) = split_line(line) else {
+                continue;
+            };
+            match keyword.as_str() {
+                "host" => {
+                    in_block = true;
+                    self.config.blocks.push(HostBlock {
+                        patterns: value.split_whitespace().map(str::to_string).collect(),
+                        options: Vec::new(),
+                    });
+                }
+                "match" => {
+                    in_block = true;
+                    let patterns = match_patterns(&value).unwrap_or_else(|| {
+                        self.config.warnings.push(format!(
+                            "{}:{}: Match {} can't be resolved, its options are ignored",
+                            source,
+                            number + 1,
+                            value
+                        ));
+                        Vec::new()
+                    });
+                    self.config.blocks.push(HostBlock {
+                        patterns,
+                        options: Vec::new(),
+                    });
+                }
+                // its hosts would only apply when the block matches too, which can't be imported
+                "include" if in_block => self.config.warnings.push(format!(
+                    "{}:{}: unsupported Include inside a Host or Match block ignored",
+                    source,
+                    number + 1
+                )),
+                "include" => self.include(&value, source, number + 1, depth),
+                _ => {
+                    if let Some(block) = self.config.blocks.last_mut() {
+                        block.options.push((keyword, value));
+                    }
+                }
+            }
+        }
+    }
+
+    fn include(&mut self, value: &str, source: &str, line: usize, depth: usize) {
+        let Some(base_dir) = self.base_dir.clone() else {
+            self.config
+                .warnings
+                .push(format!("{}:{}: Include is not supported here", source, line));
+            return;
+        };
+        if depth >= MAX_INCLUDE_DEPTH {
+            self.config
+                .warnings
+                .push(format!("{}:{}: Include nested too deeply", source, line));
+            return;
+        }
+        // the rest of the including block continues after the included files
+        let current = self.config.blocks.len();
+        for argument in value.split_whitespace() {
+            for path in include_paths(argument, &base_dir) {
+                match fs::read_to_string(&path) {
+                    Ok(content) => self.parse(&content, &path.display().to_string(), depth + 1),
+                    Err(e) => self.config.warnings.push(format!(
+                        "{}:{}: Unable to include {:?}: {}",
+                        source, line, path, e
+                    )),
+                }
+            }
+        }
+        if self.config.blocks.len() != current {
+            let patterns = self.config.blocks[current - 1].patterns.clone();
+            self.config.blocks.push(HostBlock {
+                patterns,
+                options: Vec::new(),
+            });
+        }
+    }
+}
+
+fn parse_with(content: &str, source: &str, base_dir: Option<PathBuf>) -> SshConfig {
+    let mut parser = Parser {
+        config: SshConfig {
+            blocks: vec![HostBlock {
+                patterns: vec!["*".to_string()],
+                options: Vec::new(),
+            }],
+            warnings: Vec::new(),
+        },
+        base_dir,
+    };
+    parser.parse(content, source, 0);
+    parser.config
+}
+
+/**
+    parse a config from a string, `Include` lines are reported and skipped
+*/
+#[cfg(test)]
+pub fn parse(content: &str) -> SshConfig {
+    parse_with(content, "config", None)
+}
+
+/**
+    path of the current user's OpenSSH client config
+*/
+pub fn user_config_path() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(".ssh/config"))
+}
+
+/**
+    read a config file and the files it includes
+*/
+pub fn read_from_path<P: AsRef<Path>>(path: P) -> Result<SshConfig> {
+    let path = path.as_ref();
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Unable to read ssh config at {:?}", path))?;
+    // like ssh, relative includes are looked up in ~/.ssh
+    let base_dir = dirs::home_dir()
+        .map(|home| home.join(".ssh"))
+        .or_else(|| path.parent().map(Path::to_path_buf));
+    Ok(parse_with(&content, &path.display().to_string(), base_dir))
+}
+
+/**
+    `IdentityFile` entries of every block that applies to the host
+*/
+pub fn identity_files_for(config: &SshConfig, host: &str) -> Vec<String> {
+    config
+        .resolve(host)
+        .into_iter()
+        .filter(|(keyword, _)| keyword == "identityfile")
+        .map(|(_, value)| value)
+        .collect()
+}
+
+/// comma separated algorithm list, None for the `+`, `-` and `^` forms relative to the defaults
+fn algorithm_list(value: &str) -> Option<Vec<String>> {
+    if value.starts_with(['+', '-', '^']) {
+        return None;
+    }
+    Some(value.split(',').map(|name| name.trim().to_string()).collect())
+}
+
+/**
+    create a server for every host alias of the config
+    `user` is used when the config doesn't set one, like ssh uses the local user
+*/
+pub fn import_servers(config: &SshConfig, existing: &[Server], user: &str) -> ImportReport {
+    let mut report = ImportReport {
+        warnings: config.warnings.clone(),
+        ..Default::default()
+    };
+    // keyword -> hosts using it
+    let mut unsupported: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for alias in config.aliases() {
+        let mut server = Server::new(
+            alias.clone(),
+            alias.clone(),
+            user.to_string(),
+            "bash".to_string(),
+            22,
+        );
+        let mut ssh_options = SshOptions::default();
+        for (keyword, value) in config.resolve(&alias) {
+            let mut supported = true;
+            match keyword.as_str() {
+                "hostname" => server.ip = value.replace("%h", &alias).replace("%%", "%"),
+                "user" => server.user = value,
+                "port" => match value.parse::<u16>() {
+                    Ok(port) if port != 0 => server.port = port,
+                    _ => report
+                        .warnings
+                        .push(format!("{}: invalid port {}", alias, value)),
+                },
+                "identityfile" => server.identity_files.push(value),
+                "proxyjump" if value.eq_ignore_ascii_case("none") => {}
+                "proxyjump" => server.proxy_jump = Some(value),
+                "ciphers" | "kexalgorithms" | "macs" | "hostkeyalgorithms" => {
+                    match algorithm_list(&value) {
+                        Some(list) => match keyword.as_str() {
+                            "ciphers" => ssh_options.ciphers = list,
+                            "kexalgorithms" => ssh_options.kex = list,
+                            "macs" => ssh_options.macs = list,
+                            _ => ssh_options.host_key_algorithms = list,
+                        },
+                        None => supported = false,
+                    }
+                }
+                "compression" => ssh_options.compression = value.eq_ignore_ascii_case("yes"),
+                "connecttimeout" => ssh_options.connect_timeout = value.parse().ok(),
+                // 0 turns keepalives off in both
+                "serveraliveinterval" => ssh_options.keepalive_interval = value.parse().ok(),
+                _ => supported = false,
+            }
+            if !supported {
+                unsupported.entry(keyword).or_default().push(alias.clone());
+            }
+        }
+        server.ssh_options = ssh_options;
+
+        report.add(server, existing);
+    }
+    for (keyword, hosts) in unsupported {
+        report.warnings.push(format!(
+            "unsupported directive {} ignored for {}",
+            keyword,
+            hosts.join(", ")
+        ));
+    }
+    report
+}
+
+/**
+    quote a value containing spaces, ssh splits on whitespace otherwise
+*/
+fn quote(value: &str) -> String {
+    if value.contains(char::is_whitespace) {
+        format!("\"{}\"", value)
+    } else {
+        value.to_string()
+    }
+}
+
+/**
+    `Host` alias for a server name, ssh patterns can't hold spaces or wildcards
+*/
+pub fn host_alias(name: &str) -> String {
+    let alias: String = name
+        .trim()
+        .chars()
+        .map(|c| {
+            if c.is_whitespace() || matches!(c, '*' | '?' | '!' | ',' | '"') {
+                '-'
+            } else {
+                c
+            }
+        })
+        .collect();
+    if alias.is_empty() {
+        "-".to_string()
+    } else {
+        alias
+    }
+}
+
+/**
+    `Host` blocks that reach the saved servers the way ssh-utils does
+*/
+pub fn export_servers(servers: &[Server]) -> String {
+    let mut out = String::new();
+    for server in servers {
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        let mut lines = vec![
+            format!("Host {}", host_alias(&server.name)),
+            format!("HostName {}", server.ip),
+            format!("User {}", quote(&server.user)),
+            format!("Port {}", server.port),
+        ];
+        for entry in &server.identity_files {
+            if entry.starts_with(VAULT_KEY_PREFIX) {
+                // ssh can't read keys embedded in the vault
+                lines.push(format!("# {} is kept in the ssh-utils vault", entry));
+            } else {
+                lines.push(format!("IdentityFile {}", quote(entry)));
+            }
+        }
+        if let Some(proxy_jump) = &server.proxy_jump {
+            lines.push(format!("ProxyJump {}", proxy_jump));
+        }
+        let options = &server.ssh_options;
+        for (keyword, list) in [
+            ("KexAlgorithms", &options.kex),
+            ("Ciphers", &options.ciphers),
+            ("MACs", &options.macs),
+            ("HostKeyAlgorithms", &options.host_key_algorithms),
+        ] {
+            if !list.is_empty() {
+                lines.push(format!("{} {}", keyword, list.join(",")));
+            }
+        }
+        if options.compression {
+            lines.push("Compression yes".to_string());
+        }
+        if let Some(secs) = options.connect_timeout {
+            lines.push(format!("ConnectTimeout {}", secs));
+        }
+        if let Some(secs) = options.keepalive_interval {
+            lines.push(format!("ServerAliveInterval {}", secs));
+        }
+        out.push_str(&lines[0]);
+        out.push('\n');
+        for line in &lines[1..] {
+            out.push_str("    ");
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/**
+    rewrite the managed file with the current servers
+    a file that ssh-utils didn't write is left alone
+*/
+pub fn write_managed_config(path: &str, servers: &[Server]) -> Result<()> {
+    let file_path = expand_tilde(path);
+    if let Ok(content) = fs::read_to_string(&file_path) {
+        if content.lines().next() != Some(MANAGED_HEADER) {
+            anyhow::bail!("{} was not written by ssh-utils, refusing to overwrite it", path);
+        }
+    }
+    if let Some(parent) = file_path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory {:?}", parent))?;
+    }
+    let content = format!("{}\n\n{}", MANAGED_HEADER, export_servers(servers));
+    storage::write_atomic(&resolve_symlink(&file_path), content.as_bytes(), 0o600)
+        .with_context(|| format!("Failed to write {:?}", file_path))
+}
+
+/**
+    make sure the user's ssh config includes the managed file
+    the `Include` goes first, so it applies to every host
+    true -> the line was added
+*/
+pub fn ensure_included(config_path: &Path, managed: &str) -> Result<bool> {
+    let content = match fs::read_to_string(config_path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e).with_context(|| format!("Unable to read {:?}", config_path)),
+    };
+    let included = content.lines().any(|line| {
+        split_line(line).is_some_and(|(keyword, value)| {
+            keyword == "include" && value.split_whitespace().any(|v| v == managed)
+        })
+    });
+    if included {
+        return Ok(false);
+    }
+    if let Some(parent) = config_path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory {:?}", parent))?;
+    }
+    let content = format!("Include {}\n\n{}", managed, content);
+    storage::write_atomic(&resolve_symlink(config_path), content.as_bytes(), 0o600)
+        .with_context(|| format!("Failed to write {:?}", config_path))?;
+    Ok(true)
+}
+
+/**
+    the file a symlink points to, so replacing it keeps the link
+    (dotfile managers link ~/.ssh/config into their repository)
+*/
+fn resolve_symlink(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*", "anything"));
+        assert!(glob_match("*.example.com", "db.example.com"));
+        assert!(!glob_match("*.example.com", "example.com"));
+        assert!(glob_match("web-??", "web-01"));
+        assert!(!glob_match("web-??", "web-001"));
+        assert!(glob_match("DB*", "db1"));
+    }
+
+    #[test]
+    fn test_identity_files_for() {
+        let content = r#"
+            IdentityFile ~/.ssh/id_global
+
+            Host *.prod !bastion.prod
+                IdentityFile ~/.ssh/id_prod
+                User deploy
+
+            Host=bastion.prod
+                IdentityFile "~/.ssh/id bastion"
+        "#;
+        let config = parse(content);
+        assert_eq!(config.blocks.len(), 3);
+        assert_eq!(
+            identity_files_for(&config, "db.prod"),
+            vec!["~/.ssh/id_global", "~/.ssh/id_prod"]
+        );
+        assert_eq!(
+            identity_files_for(&config, "bastion.prod"),
+            vec!["~/.ssh/id_global", "~/.ssh/id bastion"]
+        );
+        assert_eq!(identity_files_for(&config, "dev"), vec!["~/.ssh/id_global"]);
+    }
+
+    #[test]
+    fn test_import_servers() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let hosts_dir = temp_dir.path().join("hosts.d");
+        fs::create_dir(&hosts_dir).unwrap();
+        fs::write(
+            hosts_dir.join("db.conf"),
+            "Host db1 db2\n    HostName %h.internal\n    ProxyJump bastion\n",
+        )
+        .unwrap();
+        fs::write(hosts_dir.join("web.inc"), "Host web-only\n").unwrap();
+        let config_path = temp_dir.path().join("config");
+        fs::write(
+            &config_path,
+            format!(
+                r#"
+Include {0}/*.conf
+
+Host web
+    HostName 10.0.0.5
+    Port 2222
+    IdentityFile ~/.ssh/id_web
+    ForwardAgent yes
+    Include {0}/web.inc
+
+Host bastion
+    HostName bastion.example.com
+    Ciphers aes256-gcm@openssh.com
+    MACs +hmac-sha1
+
+Match host db*
+    User dba
+
+Match exec "true"
+    User nobody
+
+Host *
+    User deploy
+    ServerAliveInterval 30
+"#,
+                hosts_dir.display()
+            ),
+        )
+        .unwrap();
+        let config = read_from_path(&config_path).unwrap();
+        let existing = vec![Server::new(
+            "old".to_string(),
+            "bastion.example.com".to_string(),
+            "deploy".to_string(),
+            "bash".to_string(),
+            22,
+        )];
+        let report = import_servers(&config, &existing, "alice");
+
+        let names: Vec<&str> = report.servers.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["db1", "db2", "web"]);
+        assert_eq!(report.duplicates, vec!["bastion"]);
+
+        let web = &report.servers[2];
+        assert_eq!((web.ip.as_str(), web.port), ("10.0.0.5", 2222));
+        assert_eq!(web.user, "deploy");
+        assert_eq!(web.identity_files, vec!["~/.ssh/id_web"]);
+        assert_eq!(web.ssh_options.keepalive_interval, Some(30));
+
+        let db1 = &report.servers[0];
+        assert_eq!(db1.ip, "db1.internal");
+        assert_eq!(db1.user, "dba");
+        assert_eq!(db1.proxy_jump.as_deref(), Some("bastion"));
+
+        let warnings = report.warnings.join("\n");
+        assert!(warnings.contains("Match exec \"true\" can't be resolved"));
+        assert!(warnings.contains("unsupported Include inside a Host or Match block ignored"));
+        assert!(warnings.contains("unsupported directive forwardagent ignored for web"));
+        assert!(warnings.contains("unsupported directive macs ignored for bastion"));
+        assert!(report
+            .summary()
+            .contains("unsupported directive proxyjump kept but not used to connect to db1, db2"));
+    }
+
+    #[test]
+    fn test_export_servers() {
+        let mut web = Server::new(
+            "web 1".to_string(),
+            "10.0.0.5".to_string(),
+            "deploy".to_string(),
+            "bash".to_string(),
+            2222,
+        );
+        web.identity_files = vec!["~/.ssh/id web".to_string(), "vault:deploy".to_string()];
+        web.proxy_jump = Some("admin@bastion:2200".to_string());
+        web.ssh_options.ciphers = vec!["aes256-ctr".to_string(), "aes128-ctr".to_string()];
+        web.ssh_options.keepalive_interval = Some(30);
+        let exported = export_servers(&[web.clone()]);
+        assert!(exported.starts_with("Host web-1\n    HostName 10.0.0.5\n"));
+        assert!(exported.contains("    # vault:deploy is kept in the ssh-utils vault\n"));
+
+        // importing the export gives the server back
+        let report = import_servers(&parse(&exported), &[], "nobody");
+        let server = &report.servers[0];
+        assert_eq!(server.name, "web-1");
+        assert_eq!((server.ip.as_str(), server.user.as_str(), server.port), ("10.0.0.5", "deploy", 2222));
+        assert_eq!(server.identity_files, vec!["~/.ssh/id web"]);
+        assert_eq!(server.proxy_jump, web.proxy_jump);
+        assert_eq!(server.ssh_options, web.ssh_options);
+        assert!(report.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_managed_config() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let managed = temp_dir.path().join("ssh-utils.conf");
+        let managed = managed.to_str().unwrap();
+        let server = Server::new(
+            "db".to_string(),
+            "db.internal".to_string(),
+            "dba".to_string(),
+            "bash".to_string(),
+            22,
+        );
+        write_managed_config(managed, &[server]).unwrap();
+        let content = fs::read_to_string(managed).unwrap();
+        assert!(content.starts_with(MANAGED_HEADER));
+        assert!(content.contains("Host db\n"));
+        write_managed_config(managed, &[]).unwrap();
+        assert!(!fs::read_to_string(managed).unwrap().contains("Host db"));
+
+        // a file the user wrote is never overwritten
+        let foreign = temp_dir.path().join("config");
+        fs::write(&foreign, "Host mine\n").unwrap();
+        assert!(write_managed_config(foreign.to_str().unwrap(), &[]).is_err());
+        assert_eq!(fs::read_to_string(&foreign).unwrap(), "Host mine\n");
+
+        assert!(ensure_included(&foreign, managed).unwrap());
+        assert!(!ensure_included(&foreign, managed).unwrap());
+        let content = fs::read_to_string(&foreign).unwrap();
+        assert_eq!(content, format!("Include {}\n\nHost mine\n", managed));
+
+        // a symlinked config stays a symlink, its target gets the Include
+        let target = temp_dir.path().join("dotfiles-config");
+        fs::write(&target, "Host linked\n").unwrap();
+        let link = temp_dir.path().join("linked-config");
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        assert!(ensure_included(&link, managed).unwrap());
+        assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+        let content = fs::read_to_string(&target).unwrap();
+        assert_eq!(content, format!("Include {}\n\nHost linked\n", managed));
+    }
+}
@@ -0,0 +1,291 @@
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    os::unix::{
+        fs::{OpenOptionsExt, PermissionsExt},
+        io::AsRawFd,
+    },
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// name of the lock file in the config directory
+const LOCK_FILE: &str = ".lock";
+
+/// hash of each file as this process last read or wrote it
+static KNOWN: Mutex<BTreeMap<PathBuf, [u8; 32]>> = Mutex::new(BTreeMap::new());
+
+/**
+    the file was changed by another ssh-utils since it was read,
+    saving would throw that change away
+*/
+#[derive(Debug)]
+pub struct ChangedOnDisk {
+    pub path: PathBuf,
+}
+
+impl fmt::Display for ChangedOnDisk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} was changed by another ssh-utils since it was read",
+            self.path
+        )
+    }
+}
+
+impl std::error::Error for ChangedOnDisk {}
+
+fn fingerprint(content: &[u8]) -> [u8; 32] {
+    Sha256::digest(content).into()
+}
+
+fn remember(path: &Path, content: &[u8]) {
+    let mut known = KNOWN.lock().unwrap_or_else(|e| e.into_inner());
+    known.insert(path.to_path_buf(), fingerprint(content));
+}
+
+fn known(path: &Path) -> Option<[u8; 32]> {
+    let known = KNOWN.lock().unwrap_or_else(|e| e.into_inner());
+    known.get(path).copied()
+}
+
+/**
+    held while files of the config directory are checked and written,
+    so two instances don't write at the same time
+*/
+pub struct DirLock {
+    _file: File,
+}
+
+/**
+    wait for the advisory lock of the config directory, released on drop
+*/
+pub fn lock(dir: &Path) -> Result<DirLock> {
+    fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create config directory at {:?}", dir))?;
+    let path = dir.join(LOCK_FILE);
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .mode(0o600)
+        .open(&path)
+        .with_context(|| format!("Failed to open lock file {:?}", path))?;
+    flock(&file, libc::LOCK_EX).with_context(|| format!("Failed to lock {:?}", dir))?;
+    Ok(DirLock { _file: file })
+}
+
+/// flock(2), `File::lock` needs Rust 1.89, newer than our `rust-version`
+fn flock(file: &File, operation: libc::c_int) -> io::Result<()> {
+    // SAFETY: the descriptor stays open for the duration of the call
+    if unsafe { libc::flock(file.as_raw_fd(), operation) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/**
+    read a file and remember its content to notice later changes by others
+*/
+pub fn read(path: &Path) -> Result<Vec<u8>> {
+    let content = fs::read(path).with_context(|| format!("Unable to read {:?}", path))?;
+    remember(path, &content);
+    Ok(content)
+}
+
+/**
+    read a file and decode it, the content is only remembered if it decodes,
+    so a file this process can't make sense of is never replaced by a save
+*/
+pub fn read_with<T>(path: &Path, decode: impl FnOnce(&[u8]) -> Result<T>) -> Result<T> {
+    let content = fs::read(path).with_context(|| format!("Unable to read {:?}", path))?;
+    let value = decode(&content)?;
+    remember(path, &content);
+    Ok(value)
+}
+
+/**
+    replace a file unless someone else changed it since this process read it,
+    the config directory must be locked
+    new files get `mode`, existing ones keep theirs
+*/
+pub fn write(path: &Path, content: &[u8], mode: u32) -> Result<()> {
+    ensure_unchanged(path)?;
+    write_atomic(path, content, mode)?;
+    remember(path, content);
+    Ok(())
+}
+
+/**
+    like write, but the file ends up readable by the user only,
+    even if it already existed with a wider mode
+*/
+pub fn write_secret(path: &Path, content: &[u8]) -> Result<()> {
+    ensure_unchanged(path)?;
+    write_private(path, content)?;
+    remember(path, content);
+    Ok(())
+}
+
+/// fails with `ChangedOnDisk` if the file differs from what this process read
+fn ensure_unchanged(path: &Path) -> Result<()> {
+    if let Some(expected) = known(path) {
+        let current = match fs::read(path) {
+            Ok(current) => Some(fingerprint(&current)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e).with_context(|| format!("Unable to read {:?}", path)),
+        };
+        if current != Some(expected) {
+            return Err(ChangedOnDisk {
+                path: path.to_path_buf(),
+            }
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/**
+    write to a temporary file next to it, sync it and rename it over the file,
+    so a crash leaves either the old or the new content
+*/
+pub fn write_atomic(path: &Path, content: &[u8], mode: u32) -> Result<()> {
+    let mode = match fs::metadata(path) {
+        Ok(metadata) => metadata.permissions().mode() & 0o7777,
+        Err(_) => mode,
+    };
+    write_with_mode(path, content, mode)
+}
+
+/**
+    like write_atomic, but the file ends up readable by the user only,
+    even if it already existed with a wider mode
+*/
+pub fn write_private(path: &Path, content: &[u8]) -> Result<()> {
+    write_with_mode(path, content, 0o600)
+}
+
+fn write_with_mode(path: &Path, content: &[u8], mode: u32) -> Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let file_name = path
+        .file_name()
+        .with_context(|| format!("Invalid file path {:?}", path))?;
+    let mut temp_name = file_name.to_owned();
+    temp_name.push(format!(".tmp-{}", std::process::id()));
+    let temp_path = dir.join(temp_name);
+
+    let result = (|| -> Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .mode(mode)
+            .open(&temp_path)?;
+        // the umask may have narrowed the mode of the new file
+        file.set_permissions(fs::Permissions::from_mode(mode))?;
+        file.write_all(content)?;
+        file.sync_all()?;
+        fs::rename(&temp_path, path)?;
+        // make the rename itself durable
+        File::open(dir)?.sync_all()?;
+        Ok(())
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result.with_context(|| format!("Failed to write {:?}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_write_atomic() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("encrypted_data.bin");
+        write_atomic(&path, b"secret", 0o600).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"secret");
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+
+        // existing files keep their mode
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        write_atomic(&path, b"new", 0o600).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o640);
+        // no temporary file is left behind
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+
+        // an export with secrets over an existing file narrows its mode
+        let export_path = temp_dir.path().join("servers.json");
+        fs::write(&export_path, "[]").unwrap();
+        fs::set_permissions(&export_path, fs::Permissions::from_mode(0o644)).unwrap();
+        write_private(&export_path, b"[{\"password\":\"pw\"}]").unwrap();
+        assert_eq!(fs::metadata(&export_path).unwrap().permissions().mode() & 0o777, 0o600);
+        assert_eq!(fs::read(&export_path).unwrap(), b"[{\"password\":\"pw\"}]");
+    }
+
+    #[test]
+    fn test_write_detects_changes() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("config.toml");
+        fs::write(&path, "servers = []").unwrap();
+        let _lock = lock(temp_dir.path()).unwrap();
+
+        assert_eq!(read(&path).unwrap(), b"servers = []");
+        write(&path, b"version = 1", 0o644).unwrap();
+        // our own writes are not changes by others
+        write(&path, b"version = 1\nservers = []", 0o644).unwrap();
+
+        // another instance saved in between
+        fs::write(&path, "version = 1\n# other").unwrap();
+        let error = write(&path, b"version = 1", 0o644).unwrap_err();
+        assert!(error.downcast_ref::<ChangedOnDisk>().is_some());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "version = 1\n# other");
+
+        // after reading it again, saving works
+        read(&path).unwrap();
+        write(&path, b"version = 1", 0o644).unwrap();
+
+        // a change that can't be decoded, e.g. a vault under a new passphrase, stays refused
+        fs::write(&path, "version = 2").unwrap();
+        assert!(read_with::<()>(&path, |_| anyhow::bail!("wrong passphrase")).is_err());
+        let error = write(&path, b"version = 1", 0o644).unwrap_err();
+        assert!(error.downcast_ref::<ChangedOnDisk>().is_some());
+        assert_eq!(read_with(&path, |content| Ok(content.len())).unwrap(), 11);
+        write(&path, b"version = 1", 0o644).unwrap();
+
+        // files never read are written as they are
+        let other = temp_dir.path().join("new.toml");
+        write(&other, b"a", 0o644).unwrap();
+
+        // secrets narrow the mode of an existing file, and refuse changes by others too
+        fs::set_permissions(&other, fs::Permissions::from_mode(0o644)).unwrap();
+        write_secret(&other, b"b").unwrap();
+        assert_eq!(fs::metadata(&other).unwrap().permissions().mode() & 0o777, 0o600);
+        fs::write(&other, "c").unwrap();
+        let error = write_secret(&other, b"d").unwrap_err();
+        assert!(error.downcast_ref::<ChangedOnDisk>().is_some());
+    }
+
+    #[test]
+    fn test_lock() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path().join("ssh-utils");
+        let lock_dir = lock(&dir).unwrap();
+        let lock_file = File::open(dir.join(LOCK_FILE)).unwrap();
+        assert!(flock(&lock_file, libc::LOCK_EX | libc::LOCK_NB).is_err());
+        drop(lock_dir);
+        assert!(flock(&lock_file, libc::LOCK_EX | libc::LOCK_NB).is_ok());
+    }
+}
@@ -0,0 +1,311 @@
+use anyhow::{Context, Result};
+use std::{fs, io, sync::Mutex};
+
+use crate::config::app_config::Config;
+use crate::config::app_vault::{
+    self, current_kdf, encrypt_password, encrypt_vault, write_vault_file, Vault,
+};
+use crate::config::paths::ConfigPaths;
+use crate::config::{backup, storage};
+
+/// why the managed ssh config couldn't be rewritten after the last save
+static WARNING: Mutex<Option<String>> = Mutex::new(None);
+
+/**
+    a problem of the last save that didn't undo it, to show the user once
+*/
+pub fn take_warning() -> Option<String> {
+    WARNING.lock().unwrap_or_else(|e| e.into_inner()).take()
+}
+
+/**
+    change config and vault as one unit, both files are written or neither
+    if anything fails, the files and the values in memory are put back as they were
+    the managed ssh config is rewritten afterwards, a failure there is left to `take_warning`
+*/
+pub fn update<T>(
+    config: &mut Config,
+    vault: &mut Vault,
+    encryption_key: &[u8; 32],
+    change: impl FnOnce(&mut Config, &mut Vault) -> Result<T>,
+) -> Result<T> {
+    update_in(ConfigPaths::get()?, config, vault, encryption_key, change)
+}
+
+fn update_in<T>(
+    paths: &ConfigPaths,
+    config: &mut Config,
+    vault: &mut Vault,
+    encryption_key: &[u8; 32],
+    change: impl FnOnce(&mut Config, &mut Vault) -> Result<T>,
+) -> Result<T> {
+    let (saved_config, saved_vault) = (config.clone(), vault.clone());
+    let result = change(config, vault).and_then(|value| {
+        commit(paths, config, vault, encryption_key)?;
+        Ok(value)
+    });
+    if result.is_err() {
+        *config = saved_config;
+        *vault = saved_vault;
+        return result;
+    }
+    // only derived from the config, rewritten on the next save if this fails
+    if let Err(e) = config.write_managed_ssh_config() {
+        *WARNING.lock().unwrap_or_else(|e| e.into_inner()) = Some(format!("{:#}", e));
+    }
+    result
+}
+
+/**
+    write config.toml, then the vault
+    if the vault can't be written, config.toml gets its previous content back
+*/
+fn commit(paths: &ConfigPaths, config: &Config, vault: &Vault, encryption_key: &[u8; 32]) -> Result<()> {
+    let _lock = storage::lock(paths.dir())?;
+    // everything that can fail before touching a file
+    let config_str = config.to_toml()?;
+    let encrypt_data = encrypt_vault(vault, encryption_key, &current_kdf(paths)?)?;
+    backup::snapshot(paths)?;
+    let config_file = paths.config_file();
+    let previous = match fs::read(&config_file) {
+        Ok(previous) => Some(previous),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e).with_context(|| format!("Unable to read {:?}", config_file)),
+    };
+    storage::write(&config_file, config_str.as_bytes(), 0o644)?;
+    if let Err(e) = write_vault_file(paths, &encrypt_data) {
+        let restored = match previous {
+            Some(previous) => storage::write(&config_file, &previous, 0o644),
+            None => fs::remove_file(&config_file).map_err(Into::into),
+        };
+        if let Err(restore_error) = restored {
+            return Err(e.context(format!(
+                "Failed to put back {:?} as well: {}",
+                config_file, restore_error
+            )));
+        }
+        return Err(e);
+    }
+    Ok(())
+}
+
+/**
+    how config and vault disagree
+*/
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Consistency {
+    /// ids of servers without a password record
+    pub missing: Vec<String>,
+    /// ids of password records without a server
+    pub orphaned: Vec<String>,
+}
+
+impl Consistency {
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.orphaned.is_empty()
+    }
+
+    /**
+        one line per problem, servers by name
+    */
+    pub fn describe(&self, config: &Config) -> Vec<String> {
+        let name = |id: &String| {
+            config
+                .servers
+                .iter()
+                .find(|server| &server.id == id)
+                .map_or_else(|| id.clone(), |server| server.name.clone())
+        };
+        let missing = self
+            .missing
+            .iter()
+            .map(|id| format!("server {} has no password record", name(id)));
+        let orphaned = self
+            .orphaned
+            .iter()
+            .map(|id| format!("password record {} belongs to no server", id));
+        missing.chain(orphaned).collect()
+    }
+}
+
+/**
+    find servers lacking a vault record and vault records lacking a server
+*/
+pub fn check(config: &Config, vault: &Vault) -> Consistency {
+    let missing = config
+        .servers
+        .iter()
+        .filter(|server| !vault.servers.iter().any(|record| record.id == server.id))
+        .map(|server| server.id.clone())
+        .collect();
+    let orphaned = vault
+        .servers
+        .iter()
+        .filter(|record| !config.servers.iter().any(|server| server.id == record.id))
+        .map(|record| record.id.clone())
+        .collect();
+    Consistency { missing, orphaned }
+}
+
+/**
+    drop orphaned records and give servers without one an empty password
+*/
+pub fn repair(config: &mut Config, vault: &mut Vault, encryption_key: &[u8; 32]) -> Result<Consistency> {
+    update(config, vault, encryption_key, |config, vault| {
+        repair_in_memory(config, vault, encryption_key)
+    })
+}
+
+fn repair_in_memory(config: &Config, vault: &mut Vault, encryption_key: &[u8; 32]) -> Result<Consistency> {
+    let consistency = check(config, vault);
+    for id in &consistency.orphaned {
+        vault.delete_server(id);
+    }
+    for id in &consistency.missing {
+        let password = encrypt_password(id, "", encryption_key)?;
+        vault.set_server(app_vault::Server::new(id.clone(), password));
+    }
+    Ok(consistency)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::app_config::Server;
+    use crate::config::app_vault::{decrypt_password, decrypt_vault};
+    use crate::config::crypto::Kdf;
+    use tempfile::TempDir;
+
+    fn server(name: &str) -> Server {
+        Server::new(name.to_string(), "10.0.0.1".to_string(), "root".to_string(), "bash".to_string(), 22)
+    }
+
+    #[test]
+    fn test_update_rolls_back() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let paths = ConfigPaths::new(temp_dir.path().to_path_buf());
+        let kdf = Kdf::new(argon2::Variant::Argon2id, 1024, 1, 1);
+        let encryption_key = kdf.derive("pw")?;
+        let (mut config, mut vault) = (Config::default(), Vault::default());
+        fs::write(paths.vault_file(), encrypt_vault(&vault, &encryption_key, &kdf)?)?;
+
+        let web = server("web");
+        let password = encrypt_password(&web.id, "secret", &encryption_key)?;
+        update_in(&paths, &mut config, &mut vault, &encryption_key, |config, vault| {
+            config.add_server(web.clone());
+            vault.set_server(app_vault::Server::new(web.id.clone(), password));
+            Ok(())
+        })?;
+        let config_before = fs::read(paths.config_file())?;
+        let (saved, _) = decrypt_vault(&storage::read(&paths.vault_file())?, &encryption_key)?;
+        assert_eq!(saved, vault);
+
+        // a failing change saves nothing
+        let error = update_in(&paths, &mut config, &mut vault, &encryption_key, |config, vault| {
+            vault.delete_server(&web.id);
+            config.delete_server("unknown")
+        });
+        assert!(error.is_err());
+        assert_eq!(vault.servers.len(), 1);
+
+        // another instance changed the vault, the config is put back
+        let vault_before = fs::read(paths.vault_file())?;
+        let changed = encrypt_vault(&Vault::default(), &encryption_key, &kdf)?;
+        fs::write(paths.vault_file(), changed)?;
+        let db = server("db");
+        let error = update_in(&paths, &mut config, &mut vault, &encryption_key, |config, vault| {
+            config.add_server(db.clone());
+            vault.set_server(app_vault::Server::new(db.id.clone(), String::new()));
+            Ok(())
+        })
+        .unwrap_err();
+        assert!(error.downcast_ref::<storage::ChangedOnDisk>().is_some());
+        assert_eq!(fs::read(paths.config_file())?, config_before);
+        assert_eq!(config.servers.len(), 1);
+        assert_eq!(vault.servers.len(), 1);
+
+        // the put back config can still be saved
+        fs::write(paths.vault_file(), vault_before)?;
+        update_in(&paths, &mut config, &mut vault, &encryption_key, |config, _| {
+            config.delete_server(&web.id)
+        })?;
+        assert!(config.servers.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_keeps_change_if_managed_config_fails() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let paths = ConfigPaths::new(temp_dir.path().join("ssh-utils"));
+        let kdf = Kdf::new(argon2::Variant::Argon2id, 1024, 1, 1);
+        let encryption_key = kdf.derive("pw")?;
+        fs::create_dir(paths.dir())?;
+        fs::write(paths.vault_file(), encrypt_vault(&Vault::default(), &encryption_key, &kdf)?)?;
+        // edited by hand, ssh-utils refuses to overwrite it
+        let managed = temp_dir.path().join("ssh-utils.conf");
+        fs::write(&managed, "Host mine\n")?;
+        let mut config = Config {
+            managed_ssh_config: Some(managed.display().to_string()),
+            ..Config::default()
+        };
+        let mut vault = Vault::default();
+
+        let web = server("web");
+        update_in(&paths, &mut config, &mut vault, &encryption_key, |config, vault| {
+            config.add_server(web.clone());
+            vault.set_server(app_vault::Server::new(web.id.clone(), String::new()));
+            Ok(())
+        })?;
+        assert!(take_warning().is_some_and(|warning| warning.contains("refusing to overwrite")));
+        assert_eq!(config.servers.len(), 1);
+        update_in(&paths, &mut config, &mut vault, &encryption_key, |config, vault| {
+            vault.delete_server(&web.id);
+            config.delete_server(&web.id)
+        })?;
+        assert!(take_warning().is_some());
+        assert!(config.servers.is_empty() && vault.servers.is_empty());
+
+        // the files hold the change as well, and the next save doesn't undo it
+        assert!(!fs::read_to_string(paths.config_file())?.contains(&web.id));
+        let (saved, _) = decrypt_vault(&fs::read(paths.vault_file())?, &encryption_key)?;
+        assert!(saved.servers.is_empty());
+        update_in(&paths, &mut config, &mut vault, &encryption_key, |_, _| Ok(()))?;
+        assert!(config.servers.is_empty());
+        assert_eq!(fs::read_to_string(&managed)?, "Host mine\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_and_repair() -> Result<()> {
+        let encryption_key = Kdf::new(argon2::Variant::Argon2id, 1024, 1, 1).derive("pw")?;
+        let (web, db) = (server("web"), server("db"));
+        let mut config = Config::default();
+        config.add_server(web.clone());
+        config.add_server(db.clone());
+        let mut vault = Vault::default();
+        let password = encrypt_password(&web.id, "secret", &encryption_key)?;
+        vault.set_server(app_vault::Server::new(web.id.clone(), password));
+        vault.set_server(app_vault::Server::new("gone".to_string(), String::new()));
+
+        let consistency = check(&config, &vault);
+        assert!(!consistency.is_ok());
+        assert_eq!(consistency.missing, vec![db.id.clone()]);
+        assert_eq!(consistency.orphaned, vec!["gone".to_string()]);
+        assert_eq!(
+            consistency.describe(&config),
+            vec![
+                "server db has no password record".to_string(),
+                "password record gone belongs to no server".to_string()
+            ]
+        );
+
+        assert_eq!(repair_in_memory(&config, &mut vault, &encryption_key)?, consistency);
+        assert!(check(&config, &vault).is_ok());
+        let records: Vec<&str> = vault.servers.iter().map(|s| s.id.as_str()).collect();
+        assert_eq!(records, vec![web.id.as_str(), db.id.as_str()]);
+        // existing passwords are kept, the added one is empty
+        assert_eq!(decrypt_password(&web.id, &vault.servers[0].password, &encryption_key)?, "secret");
+        assert_eq!(decrypt_password(&db.id, &vault.servers[1].password, &encryption_key)?, "");
+        Ok(())
+    }
+}
\ No newline at end of file
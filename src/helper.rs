@@ -1,31 +1,57 @@
-use std::path::PathBuf;
 use anyhow::{Context, Result};
 use ratatui::layout::{Constraint, Direction, Layout, Rect};
 
 use crate::config::app_vault::EncryptionKey;
 
-pub static CONFIG_FILE: &str = "config.toml";
-pub static ENCRYPTED_FILE: &str = "encrypted_data.bin";
-
-pub fn get_file_path(file_name: &str) -> Result<String> {
-    let mut config_dir: PathBuf = if cfg!(debug_assertions) {
-        ".".into() // current running dir
-    } else {
-        dirs::home_dir().context("Unable to reach user's home directory.")?
-    };
-
-    config_dir.push(".config/ssh-utils");
-    config_dir.push(file_name);
-    let file_path = config_dir.to_str().context("Failed to convert path to string")?.to_string();
-    Ok(file_path)
-}
-
 pub fn convert_to_array(vec: &EncryptionKey) -> Result<[u8; 32]> {
     let slice = vec.as_slice();
     let array: &[u8; 32] = slice.try_into().context("Failed to convert Vec<u8> to [u8; 32]")?;
     Ok(*array)
 }
 
+/**
+    year, month, day, hour, minute and second of a unix timestamp in UTC
+*/
+fn date_time(timestamp: u64) -> (i64, i64, i64, u64, u64, u64) {
+    let days = (timestamp / 86400) as i64;
+    let seconds = timestamp % 86400;
+
+    // convert days since 1970-01-01 to a civil date (Howard Hinnant's algorithm)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    (year, month, day, seconds / 3600, seconds % 3600 / 60, seconds % 60)
+}
+
+/**
+    format a unix timestamp as "YYYY-MM-DD HH:MM:SS UTC"
+*/
+pub fn format_timestamp(timestamp: u64) -> String {
+    let (year, month, day, hour, minute, second) = date_time(timestamp);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year, month, day, hour, minute, second
+    )
+}
+
+/**
+    format a unix timestamp as "YYYYMMDD-HHMMSS" in UTC, for file names
+*/
+pub fn format_compact_timestamp(timestamp: u64) -> String {
+    let (year, month, day, hour, minute, second) = date_time(timestamp);
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year, month, day, hour, minute, second
+    )
+}
+
 /// helper function to create a centered rect using up certain percentage of the available rect `r`
 pub fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
     let popup_layout = Layout::default()
@@ -51,4 +77,17 @@ pub fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
             .as_ref(),
         )
         .split(popup_layout[1])[1]
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_timestamp(951782400), "2000-02-29 00:00:00 UTC");
+        assert_eq!(format_timestamp(1792322096), "2026-10-18 11:14:56 UTC");
+        assert_eq!(format_compact_timestamp(1792322096), "20261018-111456");
+    }
+}
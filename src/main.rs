@@ -7,11 +7,25 @@ mod widgets;
 
 use anyhow::{Context, Result};
 use app::App;
-use clap::Parser;
+use clap::{Args, Parser, Subcommand};
 use config::{
-    app_config,
-    app_vault::{check_if_vault_bin_exists, decrypt_vault, EncryptionKey, Vault},
-    crypto::derive_key_from_password,
+    ansible,
+    app_config::{self, Config, Server},
+    backup,
+    filter::ServerFilter,
+    import::{local_user, save_imported, ImportReport},
+    inventory::{self, Format},
+    migrate,
+    paths::ConfigPaths,
+    ssh_config,
+    storage,
+    store,
+    app_vault::{
+        check_if_vault_bin_exists, read_vault, save_with_new_key, vault_header, EncryptionKey,
+        Vault, VaultError, FILE_FORMAT,
+    },
+    crypto::{derive_key_from_password, Kdf},
+    passphrase::PassphraseSource,
 };
 use crossterm::{
     cursor::{RestorePosition, SavePosition},
@@ -19,22 +33,543 @@ use crossterm::{
     style::{Color, ResetColor, SetForegroundColor},
     terminal::{disable_raw_mode, enable_raw_mode, Clear, ClearType},
 };
-use helper::{get_file_path, ENCRYPTED_FILE};
+use helper::convert_to_array;
 use ratatui::{backend::CrosstermBackend, Terminal, TerminalOptions, Viewport};
+use ssh::identity::{display_path, Identity};
+use ssh::key_file::{
+    default_comment, fingerprint, generate_key, randomart, read_private_key, save_generated_key,
+    save_key_files, write_private_key, KeySpec,
+};
 use std::io::{stdout, Write};
 use std::{
-    fs::File,
-    io::{self, Read, Stdout},
-    panic::{self, PanicInfo},
+    fs,
+    io::{self, IsTerminal, Stdout},
+    panic::{self, PanicHookInfo},
+    path::PathBuf,
 };
 use zeroize::Zeroize;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
-    /// remove all of the config file
+    /// remove all of the config files, a tar archive of them is kept next to the directory
     #[arg(short, long)]
     flush: bool,
+    /// keep config and vault in this directory instead of ~/.config/ssh-utils,
+    /// also read from $SSH_UTILS_CONFIG_DIR
+    #[arg(long, global = true, value_name = "DIR")]
+    config_dir: Option<PathBuf>,
+    /// read the vault passphrase from this file descriptor instead of asking, e.g. 3 with 3<file,
+    /// $SSH_UTILS_PASSPHRASE_FILE and passphrase_command in config.toml work too
+    #[arg(long, global = true, value_name = "FD")]
+    passphrase_fd: Option<i32>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// manage private keys embedded in the vault
+    Key {
+        #[command(subcommand)]
+        action: KeyAction,
+    },
+    /// generate a new key pair
+    Keygen {
+        /// ed25519, ecdsa or rsa
+        #[arg(short = 't', long = "type", default_value = "ed25519")]
+        key_type: String,
+        /// key size: 256, 384 or 521 for ecdsa, 2048 or more for rsa
+        #[arg(short, long)]
+        bits: Option<usize>,
+        /// where to save the key, defaults to ~/.ssh/id_<type>; use vault:<name> to keep it in the vault
+        #[arg(short, long)]
+        file: Option<String>,
+        /// comment of the key, defaults to user@host
+        #[arg(short = 'C', long)]
+        comment: Option<String>,
+    },
+    /// add servers described by another tool
+    Import {
+        /// show what would change without saving
+        #[arg(long, global = true)]
+        dry_run: bool,
+        #[command(subcommand)]
+        source: ImportSource,
+    },
+    /// describe the saved servers for another tool
+    Export {
+        /// only the servers in this group and its subgroups
+        #[arg(long, global = true)]
+        group: Option<String>,
+        /// only the servers matching a query like "tag:prod db"
+        #[arg(long, global = true)]
+        filter: Option<String>,
+        #[command(subcommand)]
+        target: ExportTarget,
+    },
+    /// print the saved servers, only those matching a query like "tag:prod group:eu db" if given
+    List { query: Vec<String> },
+    /// list or restore the backups taken before each save
+    Backup {
+        #[command(subcommand)]
+        action: BackupAction,
+    },
+    /// change the passphrase of the vault, or how hard it is to guess
+    Passwd {
+        /// Argon2 memory in MiB, keeps the current one if not given
+        #[arg(long, value_name = "MIB")]
+        kdf_memory: Option<u32>,
+        /// Argon2 iterations, keeps the current ones if not given
+        #[arg(long, value_name = "N")]
+        kdf_iterations: Option<u32>,
+        /// Argon2 lanes, keeps the current ones if not given
+        #[arg(long, value_name = "N")]
+        kdf_lanes: Option<u32>,
+    },
+    /// find servers without a password record and records without a server
+    Check {
+        /// repair without asking
+        #[arg(long)]
+        repair: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum ExportTarget {
+    /// OpenSSH client config, for ssh, scp, rsync or VS Code Remote
+    SshConfig {
+        /// write to a file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// keep ~/.ssh/ssh-utils.conf up to date and include it from ~/.ssh/config
+        #[arg(long, conflicts_with_all = ["output", "no_sync"])]
+        sync: bool,
+        /// stop updating ~/.ssh/ssh-utils.conf
+        #[arg(long, conflicts_with = "output")]
+        no_sync: bool,
+    },
+    /// JSON list of servers
+    Json(InventoryExport),
+    /// YAML list of servers
+    Yaml(InventoryExport),
+    /// CSV with a header row
+    Csv(InventoryExport),
+    /// Ansible inventory with a group per tag
+    Ansible {
+        /// write to a file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// YAML instead of INI
+        #[arg(long)]
+        yaml: bool,
+    },
+}
+
+#[derive(Args)]
+struct InventoryExport {
+    /// write to a file instead of stdout
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// include the passwords, asks for the vault passphrase
+    #[arg(long)]
+    with_secrets: bool,
+}
+
+impl ExportTarget {
+    fn with_secrets(&self) -> bool {
+        match self {
+            ExportTarget::SshConfig { .. } | ExportTarget::Ansible { .. } => false,
+            ExportTarget::Json(export) | ExportTarget::Yaml(export) | ExportTarget::Csv(export) => {
+                export.with_secrets
+            }
+        }
+    }
+}
+
+/**
+    write an export to the file, or stdout
+    a file holding secrets is only readable by the user
+*/
+fn write_export(output: Option<PathBuf>, content: &str, secret: bool) -> Result<()> {
+    let Some(path) = output else {
+        print!("{}", content);
+        return Ok(());
+    };
+    if secret {
+        // a file that already exists would keep its wider mode
+        return storage::write_private(&path, content.as_bytes());
+    }
+    fs::write(&path, content).with_context(|| format!("Failed to write {:?}", path))
+}
+
+/**
+    vault -> the unlocked vault, to export passwords
+*/
+fn run_export(
+    target: ExportTarget,
+    group: Option<String>,
+    filter: Option<String>,
+    vault: Option<(&Vault, &[u8; 32])>,
+) -> Result<()> {
+    let mut config = app_config::read_config()?;
+    let server_filter = ServerFilter::parse(filter.as_deref().unwrap_or_default());
+    let servers: Vec<Server> = config
+        .servers
+        .iter()
+        .filter(|server| group.as_ref().is_none_or(|group| server.in_group(group)))
+        .filter(|server| server_filter.matches(server))
+        .cloned()
+        .collect();
+    let (format, export) = match target {
+        ExportTarget::SshConfig {
+            output,
+            sync,
+            no_sync,
+        } => {
+            if (sync || no_sync) && (group.is_some() || filter.is_some()) {
+                anyhow::bail!(
+                    "The managed file always has every server, --group and --filter can't be used"
+                );
+            }
+            if sync {
+                config.managed_ssh_config = Some(ssh_config::MANAGED_CONFIG.to_string());
+                config.save()?;
+                let user_config = ssh_config::user_config_path()
+                    .context("Unable to reach user's home directory.")?;
+                if ssh_config::ensure_included(&user_config, ssh_config::MANAGED_CONFIG)? {
+                    println!("Added \"Include {}\" to {}", ssh_config::MANAGED_CONFIG, display_path(&user_config));
+                }
+                println!("{} is now updated on every change", ssh_config::MANAGED_CONFIG);
+            } else if no_sync {
+                if let Some(managed) = config.managed_ssh_config.take() {
+                    config.save()?;
+                    println!("{} is no longer updated, remove it and its Include line if unused", managed);
+                }
+            } else {
+                write_export(output, &ssh_config::export_servers(&servers), false)?;
+            }
+            return Ok(());
+        }
+        ExportTarget::Ansible { output, yaml } => {
+            let inventory = if yaml {
+                ansible::export_yaml(&servers)?
+            } else {
+                ansible::export_ini(&servers)
+            };
+            return write_export(output, &inventory, false);
+        }
+        ExportTarget::Json(export) => (Format::Json, export),
+        ExportTarget::Yaml(export) => (Format::Yaml, export),
+        ExportTarget::Csv(export) => (Format::Csv, export),
+    };
+    let secrets = vault.filter(|_| export.with_secrets);
+    let records = inventory::export_records(&servers, secrets)?;
+    let content = inventory::format_records(format, &records, secrets.is_some())?;
+    write_export(export.output, &content, secrets.is_some())
+}
+
+/// print the servers matching the query, with their tags and notes
+fn list_servers(query: &str) -> Result<()> {
+    let config = app_config::read_config()?;
+    let filter = ServerFilter::parse(query);
+    for server in config.servers.iter().filter(|server| filter.matches(server)) {
+        let mut line = format!(
+            "{:<20} {}@{}:{}",
+            server.name, server.user, server.ip, server.port
+        );
+        if !server.group.is_empty() {
+            line.push_str(&format!("  [{}]", server.group));
+        }
+        if !server.tags.is_empty() {
+            line.push_str(&format!("  tags: {}", server.tags.join(", ")));
+        }
+        println!("{}", line);
+        for note in server.notes.lines() {
+            println!("    {}", note);
+        }
+    }
+    Ok(())
+}
+
+#[derive(Subcommand)]
+enum ImportSource {
+    /// OpenSSH client config, ~/.ssh/config by default
+    SshConfig { path: Option<PathBuf> },
+    /// JSON list of servers, merged by id or name
+    Json { path: PathBuf },
+    /// YAML list of servers, merged by id or name
+    Yaml { path: PathBuf },
+    /// CSV with a header row, merged by id or name
+    Csv { path: PathBuf },
+    /// Ansible inventory, INI or YAML (.yml, .yaml), merged by name
+    Ansible { path: PathBuf },
+}
+
+fn run_import(
+    source: ImportSource,
+    dry_run: bool,
+    config: &mut Config,
+    vault: &mut Vault,
+    encryption_key: &[u8; 32],
+) -> Result<()> {
+    let (format, path) = match source {
+        ImportSource::SshConfig { path } => {
+            let path = match path {
+                Some(path) => path,
+                None => ssh_config::user_config_path()
+                    .context("Unable to reach user's home directory.")?,
+            };
+            let ssh_config = ssh_config::read_from_path(&path)?;
+            let report = ssh_config::import_servers(&ssh_config, &config.servers, &local_user());
+            return finish_import(report, dry_run, config, vault, encryption_key);
+        }
+        ImportSource::Ansible { path } => {
+            let content =
+                fs::read_to_string(&path).with_context(|| format!("Unable to read {:?}", path))?;
+            let is_yaml = path
+                .extension()
+                .is_some_and(|extension| extension == "yml" || extension == "yaml");
+            let inventory = if is_yaml {
+                ansible::parse_yaml(&content)?
+            } else {
+                ansible::parse_ini(&content)
+            };
+            let report = inventory.import(&config.servers, &local_user());
+            return finish_import(report, dry_run, config, vault, encryption_key);
+        }
+        ImportSource::Json { path } => (Format::Json, path),
+        ImportSource::Yaml { path } => (Format::Yaml, path),
+        ImportSource::Csv { path } => (Format::Csv, path),
+    };
+    let content =
+        fs::read_to_string(&path).with_context(|| format!("Unable to read {:?}", path))?;
+    let records = inventory::parse_records(format, &content)?;
+    let report = inventory::import_records(records, &config.servers, &local_user());
+    finish_import(report, dry_run, config, vault, encryption_key)
+}
+
+fn finish_import(
+    report: ImportReport,
+    dry_run: bool,
+    config: &mut Config,
+    vault: &mut Vault,
+    encryption_key: &[u8; 32],
+) -> Result<()> {
+    println!("{}", report.summary());
+    if dry_run {
+        println!("Dry run, nothing was saved");
+        return Ok(());
+    }
+    save_imported(config, vault, report, encryption_key)
+}
+
+#[derive(Subcommand)]
+enum BackupAction {
+    /// list the backups, oldest first
+    List,
+    /// put config and vault back as they were, the current files are backed up first
+    Restore {
+        /// name of the backup, or the start of it
+        name: String,
+    },
+}
+
+fn run_backup_command(action: BackupAction) -> Result<()> {
+    let paths = ConfigPaths::get()?;
+    match action {
+        BackupAction::List => {
+            for backup in backup::list(paths)? {
+                match backup.server_count(paths) {
+                    Some(count) => println!("{}  {} servers", backup.name, count),
+                    None => println!("{}", backup.name),
+                }
+            }
+        }
+        BackupAction::Restore { name } => {
+            let _lock = storage::lock(paths.dir())?;
+            let backup = backup::find(paths, &name)?;
+            let kept = backup::restore(paths, &backup, true)?;
+            println!("Restored backup {}.", backup.name);
+            if let Some(kept) = kept {
+                println!("The replaced files are kept as backup {}.", kept.name);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[derive(Subcommand)]
+enum KeyAction {
+    /// copy a private key file into the vault
+    Import {
+        path: PathBuf,
+        /// name to refer to the key, defaults to the file name
+        #[arg(short, long)]
+        name: Option<String>,
+    },
+    /// write an embedded key back out in OpenSSH format
+    Export { name: String, path: PathBuf },
+    /// list the embedded keys
+    List,
+}
+
+fn run_key_command(action: KeyAction, vault: &mut Vault, encryption_key: &[u8; 32]) -> Result<()> {
+    match action {
+        KeyAction::Import { path, name } => {
+            let name = match name {
+                Some(name) => name,
+                None => path
+                    .file_name()
+                    .context("Unable to name the key after its path, use --name")?
+                    .to_string_lossy()
+                    .into_owned(),
+            };
+            let private_key = read_private_key(&path, || {
+                prompt_passphrase(&format!("Enter passphrase of {}: ", path.display()))
+            })?;
+            vault.add_key(&name, &private_key, encryption_key)?;
+            println!("Key {} imported, use \"vault:{}\" as the server's identity.", name, name);
+        }
+        KeyAction::Export { name, path } => {
+            let private_key = zeroize::Zeroizing::new(vault.private_key(&name, encryption_key)?);
+            write_private_key(&path, &private_key)?;
+            println!("Key {} exported to {}.", name, path.display());
+        }
+        KeyAction::List => {
+            for key in &vault.keys {
+                println!("{}", key.name);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn run_keygen(
+    spec: KeySpec,
+    file: Option<String>,
+    comment: Option<String>,
+    unlocked: Option<(&mut Vault, &[u8; 32])>,
+) -> Result<()> {
+    let target = match file {
+        Some(file) => Identity::parse(&file),
+        None => Identity::File(
+            dirs::home_dir()
+                .context("Unable to reach user's home directory.")?
+                .join(".ssh")
+                .join(spec.default_file_name()),
+        ),
+    };
+    if let Identity::File(path) = &target {
+        if path.exists() {
+            return Err(anyhow::anyhow!("{} already exists", display_path(path)));
+        }
+    }
+    let key = generate_key(spec, &comment.unwrap_or_else(default_comment))?;
+    let passphrase = match target {
+        Identity::File(_) => {
+            let passphrase = zeroize::Zeroizing::new(prompt_passphrase(
+                "Enter passphrase (empty for no passphrase): ",
+            )?);
+            let confirm_passphrase =
+                zeroize::Zeroizing::new(prompt_passphrase("Enter same passphrase again: ")?);
+            if passphrase != confirm_passphrase {
+                return Err(anyhow::anyhow!("Passphrases do not match."));
+            }
+            passphrase
+        }
+        // protected by the master passphrase
+        Identity::Vault(_) => zeroize::Zeroizing::new(String::new()),
+    };
+    match &target {
+        Identity::File(path) => save_key_files(path, &key, &passphrase)?,
+        Identity::Vault(_) => {
+            let (vault, encryption_key) = unlocked.context(LOCKED)?;
+            save_generated_key(&target, &key, &passphrase, vault, encryption_key)?;
+        }
+    }
+    println!("Your key has been saved in {}", target);
+    println!("The key fingerprint is:");
+    println!("{} {}", fingerprint(key.public_key()), key.comment());
+    println!("The key's randomart image is:");
+    println!("{}", randomart(key.public_key()));
+    Ok(())
+}
+
+/**
+    print how config and vault disagree and offer to repair it
+    repair -> don't ask
+    returns whether they agree now
+*/
+fn check_store(
+    config: &mut Config,
+    vault: &mut Vault,
+    encryption_key: &[u8; 32],
+    repair: bool,
+) -> Result<bool> {
+    let consistency = store::check(config, vault);
+    if consistency.is_ok() {
+        return Ok(true);
+    }
+    println!("The server list and the vault don't match:");
+    for problem in consistency.describe(config) {
+        println!("  {}", problem);
+    }
+    if !repair {
+        print!("Drop the stray records and give the servers an empty password? (y/N): ");
+        io::stdout().flush()?;
+        let mut input = String::new();
+        io::stdin().read_line(&mut input)?;
+        if input.trim().to_lowercase() != "y" {
+            return Ok(false);
+        }
+    }
+    store::repair(config, vault, encryption_key)?;
+    println!("Repaired.");
+    Ok(true)
+}
+
+/**
+    ask for a new passphrase and encrypt the vault and every secret in it with it
+    kdf -> the new key derivation, with a new salt
+*/
+fn change_passphrase(vault: &Vault, encryption_key: &[u8; 32], kdf: Kdf) -> Result<()> {
+    kdf.validate()?;
+    let passphrase = zeroize::Zeroizing::new(prompt_passphrase(
+        "Enter new passphrase (empty for no passphrase): ",
+    )?);
+    let confirm_passphrase =
+        zeroize::Zeroizing::new(prompt_passphrase("Enter the same passphrase again: ")?);
+    if passphrase != confirm_passphrase {
+        return Err(anyhow::anyhow!("Passphrases do not match."));
+    }
+    let new_key = kdf.derive(passphrase.as_str())?;
+    let new_vault = vault.reencrypt(encryption_key, &new_key)?;
+    save_with_new_key(&new_vault, &new_key, &kdf)?;
+    println!("The passphrase has been changed, the key is derived with {}.", kdf);
+
+    // the old copies still open with the old passphrase, which may have leaked
+    let paths = ConfigPaths::get()?;
+    let _lock = storage::lock(paths.dir())?;
+    let copies = backup::vault_copies(paths)?.len();
+    if copies == 0 {
+        return Ok(());
+    }
+    println!("{} backups still open with the old passphrase.", copies);
+    // without a terminal to ask, they go
+    if io::stdin().is_terminal() {
+        print!("Delete them? (Y/n): ");
+        io::stdout().flush()?;
+        let mut input = String::new();
+        io::stdin().read_line(&mut input)?;
+        if input.trim().eq_ignore_ascii_case("n") {
+            println!("The backups are kept.");
+            return Ok(());
+        }
+    }
+    let removed = backup::remove_vault_copies(paths)?;
+    println!("Deleted {} backups.", removed);
+    Ok(())
 }
 
 fn flush_config() -> Result<()> {
@@ -50,10 +585,12 @@ fn flush_config() -> Result<()> {
     io::stdin().read_line(&mut input)?;
 
     if input.trim().to_lowercase() == "y" {
-        let mut path = dirs::home_dir().context("Unable to reach user's home directory.")?;
-        path.push(".config/ssh-utils/");
-        std::fs::remove_dir_all(&path).context("Failed to delete config directory")?;
+        let paths = ConfigPaths::get()?;
+        // a last copy to restore from, in case it was a mistake
+        let archive = backup::archive(paths)?;
+        std::fs::remove_dir_all(paths.dir()).context("Failed to delete config directory")?;
         println!("Config files have been successfully deleted.");
+        println!("A copy is kept at {:?}, extract it with tar -xf to undo.", archive);
     } else {
         println!("Operation cancelled.");
     }
@@ -63,6 +600,7 @@ fn flush_config() -> Result<()> {
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
+    ConfigPaths::init(cli.config_dir)?;
     if cli.flush {
         flush_config()?;
         std::process::exit(0);
@@ -70,9 +608,41 @@ async fn main() -> Result<()> {
     // Setup panic hook
     panic::set_hook(Box::new(panic_hook));
     app_config::ensure_config_exists()?;
+    // on stderr, so exports written to stdout stay clean
+    if let Some(backup) = app_config::upgrade_config()? {
+        eprintln!(
+            "Upgraded the config file to format {}, the original is kept at {:?}",
+            migrate::CONFIG.version(),
+            backup
+        );
+    }
+    let command = match cli.command {
+        Some(command) if !needs_vault(&command) => return run_command(command, None),
+        command => command,
+    };
+    let source = PassphraseSource::resolve(
+        cli.passphrase_fd,
+        app_config::read_config()?.passphrase_command,
+    );
+    // the new passphrase is only ever typed, a script would hang on the prompt
+    if matches!(command, Some(Command::Passwd { .. })) && !source.is_interactive() {
+        return Err(anyhow::anyhow!(
+            "passwd asks for the new passphrase on the terminal, it can't be used with {}",
+            source
+        ));
+    }
     let mut encryption_key: EncryptionKey = Vec::with_capacity(32);
-    let mut vault = init_vault(&mut encryption_key)?;
+    let mut vault = init_vault(&mut encryption_key, &source)?;
+    if let Some(command) = command {
+        let encryption_key = convert_to_array(&encryption_key)?;
+        let result = run_command(command, Some((&mut vault, &encryption_key)));
+        if let Some(warning) = store::take_warning() {
+            eprintln!("{}", warning);
+        }
+        return result;
+    }
     let mut config = app_config::read_config()?;
+    check_store(&mut config, &mut vault, &convert_to_array(&encryption_key)?, false)?;
     let app = App::new(&mut config, &mut vault, encryption_key)?;
     let mut terminal = create_terminal()?;
     setup_terminal(&mut terminal)?;
@@ -81,6 +651,84 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+/// error of a command run without the vault it needs
+const LOCKED: &str = "The vault has to be unlocked for this command";
+
+/**
+    whether a subcommand reads or writes secrets, so the vault is unlocked first
+    exports without secrets don't need the passphrase
+*/
+fn needs_vault(command: &Command) -> bool {
+    match command {
+        Command::List { .. } | Command::Backup { .. } => false,
+        Command::Export { target, .. } => target.with_secrets(),
+        // keys for ~/.ssh are generated before there is a vault, e.g. on the first run
+        Command::Keygen { file, .. } => {
+            matches!(file.as_deref().map(Identity::parse), Some(Identity::Vault(_)))
+        }
+        Command::Key { .. }
+        | Command::Import { .. }
+        | Command::Passwd { .. }
+        | Command::Check { .. } => true,
+    }
+}
+
+/**
+    run a subcommand, `unlocked` is the vault when `needs_vault` asks for it
+*/
+fn run_command(command: Command, unlocked: Option<(&mut Vault, &[u8; 32])>) -> Result<()> {
+    match command {
+        Command::List { query } => list_servers(&query.join(" ")),
+        Command::Backup { action } => run_backup_command(action),
+        Command::Key { action } => {
+            let (vault, encryption_key) = unlocked.context(LOCKED)?;
+            run_key_command(action, vault, encryption_key)
+        }
+        Command::Import { dry_run, source } => {
+            let (vault, encryption_key) = unlocked.context(LOCKED)?;
+            let mut config = app_config::read_config()?;
+            run_import(source, dry_run, &mut config, vault, encryption_key)
+        }
+        Command::Export {
+            group,
+            filter,
+            target,
+        } => {
+            let unlocked = unlocked.map(|(vault, encryption_key)| (&*vault, encryption_key));
+            run_export(target, group, filter, unlocked)
+        }
+        Command::Passwd {
+            kdf_memory,
+            kdf_iterations,
+            kdf_lanes,
+        } => {
+            let (vault, encryption_key) = unlocked.context(LOCKED)?;
+            let current = vault_header()?.map(|(kdf, _)| kdf).unwrap_or_default();
+            let kdf = Kdf {
+                memory: kdf_memory.map_or(current.memory, |mib| mib.saturating_mul(1024)),
+                iterations: kdf_iterations.unwrap_or(current.iterations),
+                lanes: kdf_lanes.unwrap_or(current.lanes),
+                ..current.renew()
+            };
+            change_passphrase(vault, encryption_key, kdf)
+        }
+        Command::Check { repair } => {
+            let (vault, encryption_key) = unlocked.context(LOCKED)?;
+            let mut config = app_config::read_config()?;
+            if check_store(&mut config, vault, encryption_key, repair)? {
+                println!("The server list and the vault match.");
+            }
+            Ok(())
+        }
+        Command::Keygen {
+            key_type,
+            bits,
+            file,
+            comment,
+        } => run_keygen(KeySpec::new(&key_type, bits)?, file, comment, unlocked),
+    }
+}
+
 fn prompt_passphrase(prompt: &str) -> Result<String, anyhow::Error> {
     let prompt_password = |prompt: &str| {
         rpassword::prompt_password(prompt).or_else(|_| {
@@ -99,8 +747,26 @@ fn prompt_passphrase(prompt: &str) -> Result<String, anyhow::Error> {
     Ok(passphrase)
 }
 
-fn init_vault(encryption_key: &mut EncryptionKey) -> Result<Vault, anyhow::Error> {
+/**
+    point to the backups if the vault file is broken whatever the passphrase
+*/
+fn explain_vault_error(e: anyhow::Error) -> anyhow::Error {
+    match e.downcast_ref::<VaultError>() {
+        Some(error) if error.is_corrupt() => e.context(
+            "The vault file is damaged, \"ssh-utils backup list\" shows the backups to restore",
+        ),
+        _ => e,
+    }
+}
+
+/**
+    unlock the vault, or create it on the first run
+    only a passphrase from the terminal is asked for again if it is wrong
+*/
+fn init_vault(encryption_key: &mut EncryptionKey, source: &PassphraseSource) -> Result<Vault, anyhow::Error> {
     if check_if_vault_bin_exists()? {
+        // None -> format 0, salted with the passphrase itself
+        let header = vault_header().map_err(explain_vault_error)?;
         for attempt in 1..=3 {
             let prompt_message = if attempt == 1 {
                 "please enter your passphrase: ".to_string()
@@ -108,15 +774,62 @@ fn init_vault(encryption_key: &mut EncryptionKey) -> Result<Vault, anyhow::Error
                 format!("Enter passphrase (Attempt {} of 3): ", attempt)
             };
 
-            let mut passphrase = prompt_passphrase(&prompt_message)?;
-            let try_encryption_key: [u8; 32] = derive_key_from_password(passphrase.as_str())?;
-            let mut vault_file = File::open(get_file_path(ENCRYPTED_FILE)?)?;
-            let mut vault_buf: Vec<u8> = Vec::new();
-            vault_file.read_to_end(&mut vault_buf)?;
+            let mut passphrase = match source.read()? {
+                Some(passphrase) => passphrase,
+                None => prompt_passphrase(&prompt_message)?,
+            };
+            let try_encryption_key: [u8; 32] = match &header {
+                Some((kdf, _)) => kdf.derive(passphrase.as_str())?,
+                None => derive_key_from_password(passphrase.as_str())?,
+            };
 
             // hmac challenge.
-            match decrypt_vault(&vault_buf, &try_encryption_key) {
-                Ok(vault) => {
+            match read_vault(&try_encryption_key) {
+                Ok((vault, version)) => {
+                    let (vault, try_encryption_key) = match &header {
+                        None => {
+                            // keep the original, then give it a random salt and a header
+                            let backup =
+                                migrate::backup_as(&ConfigPaths::get()?.vault_file(), "format0")?;
+                            let new_kdf = Kdf::default();
+                            let new_key = new_kdf.derive(passphrase.as_str())?;
+                            let vault = vault.reencrypt(&try_encryption_key, &new_key)?;
+                            save_with_new_key(&vault, &new_key, &new_kdf)?;
+                            println!(
+                                "Upgraded the vault to a random salt and {}, the original is kept at {:?}",
+                                new_kdf, backup
+                            );
+                            (vault, new_key)
+                        }
+                        Some((kdf, format)) if *format < FILE_FORMAT || vault.has_legacy_secrets() => {
+                            // keep the original, then encrypt it and every secret in it with AES-256-GCM
+                            let backup = migrate::backup_as(
+                                &ConfigPaths::get()?.vault_file(),
+                                &format!("format{}", format),
+                            )?;
+                            let vault = vault.reencrypt(&try_encryption_key, &try_encryption_key)?;
+                            save_with_new_key(&vault, &try_encryption_key, kdf)?;
+                            println!(
+                                "Upgraded the vault to format {} (AES-256-GCM), the original is kept at {:?}",
+                                FILE_FORMAT, backup
+                            );
+                            (vault, try_encryption_key)
+                        }
+                        Some(_) => {
+                            if version < migrate::VAULT.version() {
+                                // keep the original, then save it in the current format
+                                let backup =
+                                    migrate::backup(&ConfigPaths::get()?.vault_file(), version)?;
+                                vault.save(&try_encryption_key)?;
+                                println!(
+                                    "Upgraded the vault to format {}, the original is kept at {:?}",
+                                    migrate::VAULT.version(),
+                                    backup
+                                );
+                            }
+                            (vault, try_encryption_key)
+                        }
+                    };
                     encryption_key.extend_from_slice(&try_encryption_key);
                     // due to the drop!() is not really clear the Passphrases' data in memory.
                     // so we use zeroize to clear passphrase in memory.
@@ -125,34 +838,59 @@ fn init_vault(encryption_key: &mut EncryptionKey) -> Result<Vault, anyhow::Error
                 }
                 Err(e) => {
                     passphrase.zeroize();
-                    if let Some(_) = e.downcast_ref::<hmac::digest::MacError>() {
+                    if e.downcast_ref::<VaultError>() == Some(&VaultError::BadMac) {
+                        if !source.is_interactive() {
+                            return Err(anyhow::anyhow!(
+                                "Incorrect passphrase from {}, or the vault file was changed",
+                                source
+                            ));
+                        }
                         println!("Incorrect passphrase. Please try again.");
                         if attempt == 3 {
+                            // a changed file looks the same as a wrong passphrase
+                            println!(
+                                "If the passphrase is right, the vault file was changed, \
+                                 \"ssh-utils backup list\" shows the backups to restore."
+                            );
                             println!("Maximum attempts reached. Exiting.");
                             std::process::exit(1);
                         }
                     } else {
-                        return Err(anyhow::anyhow!("Failed to decrypt vault: {:?}", e));
+                        return Err(explain_vault_error(e));
                     }
                 }
             }
         }
     } else {
+        // on stderr, so exports written to stdout stay clean
         execute!(
-            io::stdout(),
+            io::stderr(),
             SetForegroundColor(Color::Green),
             crossterm::style::Print("You are the first time to use this tool.\n"),
             ResetColor
         )?;
-        let mut passphrase = prompt_passphrase("Enter a passphrase to start (empty for no passphrase): ")?;
-        let mut confirm_passphrase = prompt_passphrase("Enter the same passphrase again: ")?;
+        let (mut passphrase, mut confirm_passphrase) = match source.read()? {
+            // nobody sees an empty passphrase from a script, refuse instead of using it
+            Some(passphrase) if passphrase.is_empty() => {
+                return Err(anyhow::anyhow!(
+                    "{} gave an empty passphrase, refusing to create the vault with it",
+                    source
+                ));
+            }
+            Some(passphrase) => (passphrase.clone(), passphrase),
+            None => (
+                prompt_passphrase("Enter a passphrase to start (empty for no passphrase): ")?,
+                prompt_passphrase("Enter the same passphrase again: ")?,
+            ),
+        };
         if passphrase == confirm_passphrase {
-            let try_encryption_key: [u8; 32] = derive_key_from_password(passphrase.as_str())?;
+            let kdf = Kdf::default();
+            let try_encryption_key: [u8; 32] = kdf.derive(passphrase.as_str())?;
             passphrase.zeroize();
             confirm_passphrase.zeroize();
             encryption_key.extend_from_slice(&try_encryption_key);
             let empty_vault = Vault::default();
-            empty_vault.save(&try_encryption_key)?;
+            save_with_new_key(&empty_vault, &try_encryption_key, &kdf)?;
             return Ok(Vault::default());
         } else {
             println!("Passphrases do not match. Please ensure both entries are identical.");
@@ -196,7 +934,7 @@ fn restore_terminal() -> Result<()> {
 
 /// A panic hook to properly restore the terminal in the case of a panic.
 /// Originally based on [spotify-tui's implementation](https://github.com/Rigellute/spotify-tui/blob/master/src/main.rs).
-fn panic_hook(panic_info: &PanicInfo<'_>) {
+fn panic_hook(panic_info: &PanicHookInfo<'_>) {
     let mut stdout = stdout();
 
     let msg = match panic_info.payload().downcast_ref::<&'static str>() {
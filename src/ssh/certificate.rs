@@ -0,0 +1,132 @@
+use anyhow::{Context, Result};
+use ssh_key::Certificate;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::helper::format_timestamp;
+
+/**
+    OpenSSH keeps the certificate of `id_ed25519` in `id_ed25519-cert.pub`
+*/
+pub fn certificate_path(key_path: &Path) -> PathBuf {
+    let mut path = key_path.as_os_str().to_owned();
+    path.push("-cert.pub");
+    PathBuf::from(path)
+}
+
+/**
+    load the certificate that sits next to the private key, if any
+*/
+pub fn load_certificate(key_path: &Path) -> Result<Option<Certificate>> {
+    let cert_path = certificate_path(key_path);
+    if !cert_path.exists() {
+        return Ok(None);
+    }
+    let cert = Certificate::read_file(&cert_path)
+        .with_context(|| format!("Failed to read certificate at {:?}", cert_path))?;
+    Ok(Some(cert))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+pub fn is_expired(cert: &Certificate) -> bool {
+    cert.valid_before() <= now()
+}
+
+/**
+    human readable expiry, certificates without an end date never expire
+*/
+pub fn expiry(cert: &Certificate) -> String {
+    if cert.valid_before() == u64::MAX {
+        "never".to_string()
+    } else {
+        format_timestamp(cert.valid_before())
+    }
+}
+
+/**
+    summary shown in the server detail view
+*/
+pub fn describe(cert: &Certificate) -> String {
+    let principals = if cert.valid_principals().is_empty() {
+        "(any)".to_string()
+    } else {
+        cert.valid_principals().join(", ")
+    };
+    let state = if is_expired(cert) { " (expired)" } else { "" };
+    format!(
+        "certificate: {}\nprincipals: {}\nexpires: {}{}",
+        cert.key_id(),
+        principals,
+        expiry(cert),
+        state
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_certificate_path() {
+        assert_eq!(
+            certificate_path(Path::new("/home/user/.ssh/id_ed25519")),
+            PathBuf::from("/home/user/.ssh/id_ed25519-cert.pub")
+        );
+    }
+
+    /// sign a fresh key with a throwaway CA through ssh-keygen
+    fn sign_key(dir: &Path, validity: &str) -> PathBuf {
+        let ca_path = dir.join("ca");
+        let key_path = dir.join("id_ed25519");
+        for path in [&ca_path, &key_path] {
+            let status = std::process::Command::new("ssh-keygen")
+                .args(["-q", "-t", "ed25519", "-N", "", "-f"])
+                .arg(path)
+                .status()
+                .expect("Failed to execute ssh-keygen");
+            assert!(status.success());
+        }
+        let status = std::process::Command::new("ssh-keygen")
+            .args(["-q", "-s"])
+            .arg(&ca_path)
+            .args(["-I", "alice@example", "-n", "alice,deploy", "-V", validity])
+            .arg(key_path.with_extension("pub"))
+            .status()
+            .expect("Failed to execute ssh-keygen");
+        assert!(status.success());
+        key_path
+    }
+
+    #[test]
+    fn test_load_certificate() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let key_path = sign_key(temp_dir.path(), "-1d:+52w");
+        let cert = load_certificate(&key_path).unwrap().unwrap();
+        assert_eq!(cert.key_id(), "alice@example");
+        assert_eq!(cert.valid_principals(), ["alice", "deploy"]);
+        assert!(!is_expired(&cert));
+        assert!(describe(&cert).contains("principals: alice, deploy"));
+    }
+
+    #[test]
+    fn test_expired_certificate() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let key_path = sign_key(temp_dir.path(), "-2d:-1d");
+        let cert = load_certificate(&key_path).unwrap().unwrap();
+        assert!(is_expired(&cert));
+        assert!(describe(&cert).ends_with("(expired)"));
+    }
+
+    #[test]
+    fn test_load_certificate_missing() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let key_path = temp_dir.path().join("id_ed25519");
+        assert!(load_certificate(&key_path).unwrap().is_none());
+    }
+}
@@ -1,21 +1,124 @@
 use anyhow::Result;
 use crossterm::terminal::size;
 use russh::{client::Msg, *};
-use std::{convert::TryFrom, time::Duration};
+use std::{borrow::Cow, convert::TryFrom, sync::Arc, time::Duration};
 use std::env;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::ToSocketAddrs;
+use tokio::time::timeout;
+
+use crate::config::app_config::SshOptions;
 
 pub struct SshChannel {
     channel: Channel<Msg>,
     last_size: (u16, u16),
 }
 
-pub fn default_ssh_config() -> client::Config {
-    client::Config {
-        keepalive_interval: Some(Duration::from_secs(15)),
+/// keepalive interval used when the server does not override it
+const DEFAULT_KEEPALIVE_INTERVAL: u64 = 15;
+
+/**
+    build the client config from the per-server options
+    fields that are not overridden keep the russh defaults
+*/
+pub fn ssh_config(options: &SshOptions) -> Result<client::Config> {
+    let mut preferred = Preferred::default();
+    if !options.kex.is_empty() {
+        let mut kex_names: Vec<kex::Name> = parse_names(&options.kex, "key exchange")?;
+        // the extension markers are negotiated through the kex list too,
+        // keep them so strict kex still protects legacy algorithms
+        kex_names.push(kex::EXTENSION_SUPPORT_AS_CLIENT);
+        kex_names.push(kex::EXTENSION_OPENSSH_STRICT_KEX_AS_CLIENT);
+        preferred.kex = Cow::Owned(kex_names);
+    }
+    if !options.ciphers.is_empty() {
+        preferred.cipher = Cow::Owned(parse_names(&options.ciphers, "cipher")?);
+    }
+    if !options.macs.is_empty() {
+        preferred.mac = Cow::Owned(parse_names(&options.macs, "MAC")?);
+    }
+    if !options.host_key_algorithms.is_empty() {
+        preferred.key = Cow::Owned(parse_names(&options.host_key_algorithms, "host key")?);
+    }
+    if options.compression {
+        preferred.compression = Cow::Borrowed(&[
+            compression::ZLIB_LEGACY,
+            compression::ZLIB,
+            compression::NONE,
+        ]);
+    }
+
+    Ok(client::Config {
+        preferred,
+        inactivity_timeout: seconds(options.inactivity_timeout),
+        keepalive_interval: seconds(Some(
+            options.keepalive_interval.unwrap_or(DEFAULT_KEEPALIVE_INTERVAL),
+        )),
         // 其他字段使用默认值
         ..Default::default()
+    })
+}
+
+/// 0 disables a timer, like `ServerAliveInterval 0` does in OpenSSH
+fn seconds(secs: Option<u64>) -> Option<Duration> {
+    secs.filter(|&secs| secs > 0).map(Duration::from_secs)
+}
+
+fn parse_names<N>(names: &[String], kind: &str) -> Result<Vec<N>>
+where
+    N: for<'a> TryFrom<&'a str>,
+{
+    names
+        .iter()
+        .map(|name| {
+            N::try_from(name.trim())
+                .map_err(|_| anyhow::anyhow!("Unsupported {} algorithm: {}", kind, name))
+        })
+        .collect()
+}
+
+/**
+    open the connection, giving up after the configured connect timeout
+*/
+pub async fn connect<H, A>(
+    options: &SshOptions,
+    handler: H,
+    addrs: A,
+) -> Result<client::Handle<H>>
+where
+    H: client::Handler<Error = russh::Error> + Send + 'static,
+    A: ToSocketAddrs,
+{
+    let config = Arc::new(ssh_config(options)?);
+    let session = match options.connect_timeout.filter(|&secs| secs > 0) {
+        Some(secs) => timeout(
+            Duration::from_secs(secs),
+            client::connect(config, addrs, handler),
+        )
+        .await
+        .map_err(|_| anyhow::anyhow!("Connection timed out after {} seconds", secs))??,
+        None => client::connect(config, addrs, handler).await?,
+    };
+    Ok(session)
+}
+
+/**
+    run a command without a PTY and collect its stdout
+    returns the exit code and the captured output
+*/
+pub async fn exec_command(mut channel: Channel<Msg>, command: &str) -> Result<(u32, String)> {
+    channel.exec(true, command).await?;
+
+    let mut code = 0;
+    let mut output: Vec<u8> = Vec::new();
+    while let Some(msg) = channel.wait().await {
+        match msg {
+            ChannelMsg::Data { ref data } => output.extend_from_slice(data),
+            ChannelMsg::ExitStatus { exit_status } => code = exit_status,
+            _ => {}
+        }
     }
+    Ok((code, String::from_utf8_lossy(&output).into_owned()))
 }
 
 impl SshChannel {
@@ -87,4 +190,61 @@ impl SshChannel {
         }
         Ok(code)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use russh::keys::key;
+
+    #[test]
+    fn test_ssh_config_default() {
+        let config = ssh_config(&SshOptions::default()).unwrap();
+        assert_eq!(config.keepalive_interval, Some(Duration::from_secs(15)));
+        assert_eq!(config.inactivity_timeout, None);
+        assert_eq!(config.preferred.kex, Preferred::default().kex);
+    }
+
+    #[test]
+    fn test_ssh_config_overrides() {
+        let options = SshOptions {
+            kex: vec!["diffie-hellman-group14-sha1".to_string()],
+            ciphers: vec!["aes128-ctr".to_string()],
+            host_key_algorithms: vec!["ssh-rsa".to_string()],
+            compression: true,
+            inactivity_timeout: Some(600),
+            keepalive_interval: Some(30),
+            ..Default::default()
+        };
+        let config = ssh_config(&options).unwrap();
+        assert_eq!(config.preferred.kex[0], kex::DH_G14_SHA1);
+        assert_eq!(&config.preferred.cipher[..], &[cipher::AES_128_CTR]);
+        assert_eq!(&config.preferred.key[..], &[key::SSH_RSA]);
+        assert_eq!(config.preferred.compression[0], compression::ZLIB_LEGACY);
+        assert_eq!(config.inactivity_timeout, Some(Duration::from_secs(600)));
+        assert_eq!(config.keepalive_interval, Some(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn test_ssh_config_zero_disables() {
+        let options = SshOptions {
+            connect_timeout: Some(0),
+            inactivity_timeout: Some(0),
+            keepalive_interval: Some(0),
+            ..Default::default()
+        };
+        let config = ssh_config(&options).unwrap();
+        assert_eq!(config.inactivity_timeout, None);
+        assert_eq!(config.keepalive_interval, None);
+    }
+
+    #[test]
+    fn test_ssh_config_unknown_algorithm() {
+        let options = SshOptions {
+            macs: vec!["hmac-md5".to_string()],
+            ..Default::default()
+        };
+        let err = ssh_config(&options).unwrap_err();
+        assert!(err.to_string().contains("hmac-md5"));
+    }
+}
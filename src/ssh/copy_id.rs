@@ -0,0 +1,76 @@
+use crate::ssh::multiplexer::shell_quote;
+
+/**
+    remote command that appends a public key to `~/.ssh/authorized_keys` like ssh-copy-id
+    `.ssh` and the file are created with 0700 and 0600, and nothing is appended
+    if the key is already there, whatever its comment
+*/
+pub fn install_command(public_key: &str) -> String {
+    let public_key = public_key.trim();
+    // `type base64`, the comment doesn't identify the key
+    let key_body: String = public_key
+        .split_whitespace()
+        .take(2)
+        .collect::<Vec<_>>()
+        .join(" ");
+    let script = format!(
+        concat!(
+            "umask 077 && mkdir -p ~/.ssh && chmod 700 ~/.ssh && ",
+            "touch ~/.ssh/authorized_keys && chmod 600 ~/.ssh/authorized_keys && ",
+            "{{ grep -qF {body} ~/.ssh/authorized_keys || {{ ",
+            // make sure the key starts on a line of its own
+            "[ ! -s ~/.ssh/authorized_keys ] || [ -z \"$(tail -c 1 ~/.ssh/authorized_keys)\" ] ",
+            "|| echo >> ~/.ssh/authorized_keys; ",
+            "echo {line} >> ~/.ssh/authorized_keys; }}; }}"
+        ),
+        body = shell_quote(&key_body),
+        line = shell_quote(public_key),
+    );
+    // the login shell may not be a POSIX one
+    format!("sh -c {}", shell_quote(&script))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use std::process::Command;
+
+    #[test]
+    fn test_install_command() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let home = temp_dir.path();
+        let run = |public_key: &str| {
+            let status = Command::new("sh")
+                .arg("-c")
+                .arg(install_command(public_key))
+                .env("HOME", home)
+                .status()
+                .expect("Failed to execute sh");
+            assert!(status.success());
+        };
+        let authorized_keys = home.join(".ssh/authorized_keys");
+
+        run("ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIK0 alice@laptop\n");
+        let mode = |path: &std::path::Path| {
+            std::fs::metadata(path).unwrap().permissions().mode() & 0o777
+        };
+        assert_eq!(mode(&home.join(".ssh")), 0o700);
+        assert_eq!(mode(&authorized_keys), 0o600);
+
+        // installing again, even with another comment, changes nothing
+        run("ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIK0 alice's key");
+        assert_eq!(
+            std::fs::read_to_string(&authorized_keys).unwrap(),
+            "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIK0 alice@laptop\n"
+        );
+
+        // a file without a trailing newline keeps its last key intact
+        std::fs::write(&authorized_keys, "ssh-rsa AAAAB3 bob").unwrap();
+        run("ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIK0 alice@laptop");
+        assert_eq!(
+            std::fs::read_to_string(&authorized_keys).unwrap(),
+            "ssh-rsa AAAAB3 bob\nssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIK0 alice@laptop\n"
+        );
+    }
+}
@@ -0,0 +1,446 @@
+use anyhow::{Context, Result};
+use rand::rngs::OsRng;
+use ssh_key::private::{EcdsaKeypair, Ed25519Keypair, KeypairData, RsaKeypair};
+use ssh_key::public::KeyData;
+use ssh_key::{EcdsaCurve, HashAlg, LineEnding, PrivateKey, PublicKey};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use zeroize::Zeroizing;
+
+use crate::config::app_vault::Vault;
+use crate::config::storage;
+use crate::ssh::certificate::certificate_path;
+use crate::ssh::identity::Identity;
+
+/// algorithm and size of a key to generate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeySpec {
+    Ed25519,
+    Ecdsa(EcdsaCurve),
+    Rsa(usize),
+}
+
+impl KeySpec {
+    /**
+        key type and size the way ssh-keygen takes them with `-t` and `-b`
+    */
+    pub fn new(kind: &str, bits: Option<usize>) -> Result<Self> {
+        match (kind.to_lowercase().as_str(), bits) {
+            ("ed25519", None | Some(256)) => Ok(KeySpec::Ed25519),
+            ("ecdsa", None | Some(256)) => Ok(KeySpec::Ecdsa(EcdsaCurve::NistP256)),
+            ("ecdsa", Some(384)) => Ok(KeySpec::Ecdsa(EcdsaCurve::NistP384)),
+            ("ecdsa", Some(521)) => Ok(KeySpec::Ecdsa(EcdsaCurve::NistP521)),
+            ("rsa", None) => Ok(KeySpec::Rsa(3072)),
+            ("rsa", Some(bits)) if (2048..=16384).contains(&bits) => Ok(KeySpec::Rsa(bits)),
+            ("ed25519" | "ecdsa" | "rsa", Some(bits)) => {
+                Err(anyhow::anyhow!("Unsupported {} key size: {}", kind, bits))
+            }
+            _ => Err(anyhow::anyhow!("Unsupported key type: {}", kind)),
+        }
+    }
+
+    /// file name ssh-keygen would use in `~/.ssh`
+    pub fn default_file_name(self) -> &'static str {
+        match self {
+            KeySpec::Ed25519 => "id_ed25519",
+            KeySpec::Ecdsa(_) => "id_ecdsa",
+            KeySpec::Rsa(_) => "id_rsa",
+        }
+    }
+}
+
+/// accepts `ed25519`, `ecdsa`, `ecdsa-384`, `rsa-4096` and so on
+impl FromStr for KeySpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().split_once('-') {
+            Some((kind, bits)) => {
+                let bits = bits
+                    .parse::<usize>()
+                    .map_err(|_| anyhow::anyhow!("Invalid key size: {}", bits))?;
+                KeySpec::new(kind, Some(bits))
+            }
+            None => KeySpec::new(s.trim(), None),
+        }
+    }
+}
+
+/// `user@host` like ssh-keygen puts in new keys
+pub fn default_comment() -> String {
+    let user = std::env::var("USER").unwrap_or_default();
+    let host = fs::read_to_string("/proc/sys/kernel/hostname")
+        .or_else(|_| fs::read_to_string("/etc/hostname"))
+        .unwrap_or_default();
+    match (user.is_empty(), host.trim()) {
+        (false, "") => user,
+        (false, host) => format!("{}@{}", user, host),
+        (true, host) => host.to_string(),
+    }
+}
+
+pub fn generate_key(spec: KeySpec, comment: &str) -> Result<PrivateKey> {
+    let key_data = match spec {
+        KeySpec::Ed25519 => KeypairData::Ed25519(Ed25519Keypair::random(&mut OsRng)),
+        KeySpec::Ecdsa(curve) => KeypairData::Ecdsa(EcdsaKeypair::random(&mut OsRng, curve)?),
+        KeySpec::Rsa(bits) => KeypairData::Rsa(RsaKeypair::random(&mut OsRng, bits)?),
+    };
+    PrivateKey::new(key_data, comment).context("Failed to generate key")
+}
+
+/**
+    OpenSSH encoding of a private key, encrypted unless the passphrase is empty
+*/
+pub fn encode_private_key(key: &PrivateKey, passphrase: &str) -> Result<Zeroizing<String>> {
+    let encoded = if passphrase.is_empty() {
+        key.to_openssh(LineEnding::LF)
+    } else {
+        key.encrypt(&mut OsRng, passphrase)?.to_openssh(LineEnding::LF)
+    };
+    encoded.context("Failed to encode private key")
+}
+
+/// key type and size as ssh-keygen prints them, e.g. `ED25519 256`
+pub fn key_type(key: &PublicKey) -> String {
+    let algorithm = key.algorithm();
+    let (name, bits) = match key.key_data() {
+        KeyData::Ed25519(_) => ("ED25519", Some(256)),
+        KeyData::Ecdsa(ecdsa) => (
+            "ECDSA",
+            Some(match ecdsa.curve() {
+                EcdsaCurve::NistP256 => 256,
+                EcdsaCurve::NistP384 => 384,
+                EcdsaCurve::NistP521 => 521,
+            }),
+        ),
+        KeyData::Rsa(rsa) => ("RSA", rsa.n.as_positive_bytes().map(|n| n.len() * 8)),
+        _ => (algorithm.as_str(), None),
+    };
+    match bits {
+        Some(bits) => format!("{} {}", name, bits),
+        None => name.to_string(),
+    }
+}
+
+pub fn fingerprint(key: &PublicKey) -> String {
+    key.fingerprint(HashAlg::Sha256).to_string()
+}
+
+pub fn randomart(key: &PublicKey) -> String {
+    key.fingerprint(HashAlg::Sha256)
+        .to_randomart(&format!("[{}]", key_type(key)))
+}
+
+/// `id_ed25519` -> `id_ed25519.pub`
+pub fn public_key_path(path: &Path) -> PathBuf {
+    let mut public_path = path.as_os_str().to_owned();
+    public_path.push(".pub");
+    PathBuf::from(public_path)
+}
+
+/**
+    public half of a key, from the `.pub` file next to a key file or from the vault
+*/
+pub fn read_public_key(
+    identity: &Identity,
+    vault: &Vault,
+    encryption_key: &[u8; 32],
+) -> Result<PublicKey> {
+    match identity {
+        Identity::File(path) => {
+            let public_path = public_key_path(path);
+            PublicKey::read_openssh_file(&public_path)
+                .with_context(|| format!("Failed to read public key at {:?}", public_path))
+        }
+        Identity::Vault(name) => {
+            let private_key = Zeroizing::new(vault.private_key(name, encryption_key)?);
+            Ok(PrivateKey::from_openssh(private_key.as_str())?
+                .public_key()
+                .clone())
+        }
+    }
+}
+
+/// what the key management view shows about a key
+pub struct KeyInfo {
+    pub identity: Identity,
+    /// e.g. `ED25519 256`, `unknown` if neither half of the key can be read
+    pub key_type: String,
+    pub fingerprint: String,
+    pub comment: String,
+    pub encrypted: bool,
+}
+
+/**
+    describe a key without asking for its passphrase
+    OpenSSH keys keep the public half in clear, older PEM keys need their `.pub` file
+*/
+pub fn key_info(identity: &Identity, vault: &Vault, encryption_key: &[u8; 32]) -> KeyInfo {
+    let (public_key, encrypted) = match identity {
+        Identity::File(path) => match PrivateKey::read_openssh_file(path) {
+            Ok(key) => (Some(key.public_key().clone()), key.is_encrypted()),
+            Err(_) => (
+                read_public_key(identity, vault, encryption_key).ok(),
+                fs::read_to_string(path).is_ok_and(|content| content.contains("ENCRYPTED")),
+            ),
+        },
+        Identity::Vault(_) => (read_public_key(identity, vault, encryption_key).ok(), false),
+    };
+    // the comment of an encrypted key is encrypted too
+    let comment = match &public_key {
+        Some(public_key) if !public_key.comment().is_empty() => public_key.comment().to_string(),
+        _ => read_public_key(identity, vault, encryption_key)
+            .map(|public_key| public_key.comment().to_string())
+            .unwrap_or_default(),
+    };
+    KeyInfo {
+        identity: identity.clone(),
+        key_type: public_key.as_ref().map_or("unknown".to_string(), key_type),
+        fingerprint: public_key.as_ref().map_or(String::new(), fingerprint),
+        comment,
+        encrypted,
+    }
+}
+
+/**
+    add, change or remove (with an empty `new`) the passphrase of an OpenSSH key file
+*/
+pub fn change_passphrase(path: &Path, old: &str, new: &str) -> Result<()> {
+    let mut key = PrivateKey::read_openssh_file(path)
+        .with_context(|| format!("Failed to read private key at {:?}", path))?;
+    if key.is_encrypted() {
+        key = key
+            .decrypt(old)
+            .map_err(|_| anyhow::anyhow!("wrong passphrase"))?;
+    }
+    let encoded = encode_private_key(&key, new)?;
+    // replaced atomically, so a failure can't lose the key
+    storage::write_private(path, encoded.as_bytes())
+}
+
+/// the private key, public key and certificate files of a key
+fn key_files(path: &Path) -> [PathBuf; 3] {
+    [path.to_path_buf(), public_key_path(path), certificate_path(path)]
+}
+
+/**
+    rename a key file together with its `.pub` and certificate
+*/
+pub fn rename_key_files(from: &Path, to: &Path) -> Result<()> {
+    if key_files(to).iter().any(|path| path.exists()) {
+        return Err(anyhow::anyhow!("{:?} already exists", to));
+    }
+    for (old, new) in key_files(from).iter().zip(key_files(to).iter()) {
+        if old.exists() {
+            fs::rename(old, new).with_context(|| format!("Failed to rename {:?}", old))?;
+        }
+    }
+    Ok(())
+}
+
+/**
+    delete a key file together with its `.pub` and certificate
+*/
+pub fn delete_key_files(path: &Path) -> Result<()> {
+    for file in key_files(path).iter().filter(|file| file.exists()) {
+        fs::remove_file(file).with_context(|| format!("Failed to delete {:?}", file))?;
+    }
+    Ok(())
+}
+
+/**
+    write a new key pair like ssh-keygen does, `~/.ssh` is created with 0700 if needed
+*/
+pub fn save_key_files(path: &Path, key: &PrivateKey, passphrase: &str) -> Result<()> {
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        if !parent.exists() {
+            fs::DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(parent)
+                .with_context(|| format!("Failed to create directory {:?}", parent))?;
+        }
+    }
+    let public_path = public_key_path(path);
+    if fs::metadata(&public_path).is_ok() {
+        return Err(anyhow::anyhow!("{:?} already exists", public_path));
+    }
+    write_private_key(path, &encode_private_key(key, passphrase)?)?;
+    let public_key = key.public_key().to_openssh()?;
+    fs::write(&public_path, format!("{}\n", public_key))
+        .with_context(|| format!("Failed to write public key at {:?}", public_path))?;
+    Ok(())
+}
+
+/**
+    store a freshly generated key in a file or the vault
+    keys in the vault are protected by the master passphrase and can't have their own
+*/
+pub fn save_generated_key(
+    target: &Identity,
+    key: &PrivateKey,
+    passphrase: &str,
+    vault: &mut Vault,
+    encryption_key: &[u8; 32],
+) -> Result<()> {
+    match target {
+        Identity::File(path) => save_key_files(path, key, passphrase),
+        Identity::Vault(name) => {
+            if !passphrase.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "keys in the vault are protected by the master passphrase, leave the passphrase empty"
+                ));
+            }
+            vault.add_key(name, &encode_private_key(key, "")?, encryption_key)
+        }
+    }
+}
+
+/**
+    read a private key file and return it in OpenSSH format without a passphrase
+    `passphrase` is only asked for if the key is encrypted
+*/
+pub fn read_private_key(
+    path: &Path,
+    passphrase: impl FnOnce() -> Result<String>,
+) -> Result<Zeroizing<String>> {
+    let mut key = PrivateKey::read_openssh_file(path)
+        .with_context(|| format!("Failed to read private key at {:?}", path))?;
+    if key.is_encrypted() {
+        let passphrase = Zeroizing::new(passphrase()?);
+        key = key
+            .decrypt(passphrase.as_bytes())
+            .map_err(|_| anyhow::anyhow!("wrong passphrase"))?;
+    }
+    key.to_openssh(LineEnding::LF)
+        .context("Failed to encode private key")
+}
+
+/**
+    write a private key readable by its owner only, refuses to overwrite a file
+*/
+pub fn write_private_key(path: &Path, private_key: &str) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+        .with_context(|| format!("Failed to create key file at {:?}", path))?;
+    file.write_all(private_key.as_bytes())
+        .with_context(|| format!("Failed to write key file at {:?}", path))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn ssh_keygen(path: &Path, passphrase: &str) {
+        let status = std::process::Command::new("ssh-keygen")
+            .args(["-q", "-t", "ed25519", "-N", passphrase, "-f"])
+            .arg(path)
+            .status()
+            .expect("Failed to execute ssh-keygen");
+        assert!(status.success());
+    }
+
+    #[test]
+    fn test_key_spec() {
+        assert_eq!("ed25519".parse::<KeySpec>().unwrap(), KeySpec::Ed25519);
+        assert_eq!(
+            "ecdsa-384".parse::<KeySpec>().unwrap(),
+            KeySpec::Ecdsa(EcdsaCurve::NistP384)
+        );
+        assert_eq!(KeySpec::new("RSA", None).unwrap(), KeySpec::Rsa(3072));
+        assert!("ecdsa-512".parse::<KeySpec>().is_err());
+        assert!("rsa-1024".parse::<KeySpec>().is_err());
+        assert!("dsa".parse::<KeySpec>().is_err());
+    }
+
+    #[test]
+    fn test_generate_key() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let key_path = temp_dir.path().join("keys").join("id_ecdsa");
+        let key = generate_key(KeySpec::Ecdsa(EcdsaCurve::NistP384), "alice@example").unwrap();
+        assert_eq!(key_type(key.public_key()), "ECDSA 384");
+        assert!(randomart(key.public_key()).starts_with("+---[ECDSA 384]---+"));
+        save_key_files(&key_path, &key, "secret").unwrap();
+
+        // ssh-keygen agrees on the fingerprint and needs the passphrase
+        let output = std::process::Command::new("ssh-keygen")
+            .args(["-l", "-E", "sha256", "-f"])
+            .arg(&key_path)
+            .output()
+            .expect("Failed to execute ssh-keygen");
+        let output = String::from_utf8_lossy(&output.stdout);
+        assert!(output.contains(&fingerprint(key.public_key())));
+        assert!(output.contains("alice@example"));
+        assert!(read_private_key(&key_path, || Ok("secret".to_string())).is_ok());
+        assert!(save_key_files(&key_path, &key, "").is_err());
+        let public_key = read_public_key(
+            &Identity::File(key_path),
+            &Vault::default(),
+            &[0u8; 32],
+        )
+        .unwrap();
+        assert_eq!(&public_key, key.public_key());
+    }
+
+    #[test]
+    fn test_manage_key_files() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let key_path = temp_dir.path().join("id_ed25519");
+        let key = generate_key(KeySpec::Ed25519, "alice@example").unwrap();
+        save_key_files(&key_path, &key, "").unwrap();
+        let identity = Identity::File(key_path.clone());
+        let vault = Vault::default();
+
+        let info = key_info(&identity, &vault, &[0u8; 32]);
+        assert_eq!(info.key_type, "ED25519 256");
+        assert_eq!(info.fingerprint, fingerprint(key.public_key()));
+        assert_eq!(info.comment, "alice@example");
+        assert!(!info.encrypted);
+
+        // add a passphrase, the comment still comes from the .pub file
+        change_passphrase(&key_path, "", "secret").unwrap();
+        let info = key_info(&identity, &vault, &[0u8; 32]);
+        assert!(info.encrypted);
+        assert_eq!(info.comment, "alice@example");
+        assert!(change_passphrase(&key_path, "wrong", "").is_err());
+        change_passphrase(&key_path, "secret", "").unwrap();
+        assert!(!key_info(&identity, &vault, &[0u8; 32]).encrypted);
+
+        let new_path = temp_dir.path().join("work");
+        rename_key_files(&key_path, &new_path).unwrap();
+        assert!(!key_path.exists());
+        assert!(public_key_path(&new_path).exists());
+        delete_key_files(&new_path).unwrap();
+        assert!(std::fs::read_dir(temp_dir.path()).unwrap().next().is_none());
+    }
+
+    #[test]
+    fn test_read_and_write_private_key() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let key_path = temp_dir.path().join("id_ed25519");
+        ssh_keygen(&key_path, "secret");
+
+        assert!(read_private_key(&key_path, || Ok("wrong".to_string())).is_err());
+        let private_key = read_private_key(&key_path, || Ok("secret".to_string())).unwrap();
+        assert!(!PrivateKey::from_openssh(private_key.as_str())
+            .unwrap()
+            .is_encrypted());
+
+        let export_path = temp_dir.path().join("exported");
+        write_private_key(&export_path, &private_key).unwrap();
+        let mode = std::fs::metadata(&export_path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        // the exported key is usable without a passphrase
+        let exported = read_private_key(&export_path, || panic!("not encrypted")).unwrap();
+        assert_eq!(exported, private_key);
+        assert!(write_private_key(&export_path, &private_key).is_err());
+    }
+}
@@ -4,8 +4,9 @@ use async_trait::async_trait;
 use russh::keys::*;
 use russh::*;
 use tokio::net::ToSocketAddrs;
-use super::common::{default_ssh_config, SshChannel};
-use super::ssh_session::{AuthMethod, SshSession};
+use super::common::{connect, exec_command, SshChannel};
+use crate::config::app_config::SshOptions;
+use super::ssh_session::{AuthMethod, AuthRejected, SshSession};
 
 pub struct Client {}
 
@@ -34,32 +35,40 @@ pub struct KeySession {
 #[async_trait::async_trait]
 impl SshSession for KeySession {
     async fn connect<A: ToSocketAddrs + Send>(
+        options: &SshOptions,
         user: impl Into<String> + Send,
         auth: impl Into<AuthMethod> + Send,
         addrs: A,
     ) -> Result<Self> {
         let auth = auth.into();
-        let key_pair = match auth {
-            AuthMethod::Key(path) => path,
+        let (key_pair, cert) = match auth {
+            AuthMethod::Key(key_pair) => (key_pair, None),
+            AuthMethod::Certificate(key_pair, cert) => (key_pair, Some(cert)),
             AuthMethod::Password(_) => anyhow::bail!("KeySession only supports key authentication"),
         };
 
         //let key_pair: key::KeyPair = load_secret_key(key_path, None)?;
 
-        let config = default_ssh_config();
-
-        let config = Arc::new(config);
         let sh = Client {};
 
-        let mut session = client::connect(config, addrs, sh).await?;
+        let mut session = connect(options, sh, addrs).await?;
 
         // 使用公钥进行认证
-        let auth_res = session
-            .authenticate_publickey(user, Arc::new(key_pair))
-            .await?;
+        let auth_res = match cert {
+            Some(cert) => {
+                session
+                    .authenticate_openssh_cert(user, Arc::new(key_pair), cert)
+                    .await?
+            }
+            None => {
+                session
+                    .authenticate_publickey(user, Arc::new(key_pair))
+                    .await?
+            }
+        };
 
         if !auth_res {
-            anyhow::bail!("public key authentication failed");
+            return Err(AuthRejected.into());
         }
 
         Ok(Self { session })
@@ -71,6 +80,11 @@ impl SshSession for KeySession {
         ssh_channel.call(command).await
     }
 
+    async fn exec(&mut self, command: &str) -> Result<(u32, String)> {
+        let channel = self.session.channel_open_session().await?;
+        exec_command(channel, command).await
+    }
+
     async fn close(&mut self) -> Result<()> {
         self.session
             .disconnect(Disconnect::ByApplication, "", "English")
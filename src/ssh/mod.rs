@@ -1,4 +1,9 @@
 pub mod password_session;
 pub mod key_session;
 pub mod ssh_session;
-pub mod common;
\ No newline at end of file
+pub mod common;
+pub mod multiplexer;
+pub mod certificate;
+pub mod identity;
+pub mod key_file;
+pub mod copy_id;
\ No newline at end of file
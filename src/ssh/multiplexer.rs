@@ -0,0 +1,96 @@
+use crate::config::app_config::MultiplexerKind;
+
+/// default session name used when none is configured
+pub static DEFAULT_SESSION: &str = "ssh-utils";
+
+/**
+    quote a string so the remote shell treats it as a single word
+*/
+pub fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/**
+    command that attaches to the named session, creating it if it does not exist
+*/
+pub fn attach_command(kind: MultiplexerKind, session: &str) -> String {
+    let session = if session.trim().is_empty() {
+        DEFAULT_SESSION
+    } else {
+        session
+    };
+    match kind {
+        MultiplexerKind::Tmux => format!("tmux new-session -A -s {}", shell_quote(session)),
+        MultiplexerKind::Screen => format!("screen -D -R -S {}", shell_quote(session)),
+    }
+}
+
+/**
+    command that lists the existing sessions, meant to run without a PTY
+*/
+pub fn list_command(kind: MultiplexerKind) -> &'static str {
+    match kind {
+        MultiplexerKind::Tmux => "tmux ls -F '#{session_name}'",
+        MultiplexerKind::Screen => "screen -ls",
+    }
+}
+
+/**
+    parse the output of `list_command` into session names
+*/
+pub fn parse_sessions(kind: MultiplexerKind, output: &str) -> Vec<String> {
+    match kind {
+        MultiplexerKind::Tmux => output
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect(),
+        // screen prints lines like "\t12345.name\t(Detached)"
+        MultiplexerKind::Screen => output
+            .lines()
+            .filter(|line| line.starts_with('\t'))
+            .filter_map(|line| line.split_whitespace().next())
+            .filter_map(|id| id.split_once('.').map(|(_, name)| name.to_string()))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attach_command() {
+        assert_eq!(
+            attach_command(MultiplexerKind::Tmux, "work"),
+            "tmux new-session -A -s 'work'"
+        );
+        assert_eq!(
+            attach_command(MultiplexerKind::Screen, ""),
+            "screen -D -R -S 'ssh-utils'"
+        );
+        assert_eq!(
+            attach_command(MultiplexerKind::Tmux, "it's"),
+            r"tmux new-session -A -s 'it'\''s'"
+        );
+    }
+
+    #[test]
+    fn test_parse_sessions() {
+        let tmux_output = "main\nbuild\n\n";
+        assert_eq!(
+            parse_sessions(MultiplexerKind::Tmux, tmux_output),
+            vec!["main", "build"]
+        );
+
+        let screen_output = "There are screens on:\n\
+            \t4242.work\t(10/18/2026 09:00:00 AM)\t(Detached)\n\
+            \t1337.pts-0.host\t(Attached)\n\
+            2 Sockets in /run/screen/S-user.\n";
+        assert_eq!(
+            parse_sessions(MultiplexerKind::Screen, screen_output),
+            vec!["work", "pts-0.host"]
+        );
+    }
+}
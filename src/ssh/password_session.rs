@@ -1,11 +1,11 @@
 use crate::ssh::ssh_session::{SshSession, AuthMethod};
-use std::sync::Arc;
 use anyhow::Result;
 use async_trait::async_trait;
 use russh::keys::*;
 use russh::*;
 use tokio::net::ToSocketAddrs;
-use super::common::{default_ssh_config, SshChannel};
+use super::common::{connect, exec_command, SshChannel};
+use crate::config::app_config::SshOptions;
 
 pub struct Client {}
 
@@ -31,15 +31,14 @@ pub struct PasswordSession {
 #[async_trait]
 impl SshSession for PasswordSession {
     async fn connect<A: ToSocketAddrs + Send>(
+        options: &SshOptions,
         user: impl Into<String> + Send,
         auth: impl Into<AuthMethod> + Send,
         addrs: A,
     ) -> Result<Self> {
-        let config = default_ssh_config();
-        let config = Arc::new(config);
         let sh = Client {};
 
-        let mut session = client::connect(config, addrs, sh).await?;
+        let mut session = connect(options, sh, addrs).await?;
 
         let user = user.into();
         let auth = auth.into();
@@ -51,7 +50,7 @@ impl SshSession for PasswordSession {
                     anyhow::bail!("Authentication (with password) failed");
                 }
             }
-            AuthMethod::Key(_) => {
+            AuthMethod::Key(_) | AuthMethod::Certificate(..) => {
                 anyhow::bail!("Key authentication not implemented for PasswordSession");
             }
         }
@@ -66,6 +65,11 @@ impl SshSession for PasswordSession {
         ssh_channel.call(command).await
     }
 
+    async fn exec(&mut self, command: &str) -> Result<(u32, String)> {
+        let channel = self.session.channel_open_session().await?;
+        exec_command(channel, command).await
+    }
+
     async fn close(&mut self) -> Result<()> {
         self.session
             .disconnect(Disconnect::ByApplication, "", "English")
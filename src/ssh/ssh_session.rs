@@ -1,10 +1,16 @@
 use anyhow::Result;
+use std::fmt;
 use russh_keys::key;
+use ssh_key::Certificate;
 use tokio::net::ToSocketAddrs;
 
+use crate::config::app_config::SshOptions;
+
 #[async_trait::async_trait]
 pub trait SshSession {
+    /// connect using the per-server algorithm and timeout overrides
     async fn connect<A: ToSocketAddrs + Send>(
+        options: &SshOptions,
         user: impl Into<String> + Send,
         auth: impl Into<AuthMethod> + Send,
         addrs: A,
@@ -13,10 +19,27 @@ pub trait SshSession {
         Self: Sized;
 
     async fn call(&mut self, command: &str) -> Result<u32>;
+    /// run a command without a PTY, returning its exit code and stdout
+    async fn exec(&mut self, command: &str) -> Result<(u32, String)>;
     async fn close(&mut self) -> Result<()>;
 }
 
+#[allow(clippy::large_enum_variant)]
 pub enum AuthMethod {
     Password(String),
     Key(key::KeyPair),
-}
\ No newline at end of file
+    /// private key together with the OpenSSH user certificate issued for it
+    Certificate(key::KeyPair, Certificate),
+}
+
+/// the server refused the offered key
+#[derive(Debug)]
+pub struct AuthRejected;
+
+impl fmt::Display for AuthRejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "public key authentication failed")
+    }
+}
+
+impl std::error::Error for AuthRejected {}
@@ -0,0 +1,54 @@
+use anyhow::Result;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::backend::Backend;
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::text::{Line, Text};
+use ratatui::widgets::{Block, Borders, Paragraph, Wrap};
+use ratatui::Terminal;
+
+use crate::helper;
+
+/// yes/no question shown as a popup, answers "no" unless `y` is pressed
+pub struct ConfirmBox {
+    title: String,
+    message: String,
+}
+
+impl ConfirmBox {
+    pub fn new(title: String, message: String) -> Self {
+        Self { title, message }
+    }
+
+    fn render(&self) -> Paragraph<'_> {
+        let mut text = Text::raw(self.message.as_str()).fg(Color::White);
+        text.push_line(Line::from(""));
+        text.push_line(Line::from("Continue? (y/N)").bold());
+        Paragraph::new(text)
+            .wrap(Wrap { trim: true })
+            .block(
+                Block::default()
+                    .border_style(Style::default().fg(Color::LightYellow))
+                    .title(self.title.clone())
+                    .borders(Borders::ALL),
+            )
+    }
+
+    fn draw(&self, terminal: &mut Terminal<impl Backend>) -> Result<()> {
+        terminal.draw(|f| {
+            let area = helper::centered_rect(50, 60, f.area());
+            f.render_widget(self.render(), area)
+        })?;
+        Ok(())
+    }
+
+    pub fn run(&mut self, terminal: &mut Terminal<impl Backend>) -> Result<bool> {
+        loop {
+            self.draw(terminal)?;
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press {
+                    return Ok(matches!(key.code, KeyCode::Char('y') | KeyCode::Char('Y')));
+                }
+            }
+        }
+    }
+}
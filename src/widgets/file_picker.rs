@@ -0,0 +1,145 @@
+use anyhow::{Context, Result};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::backend::Backend;
+use ratatui::style::{Modifier, Style};
+use ratatui::widgets::{Block, Borders, HighlightSpacing, List, ListItem, ListState};
+use ratatui::Terminal;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::helper;
+use crate::ssh::identity::display_path;
+
+/// browse the file system to pick a private key
+pub struct FilePicker {
+    dir: PathBuf,
+    entries: Vec<PathBuf>,
+    state: ListState,
+}
+
+impl FilePicker {
+    pub fn new(dir: PathBuf) -> Result<Self> {
+        let mut picker = Self {
+            dir: PathBuf::new(),
+            entries: Vec::new(),
+            state: ListState::default(),
+        };
+        picker.change_dir(dir)?;
+        Ok(picker)
+    }
+
+    /**
+        list the directory, subdirectories first
+        public keys are hidden since they can't be used as identities
+    */
+    fn change_dir(&mut self, dir: PathBuf) -> Result<()> {
+        let mut entries: Vec<PathBuf> = fs::read_dir(&dir)
+            .with_context(|| format!("Unable to read directory {:?}", dir))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_dir() || path.extension().is_none_or(|ext| ext != "pub"))
+            .collect();
+        entries.sort_by_key(|path| (!path.is_dir(), path.file_name().map(|name| name.to_owned())));
+        if let Some(parent) = dir.parent() {
+            entries.insert(0, parent.to_path_buf());
+        }
+        self.dir = dir;
+        self.entries = entries;
+        self.state.select(Some(0));
+        Ok(())
+    }
+
+    fn entry_name(&self, index: usize, path: &Path) -> String {
+        let is_parent = index == 0 && self.dir.parent() == Some(path);
+        if is_parent {
+            "../".to_string()
+        } else {
+            let name = path
+                .file_name()
+                .map_or(String::new(), |name| name.to_string_lossy().into_owned());
+            if path.is_dir() {
+                format!("{}/", name)
+            } else {
+                name
+            }
+        }
+    }
+
+    fn next(&mut self) {
+        let i = self
+            .state
+            .selected()
+            .map_or(0, |i| (i + 1) % self.entries.len().max(1));
+        self.state.select(Some(i));
+    }
+
+    fn previous(&mut self) {
+        let len = self.entries.len().max(1);
+        let i = self.state.selected().map_or(0, |i| (i + len - 1) % len);
+        self.state.select(Some(i));
+    }
+
+    fn draw(&mut self, terminal: &mut Terminal<impl Backend>) -> Result<()> {
+        let items: Vec<ListItem> = self
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(index, path)| ListItem::new(self.entry_name(index, path)))
+            .collect();
+        let list = List::new(items)
+            .block(
+                Block::default()
+                    .title(format!(" {} ", display_path(&self.dir)))
+                    .borders(Borders::ALL),
+            )
+            .highlight_style(
+                Style::default()
+                    .add_modifier(Modifier::BOLD)
+                    .add_modifier(Modifier::REVERSED),
+            )
+            .highlight_symbol("> ")
+            .highlight_spacing(HighlightSpacing::Always);
+
+        terminal.draw(|f| {
+            let area = helper::centered_rect(80, 100, f.area());
+            f.render_stateful_widget(list, area, &mut self.state)
+        })?;
+        Ok(())
+    }
+
+    /**
+     * Run and get the picked file
+     * Some(path) -> a file was picked
+     * None -> cancelled
+     */
+    pub fn run(&mut self, terminal: &mut Terminal<impl Backend>) -> Result<Option<PathBuf>> {
+        loop {
+            self.draw(terminal)?;
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press {
+                    match key.code {
+                        KeyCode::Char('j') | KeyCode::Down => self.next(),
+                        KeyCode::Char('k') | KeyCode::Up => self.previous(),
+                        KeyCode::Enter => {
+                            let Some(path) = self
+                                .state
+                                .selected()
+                                .and_then(|i| self.entries.get(i))
+                                .cloned()
+                            else {
+                                continue;
+                            };
+                            if path.is_dir() {
+                                // stay in the current directory if the new one can't be read
+                                let _ = self.change_dir(path);
+                            } else {
+                                return Ok(Some(path));
+                            }
+                        }
+                        KeyCode::Esc | KeyCode::Char('q') => return Ok(None),
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+}
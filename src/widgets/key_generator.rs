@@ -0,0 +1,221 @@
+use anyhow::Result;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::{
+    backend::Backend,
+    layout::{Constraint, Layout},
+    style::{Style, Stylize},
+    text::{Line, Span, Text},
+    widgets::{Block, Borders, Paragraph, Wrap},
+    Frame, Terminal,
+};
+use ssh_key::PrivateKey;
+
+use crate::{
+    config::app_vault::{EncryptionKey, Vault},
+    helper::convert_to_array,
+    ssh::{
+        identity::Identity,
+        key_file::{default_comment, fingerprint, generate_key, randomart, save_generated_key, KeySpec},
+    },
+};
+
+/// width of the labels in front of the inputs, including the colon
+const LABEL_WIDTH: usize = 13;
+
+/// items of the form, in display order
+const LABELS: [&str; 4] = ["type", "file", "passphrase", "comment"];
+const TYPE: usize = 0;
+const FILE: usize = 1;
+const PASSPHRASE: usize = 2;
+const COMMENT: usize = 3;
+
+/// dialog that generates a key pair into `~/.ssh` or the vault
+pub struct KeyGenerator<'a> {
+    input: [String; 4],
+    current_select: usize,
+    vault: &'a mut Vault,
+    encryption_key: &'a EncryptionKey,
+    /// why the last attempt was refused
+    error: Option<String>,
+    /// (where it was saved, the key) once generated
+    generated: Option<(String, PrivateKey)>,
+}
+
+impl<'a> KeyGenerator<'a> {
+    pub fn new(vault: &'a mut Vault, encryption_key: &'a EncryptionKey) -> Self {
+        Self {
+            input: [
+                "ed25519".to_string(),
+                String::new(),
+                String::new(),
+                default_comment(),
+            ],
+            current_select: TYPE,
+            vault,
+            encryption_key,
+            error: None,
+            generated: None,
+        }
+    }
+
+    fn placeholder(&self, item: usize) -> String {
+        match item {
+            TYPE => "ed25519, ecdsa-256/384/521 or rsa-2048/3072/4096".to_string(),
+            FILE => {
+                let file_name = self
+                    .input[TYPE]
+                    .parse::<KeySpec>()
+                    .map_or("id_<type>", KeySpec::default_file_name);
+                format!("~/.ssh/{}, or vault:<name>", file_name)
+            }
+            PASSPHRASE => "leave empty for no passphrase".to_string(),
+            _ => String::new(),
+        }
+    }
+
+    fn render_form(&self) -> Text<'_> {
+        let mut lines = vec![match &self.error {
+            Some(error) => Line::from(error.as_str()).red(),
+            None => Line::from("Generate a new key pair:").yellow(),
+        }];
+        for (item, label) in LABELS.iter().enumerate() {
+            let label = format!("{:>width$}:", label, width = LABEL_WIDTH - 1);
+            let label = if item == self.current_select {
+                Span::styled(label, Style::new().bold())
+            } else {
+                label.into()
+            };
+            let input = &self.input[item];
+            let value = if input.is_empty() {
+                Span::styled(self.placeholder(item), Style::new().dim())
+            } else if item == PASSPHRASE {
+                "*".repeat(input.chars().count()).into()
+            } else {
+                input.clone().into()
+            };
+            lines.push(Line::from(vec![label, value]));
+        }
+        lines.push(Line::from(""));
+        lines.push(Line::from("  Generate (^S), Quit (ESC)").dim());
+        Text::from(lines)
+    }
+
+    /**
+     * Generate the key and save it
+     * false -> the form is not valid, the error is shown
+     */
+    fn generate(&mut self, terminal: &mut Terminal<impl Backend>) -> Result<bool> {
+        let spec = match self.input[TYPE].parse::<KeySpec>() {
+            Ok(spec) => spec,
+            Err(e) => {
+                self.error = Some(e.to_string());
+                return Ok(false);
+            }
+        };
+        let file = self.input[FILE].trim();
+        let target = if file.is_empty() {
+            Identity::parse(&format!("~/.ssh/{}", spec.default_file_name()))
+        } else {
+            Identity::parse(file)
+        };
+        if let Identity::File(path) = &target {
+            if path.exists() {
+                self.error = Some(format!("{} already exists", target));
+                return Ok(false);
+            }
+        }
+        // large RSA keys take a while
+        self.error = Some("Generating...".to_string());
+        self.draw(terminal)?;
+        let key = generate_key(spec, self.input[COMMENT].trim())?;
+        let saved = save_generated_key(
+            &target,
+            &key,
+            &self.input[PASSPHRASE],
+            self.vault,
+            &convert_to_array(self.encryption_key)?,
+        );
+        if let Err(e) = saved {
+            self.error = Some(e.to_string());
+            return Ok(false);
+        }
+        self.generated = Some((target.to_string(), key));
+        Ok(true)
+    }
+
+    fn draw(&mut self, terminal: &mut Terminal<impl Backend>) -> Result<()> {
+        terminal.draw(|f| ui(f, self))?;
+        Ok(())
+    }
+
+    /**
+     * Run and get a result
+     * true -> a key was generated
+     * false -> cancelled
+     */
+    pub fn run(&mut self, terminal: &mut Terminal<impl Backend>) -> Result<bool> {
+        loop {
+            self.draw(terminal)?;
+            if let Event::Key(key) = event::read()? {
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                if self.generated.is_some() {
+                    // any key closes the result
+                    return Ok(true);
+                }
+                match key.code {
+                    KeyCode::Char('c') if key.modifiers == KeyModifiers::CONTROL => {
+                        return Ok(false);
+                    }
+                    KeyCode::Char('s') if key.modifiers == KeyModifiers::CONTROL => {
+                        self.generate(terminal)?;
+                    }
+                    KeyCode::Char(to_insert) => self.input[self.current_select].push(to_insert),
+                    KeyCode::Backspace => {
+                        self.input[self.current_select].pop();
+                    }
+                    KeyCode::Esc => return Ok(false),
+                    KeyCode::Up => {
+                        self.current_select = (self.current_select + LABELS.len() - 1) % LABELS.len();
+                    }
+                    KeyCode::Down | KeyCode::Enter | KeyCode::Tab => {
+                        self.current_select = (self.current_select + 1) % LABELS.len();
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+fn ui(f: &mut Frame, key_generator: &KeyGenerator) {
+    let area = f.area();
+    let Some((target, key)) = &key_generator.generated else {
+        f.render_widget(Paragraph::new(key_generator.render_form()), area);
+        let line = key_generator.current_select as u16 + 1;
+        let column = LABEL_WIDTH + key_generator.input[key_generator.current_select].chars().count();
+        f.set_cursor_position((area.x + column as u16, area.y + line));
+        return;
+    };
+    // the randomart is 11 lines high, its bottom border doesn't fit the viewport
+    let art = randomart(key.public_key());
+    let art_width = art.lines().next().map_or(0, |line| line.chars().count()) as u16;
+    let [art_area, info_area] =
+        Layout::horizontal([Constraint::Length(art_width + 2), Constraint::Min(0)]).areas(area);
+    f.render_widget(Paragraph::new(art), art_area);
+    let info = Text::from(vec![
+        Line::from(format!("Your key has been saved in {}", target)).green(),
+        Line::from(""),
+        Line::from("The key fingerprint is:"),
+        Line::from(format!("{} {}", fingerprint(key.public_key()), key.comment())),
+        Line::from(""),
+        Line::from("Press any key to continue").dim(),
+    ]);
+    f.render_widget(
+        Paragraph::new(info)
+            .wrap(Wrap { trim: true })
+            .block(Block::default().borders(Borders::LEFT)),
+        info_area,
+    );
+}
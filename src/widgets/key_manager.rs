@@ -0,0 +1,403 @@
+use anyhow::Result;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::{
+    backend::Backend,
+    layout::{Constraint, Layout},
+    style::{Modifier, Style, Stylize},
+    text::{Line, Text},
+    widgets::{HighlightSpacing, List, ListItem, ListState, Paragraph},
+    Frame, Terminal,
+};
+use std::path::PathBuf;
+
+use crate::{
+    config::{
+        app_config::Config,
+        app_vault::{decrypt_password, EncryptionKey, Vault},
+        store,
+    },
+    helper::convert_to_array,
+    ssh::{
+        identity::{expand_tilde, identity_candidates, known_key_files, Identity},
+        key_file::{change_passphrase, delete_key_files, key_info, rename_key_files, KeyInfo},
+    },
+    widgets::{confirm_box::ConfirmBox, key_generator::KeyGenerator, popup_input_box::PopupInputBox},
+};
+
+/// screen listing every key ssh-utils knows of
+pub struct KeyManager<'a> {
+    vault: &'a mut Vault,
+    config: &'a mut Config,
+    encryption_key: &'a EncryptionKey,
+    keys: Vec<KeyInfo>,
+    /// names of the servers using each key
+    users: Vec<Vec<String>>,
+    state: ListState,
+    /// result of the last action, (text, is error)
+    message: Option<(String, bool)>,
+}
+
+impl<'a> KeyManager<'a> {
+    pub fn new(
+        vault: &'a mut Vault,
+        config: &'a mut Config,
+        encryption_key: &'a EncryptionKey,
+    ) -> Result<Self> {
+        let mut key_manager = Self {
+            vault,
+            config,
+            encryption_key,
+            keys: Vec::new(),
+            users: Vec::new(),
+            state: ListState::default(),
+            message: None,
+        };
+        key_manager.reload()?;
+        Ok(key_manager)
+    }
+
+    /// collect the keys again after they changed
+    fn reload(&mut self) -> Result<()> {
+        let encryption_key = convert_to_array(self.encryption_key)?;
+        let mut identities: Vec<Identity> =
+            known_key_files().into_iter().map(Identity::File).collect();
+        identities.extend(self.vault.keys.iter().map(|key| Identity::Vault(key.name.clone())));
+        // keys outside ~/.ssh that servers point to
+        for server in &self.config.servers {
+            for entry in &server.identity_files {
+                let identity = Identity::parse(entry);
+                let exists = match &identity {
+                    Identity::File(path) => path.exists(),
+                    Identity::Vault(_) => false,
+                };
+                if exists && !identities.contains(&identity) {
+                    identities.push(identity);
+                }
+            }
+        }
+
+        // a server uses the keys it names, and the default keys too if it has no password
+        let mut key_servers: Vec<(String, Vec<Identity>)> = Vec::new();
+        for server in &self.config.servers {
+            let mut candidates: Vec<Identity> = server
+                .identity_files
+                .iter()
+                .map(|entry| Identity::parse(entry))
+                .collect();
+            let uses_password = self
+                .vault
+                .servers
+                .iter()
+                .find(|s| s.id == server.id)
+                .and_then(|s| decrypt_password(&s.id, &s.password, &encryption_key).ok())
+                .is_some_and(|password| !password.is_empty());
+            if !uses_password {
+                for identity in identity_candidates(&server.identity_files, &server.name, &server.ip) {
+                    if !candidates.contains(&identity) {
+                        candidates.push(identity);
+                    }
+                }
+            }
+            key_servers.push((server.name.clone(), candidates));
+        }
+
+        self.users = identities
+            .iter()
+            .map(|identity| {
+                key_servers
+                    .iter()
+                    .filter(|(_, candidates)| candidates.contains(identity))
+                    .map(|(name, _)| name.clone())
+                    .collect()
+            })
+            .collect();
+        self.keys = identities
+            .iter()
+            .map(|identity| key_info(identity, self.vault, &encryption_key))
+            .collect();
+        let selected = self.state.selected().unwrap_or(0);
+        self.state
+            .select(Some(selected.min(self.keys.len().saturating_sub(1))));
+        Ok(())
+    }
+
+    fn selected(&self) -> Option<usize> {
+        self.state.selected().filter(|i| *i < self.keys.len())
+    }
+
+    fn next(&mut self) {
+        let len = self.keys.len().max(1);
+        let i = self.state.selected().map_or(0, |i| (i + 1) % len);
+        self.state.select(Some(i));
+    }
+
+    fn previous(&mut self) {
+        let len = self.keys.len().max(1);
+        let i = self.state.selected().map_or(0, |i| (i + len - 1) % len);
+        self.state.select(Some(i));
+    }
+
+    /// add, change or remove the passphrase of a key file
+    fn passphrase(&mut self, index: usize, terminal: &mut Terminal<impl Backend>) -> Result<()> {
+        let key = &self.keys[index];
+        let Identity::File(path) = key.identity.clone() else {
+            self.message = Some((
+                "keys in the vault are protected by the master passphrase".to_string(),
+                true,
+            ));
+            return Ok(());
+        };
+        let name = key.identity.to_string();
+        let old = if key.encrypted {
+            let mut input_box = PopupInputBox::new(format!(" Current passphrase of {}: ", name));
+            match input_box.run(terminal)? {
+                Some(old) => old,
+                None => return Ok(()),
+            }
+        } else {
+            String::new()
+        };
+        let mut input_box =
+            PopupInputBox::new(format!(" New passphrase of {} (empty for none): ", name));
+        let Some(new) = input_box.run(terminal)? else {
+            return Ok(());
+        };
+        let mut input_box = PopupInputBox::new(" Enter the same passphrase again: ".to_string());
+        if input_box.run(terminal)?.as_ref() != Some(&new) {
+            self.message = Some(("Passphrases do not match".to_string(), true));
+            return Ok(());
+        }
+        if let Err(e) = change_passphrase(&path, &old, &new) {
+            self.message = Some((e.to_string(), true));
+            return Ok(());
+        }
+        // keep a remembered passphrase in step with the key
+        let encryption_key = convert_to_array(self.encryption_key)?;
+        let remembered = match self.vault.key_passphrase(&name, &encryption_key) {
+            Ok(Some(_)) if new.is_empty() => {
+                self.vault.remove_key_passphrase(&name, &encryption_key)
+            }
+            Ok(Some(_)) => self.vault.set_key_passphrase(&name, &new, &encryption_key),
+            Ok(None) => Ok(()),
+            Err(e) => Err(e),
+        };
+        let action = if new.is_empty() { "removed" } else { "changed" };
+        self.message = Some(match remembered {
+            Ok(()) => (format!("Passphrase of {} {}", name, action), false),
+            // the key file is already changed, only the vault is behind
+            Err(e) => (
+                format!(
+                    "Passphrase of {} {}, but the remembered one was not updated: {}",
+                    name, action, e
+                ),
+                true,
+            ),
+        });
+        self.reload()
+    }
+
+    /// rename a key and update the servers that name it
+    fn rename(&mut self, index: usize, terminal: &mut Terminal<impl Backend>) -> Result<()> {
+        let old = self.keys[index].identity.clone();
+        let mut input_box = PopupInputBox::new_plain(format!(" New name of {}: ", old));
+        let new_name = match input_box.run(terminal)? {
+            Some(name) if !name.trim().is_empty() => name.trim().to_string(),
+            _ => return Ok(()),
+        };
+        let encryption_key = convert_to_array(self.encryption_key)?;
+        let new = match &old {
+            Identity::File(path) => {
+                // a bare name stays in the same directory
+                let new_path = if new_name.contains('/') {
+                    expand_tilde(&new_name)
+                } else {
+                    path.parent()
+                        .map_or(PathBuf::from(&new_name), |dir| dir.join(&new_name))
+                };
+                if let Err(e) = rename_key_files(path, &new_path) {
+                    self.message = Some((e.to_string(), true));
+                    return Ok(());
+                }
+                Identity::File(new_path)
+            }
+            Identity::Vault(_) => Identity::Vault(new_name.clone()),
+        };
+        // the key, the servers naming it and its passphrase are saved as one
+        let result = store::update(self.config, self.vault, &encryption_key, |config, vault| {
+            if let Identity::Vault(name) = &old {
+                vault.rename_key(name, &new_name, &encryption_key)?;
+            }
+            for server in config.servers.iter_mut() {
+                for entry in server.identity_files.iter_mut() {
+                    if Identity::parse(entry) == old {
+                        *entry = new.to_string();
+                    }
+                }
+            }
+            vault.rename_key_passphrase(&old.to_string(), &new.to_string(), &encryption_key)
+        });
+        if let Err(e) = result {
+            // the servers still name the old path
+            if let (Identity::File(from), Identity::File(to)) = (&old, &new) {
+                let _ = rename_key_files(to, from);
+            }
+            self.message = Some((e.to_string(), true));
+            return Ok(());
+        }
+        self.message = Some(match store::take_warning() {
+            Some(warning) => (warning, true),
+            None => (format!("Renamed {} to {}", old, new), false),
+        });
+        self.reload()
+    }
+
+    /// delete a key, warning first if servers still use it
+    fn delete(&mut self, index: usize, terminal: &mut Terminal<impl Backend>) -> Result<()> {
+        let identity = self.keys[index].identity.clone();
+        let mut message = format!("Delete {}? This can't be undone.", identity);
+        if !self.users[index].is_empty() {
+            message.push_str(&format!(
+                "\nIt is still used by: {}",
+                self.users[index].join(", ")
+            ));
+        }
+        let mut confirm_box = ConfirmBox::new(" Delete key ".to_string(), message);
+        if !confirm_box.run(terminal)? {
+            return Ok(());
+        }
+        if let Identity::File(path) = &identity {
+            if let Err(e) = delete_key_files(path) {
+                self.message = Some((e.to_string(), true));
+                return Ok(());
+            }
+        }
+        let encryption_key = convert_to_array(self.encryption_key)?;
+        let deleted = store::update(self.config, self.vault, &encryption_key, |_, vault| {
+            if let Identity::Vault(name) = &identity {
+                vault.delete_key(name)?;
+            }
+            vault.forget_key_passphrase(&identity.to_string());
+            Ok(())
+        });
+        if let Err(e) = deleted {
+            self.message = Some((e.to_string(), true));
+            return Ok(());
+        }
+        self.message = Some(match store::take_warning() {
+            Some(warning) => (warning, true),
+            None => (format!("Deleted {}", identity), false),
+        });
+        self.reload()
+    }
+
+    fn draw(&mut self, terminal: &mut Terminal<impl Backend>) -> Result<()> {
+        terminal.draw(|f| ui(f, self))?;
+        Ok(())
+    }
+
+    pub fn run(&mut self, terminal: &mut Terminal<impl Backend>) -> Result<()> {
+        loop {
+            self.draw(terminal)?;
+            if let Event::Key(key) = event::read()? {
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                self.message = None;
+                match key.code {
+                    KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                    KeyCode::Char('j') | KeyCode::Down => self.next(),
+                    KeyCode::Char('k') | KeyCode::Up => self.previous(),
+                    KeyCode::Char('g') => {
+                        let mut key_generator = KeyGenerator::new(self.vault, self.encryption_key);
+                        if key_generator.run(terminal)? {
+                            self.reload()?;
+                        }
+                    }
+                    KeyCode::Char('p') => {
+                        if let Some(index) = self.selected() {
+                            self.passphrase(index, terminal)?;
+                        }
+                    }
+                    KeyCode::Char('r') => {
+                        if let Some(index) = self.selected() {
+                            self.rename(index, terminal)?;
+                        }
+                    }
+                    KeyCode::Char('d') => {
+                        if let Some(index) = self.selected() {
+                            self.delete(index, terminal)?;
+                        }
+                    }
+                    _ => {}
+                }
+                terminal.clear()?;
+            }
+        }
+    }
+}
+
+fn ui(f: &mut Frame, key_manager: &mut KeyManager) {
+    let [head_area, list_area, detail_area, foot_area] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Min(0),
+        Constraint::Length(2),
+        Constraint::Length(1),
+    ])
+    .areas(f.area());
+
+    f.render_widget(
+        Text::styled(
+            format!("  {:<30} {:<12} {:<10} {}", "key", "type", "encrypted", "servers"),
+            Style::default().add_modifier(Modifier::BOLD),
+        ),
+        head_area,
+    );
+    let items: Vec<ListItem> = key_manager
+        .keys
+        .iter()
+        .zip(key_manager.users.iter())
+        .map(|(key, users)| {
+            ListItem::new(format!(
+                "{:<30} {:<12} {:<10} {}",
+                key.identity.to_string(),
+                key.key_type,
+                if key.encrypted { "yes" } else { "no" },
+                users.len()
+            ))
+        })
+        .collect();
+    let list = List::new(items)
+        .highlight_style(
+            Style::default()
+                .add_modifier(Modifier::BOLD)
+                .add_modifier(Modifier::REVERSED),
+        )
+        .highlight_symbol("> ")
+        .highlight_spacing(HighlightSpacing::Always);
+    f.render_stateful_widget(list, list_area, &mut key_manager.state);
+
+    if let Some(index) = key_manager.selected() {
+        let key = &key_manager.keys[index];
+        let users = &key_manager.users[index];
+        let detail = Text::from(vec![
+            Line::from(format!("  {} {}", key.fingerprint, key.comment)),
+            Line::from(format!(
+                "  used by: {}",
+                if users.is_empty() {
+                    "-".to_string()
+                } else {
+                    users.join(", ")
+                }
+            )),
+        ])
+        .dim();
+        f.render_widget(Paragraph::new(detail), detail_area);
+    }
+
+    let footer = match &key_manager.message {
+        Some((message, true)) => Text::from(format!("  {}", message)).red(),
+        Some((message, false)) => Text::from(format!("  {}", message)).green(),
+        None => Text::from("  Generate (G), Passphrase (P), Rename (R), Delete (D), Back (ESC)").dim(),
+    };
+    f.render_widget(footer, foot_area);
+}
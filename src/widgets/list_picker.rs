@@ -0,0 +1,89 @@
+use anyhow::Result;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::backend::Backend;
+use ratatui::style::{Modifier, Style};
+use ratatui::widgets::{Block, Borders, HighlightSpacing, List, ListItem, ListState};
+use ratatui::Terminal;
+
+use crate::helper;
+
+/// popup to choose one entry of a list
+pub struct ListPicker {
+    title: String,
+    items: Vec<String>,
+    state: ListState,
+}
+
+impl ListPicker {
+    pub fn new(title: String, items: Vec<String>) -> Self {
+        let mut state = ListState::default();
+        state.select(Some(0));
+        Self {
+            title,
+            items,
+            state,
+        }
+    }
+
+    fn next(&mut self) {
+        let len = self.items.len().max(1);
+        let i = self.state.selected().map_or(0, |i| (i + 1) % len);
+        self.state.select(Some(i));
+    }
+
+    fn previous(&mut self) {
+        let len = self.items.len().max(1);
+        let i = self.state.selected().map_or(0, |i| (i + len - 1) % len);
+        self.state.select(Some(i));
+    }
+
+    fn draw(&mut self, terminal: &mut Terminal<impl Backend>) -> Result<()> {
+        let items: Vec<ListItem> = self
+            .items
+            .iter()
+            .map(|item| ListItem::new(item.clone()))
+            .collect();
+        let list = List::new(items)
+            .block(
+                Block::default()
+                    .title(self.title.clone())
+                    .borders(Borders::ALL),
+            )
+            .highlight_style(
+                Style::default()
+                    .add_modifier(Modifier::BOLD)
+                    .add_modifier(Modifier::REVERSED),
+            )
+            .highlight_symbol("> ")
+            .highlight_spacing(HighlightSpacing::Always);
+
+        terminal.draw(|f| {
+            let area = helper::centered_rect(80, 100, f.area());
+            f.render_stateful_widget(list, area, &mut self.state)
+        })?;
+        Ok(())
+    }
+
+    /**
+     * Run and get the index of the chosen entry
+     * None -> cancelled
+     */
+    pub fn run(&mut self, terminal: &mut Terminal<impl Backend>) -> Result<Option<usize>> {
+        loop {
+            self.draw(terminal)?;
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press {
+                    match key.code {
+                        KeyCode::Char('j') | KeyCode::Down => self.next(),
+                        KeyCode::Char('k') | KeyCode::Up => self.previous(),
+                        KeyCode::Enter => {
+                            return Ok(self.state.selected().filter(|i| *i < self.items.len()))
+                        }
+                        KeyCode::Esc | KeyCode::Char('q') => return Ok(None),
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,2 +1,9 @@
 pub mod server_creator;
-pub mod popup_input_box;
\ No newline at end of file
+pub mod popup_input_box;
+pub mod session_picker;
+pub mod confirm_box;
+pub mod file_picker;
+pub mod key_generator;
+pub mod list_picker;
+pub mod key_manager;
+pub mod text_editor;
\ No newline at end of file
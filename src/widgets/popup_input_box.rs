@@ -1,6 +1,6 @@
 use crossterm::event::{self, Event, KeyCode, KeyEventKind};
 use ratatui::backend::Backend;
-use ratatui::style::Style;
+use ratatui::style::{Style, Stylize};
 use ratatui::text::Line;
 use ratatui::widgets::{Block, Borders, Paragraph};
 use ratatui::Terminal;
@@ -11,6 +11,9 @@ use crate::helper;
 pub struct PopupInputBox {
     title: String,
     input: String,
+    masked: bool,
+    /// state of the "remember" checkbox, None if the box has no checkbox
+    remember: Option<bool>,
 }
 
 impl PopupInputBox {
@@ -18,13 +21,42 @@ impl PopupInputBox {
         Self {
             title,
             input: String::new(),
+            masked: true,
+            remember: None,
         }
     }
 
-    fn render(&self) -> Paragraph {
-        let mask_text = "*".repeat(self.input.len());
-        let input_text = format!("{}", mask_text);
-        let content = vec![Line::from(input_text)];
+    /// an input box that shows what is typed, for non-secret input
+    pub fn new_plain(title: String) -> Self {
+        Self {
+            title,
+            input: String::new(),
+            masked: false,
+            remember: None,
+        }
+    }
+
+    /// show a "remember" checkbox below the input, toggled with Tab
+    pub fn with_remember(mut self, checked: bool) -> Self {
+        self.remember = Some(checked);
+        self
+    }
+
+    pub fn remember(&self) -> bool {
+        self.remember.unwrap_or(false)
+    }
+
+    fn render(&self) -> Paragraph<'_> {
+        let input_text = if self.masked {
+            "*".repeat(self.input.len())
+        } else {
+            self.input.clone()
+        };
+        let mut content = vec![Line::from(input_text)];
+        if let Some(checked) = self.remember {
+            let mark = if checked { "x" } else { " " };
+            content.push(Line::from(format!("[{}] remember passphrase (Tab)", mark)).dim());
+        }
 
         Paragraph::new(content)
             .block(
@@ -51,9 +83,9 @@ impl PopupInputBox {
         Ok(())
     }
 
-    pub fn run(&mut self, mut terminal: &mut Terminal<impl Backend>) -> Result<Option<String>> {
+    pub fn run(&mut self, terminal: &mut Terminal<impl Backend>) -> Result<Option<String>> {
         loop {
-            self.draw(&mut terminal)?;
+            self.draw(terminal)?;
             if let Event::Key(key) = event::read()? {
                 if key.kind == KeyEventKind::Press {
                     match key.code {
@@ -63,6 +95,11 @@ impl PopupInputBox {
                         KeyCode::Backspace => {
                             self.backspace();
                         }
+                        KeyCode::Tab => {
+                            if let Some(checked) = self.remember.as_mut() {
+                                *checked = !*checked;
+                            }
+                        }
                         KeyCode::Enter => {
                             return Ok(Some(self.input.clone()));
                         }
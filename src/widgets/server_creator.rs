@@ -10,58 +10,151 @@ use ratatui::{
     Frame, Terminal,
 };
 use std::ops::{Add, Sub};
+use std::path::PathBuf;
 
 use crate::{
     config::{
-        app_config::{Config, Server},
+        app_config::{normalize_group, Config, Multiplexer, MultiplexerKind, Server, SshOptions},
         app_vault::{self, decrypt_password, encrypt_password, EncryptionKey, Vault},
+        store,
     },
     helper::convert_to_array,
+    ssh::{common::ssh_config, identity::display_path, multiplexer::DEFAULT_SESSION},
+    widgets::{file_picker::FilePicker, list_picker::ListPicker, text_editor::TextEditor},
 };
 
+/// width of the labels in front of the inputs, including the colon
+const LABEL_WIDTH: usize = 13;
+
 /// current selected item in form
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq)]
 enum CurrentSelect {
     User = 0,
     Ip,
     Port,
     Password,
+    Identity,
     Name,
     Shell,
+    Group,
+    Tags,
+    Notes,
+    Multiplexer,
+    Session,
+    Kex,
+    Ciphers,
+    Macs,
+    HostKeys,
+    Compression,
+    ConnectTimeout,
+    InactivityTimeout,
+    KeepaliveInterval,
 }
 
-/// impl Add and Sub for CurrentSelect
-impl Add for CurrentSelect {
-    type Output = Self;
+impl CurrentSelect {
+    /// number of items in the form
+    const COUNT: isize = 20;
 
-    fn add(self, other: Self) -> Self {
-        let new_value = (self as isize + other as isize) % 6;
-        match new_value {
+    fn from_index(index: isize) -> Self {
+        match index.rem_euclid(Self::COUNT) {
             0 => CurrentSelect::User,
             1 => CurrentSelect::Ip,
             2 => CurrentSelect::Port,
             3 => CurrentSelect::Password,
-            4 => CurrentSelect::Name,
-            5 => CurrentSelect::Shell,
+            4 => CurrentSelect::Identity,
+            5 => CurrentSelect::Name,
+            6 => CurrentSelect::Shell,
+            7 => CurrentSelect::Group,
+            8 => CurrentSelect::Tags,
+            9 => CurrentSelect::Notes,
+            10 => CurrentSelect::Multiplexer,
+            11 => CurrentSelect::Session,
+            12 => CurrentSelect::Kex,
+            13 => CurrentSelect::Ciphers,
+            14 => CurrentSelect::Macs,
+            15 => CurrentSelect::HostKeys,
+            16 => CurrentSelect::Compression,
+            17 => CurrentSelect::ConnectTimeout,
+            18 => CurrentSelect::InactivityTimeout,
+            19 => CurrentSelect::KeepaliveInterval,
             _ => unreachable!(),
         }
     }
+
+    fn label(self) -> &'static str {
+        match self {
+            CurrentSelect::User => "user",
+            CurrentSelect::Ip => "ip",
+            CurrentSelect::Port => "port",
+            CurrentSelect::Password => "password",
+            CurrentSelect::Identity => "identity",
+            CurrentSelect::Name => "name",
+            CurrentSelect::Shell => "shell",
+            CurrentSelect::Group => "group",
+            CurrentSelect::Tags => "tags",
+            CurrentSelect::Notes => "notes",
+            CurrentSelect::Multiplexer => "mux",
+            CurrentSelect::Session => "session",
+            CurrentSelect::Kex => "kex",
+            CurrentSelect::Ciphers => "ciphers",
+            CurrentSelect::Macs => "macs",
+            CurrentSelect::HostKeys => "host keys",
+            CurrentSelect::Compression => "compression",
+            CurrentSelect::ConnectTimeout => "timeout",
+            CurrentSelect::InactivityTimeout => "idle timeout",
+            CurrentSelect::KeepaliveInterval => "keepalive",
+        }
+    }
+
+    /// hint shown in place of an empty input
+    fn placeholder(self) -> &'static str {
+        match self {
+            CurrentSelect::Password => "leave empty to use SSH keys",
+            CurrentSelect::Identity => "comma separated, leave empty to try default keys",
+            CurrentSelect::Group => "folder like prod/db, leave empty for none",
+            CurrentSelect::Tags => "comma separated, like prod, db",
+            CurrentSelect::Notes => "press Enter to write notes",
+            CurrentSelect::Multiplexer => "tmux, screen or leave empty",
+            CurrentSelect::Session => DEFAULT_SESSION,
+            CurrentSelect::Kex
+            | CurrentSelect::Ciphers
+            | CurrentSelect::Macs
+            | CurrentSelect::HostKeys => "comma separated, leave empty for default",
+            CurrentSelect::Compression => "no",
+            CurrentSelect::ConnectTimeout | CurrentSelect::InactivityTimeout => {
+                "seconds, leave empty for none"
+            }
+            CurrentSelect::KeepaliveInterval => "15",
+            _ => "",
+        }
+    }
+
+    fn is_required(self) -> bool {
+        matches!(
+            self,
+            CurrentSelect::User
+                | CurrentSelect::Ip
+                | CurrentSelect::Port
+                | CurrentSelect::Name
+                | CurrentSelect::Shell
+        )
+    }
+}
+
+/// impl Add and Sub for CurrentSelect
+impl Add for CurrentSelect {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        CurrentSelect::from_index(self as isize + other as isize)
+    }
 }
 
 impl Sub for CurrentSelect {
     type Output = Self;
 
     fn sub(self, other: Self) -> Self {
-        let new_value = (self as isize - other as isize + 6) % 6;
-        match new_value {
-            0 => CurrentSelect::User,
-            1 => CurrentSelect::Ip,
-            2 => CurrentSelect::Port,
-            3 => CurrentSelect::Password,
-            4 => CurrentSelect::Name,
-            5 => CurrentSelect::Shell,
-            _ => unreachable!(),
-        }
+        CurrentSelect::from_index(self as isize - other as isize)
     }
 }
 
@@ -69,16 +162,7 @@ impl Add<isize> for CurrentSelect {
     type Output = Self;
 
     fn add(self, other: isize) -> Self {
-        let new_value = (self as isize + other).rem_euclid(6);
-        match new_value {
-            0 => CurrentSelect::User,
-            1 => CurrentSelect::Ip,
-            2 => CurrentSelect::Port,
-            3 => CurrentSelect::Password,
-            4 => CurrentSelect::Name,
-            5 => CurrentSelect::Shell,
-            _ => unreachable!(),
-        }
+        CurrentSelect::from_index(self as isize + other)
     }
 }
 
@@ -86,16 +170,7 @@ impl Sub<isize> for CurrentSelect {
     type Output = Self;
 
     fn sub(self, other: isize) -> Self {
-        let new_value = (self as isize - other).rem_euclid(6);
-        match new_value {
-            0 => CurrentSelect::User,
-            1 => CurrentSelect::Ip,
-            2 => CurrentSelect::Port,
-            3 => CurrentSelect::Password,
-            4 => CurrentSelect::Name,
-            5 => CurrentSelect::Shell,
-            _ => unreachable!(),
-        }
+        CurrentSelect::from_index(self as isize - other)
     }
 }
 
@@ -113,6 +188,8 @@ pub struct ServerCreator<'a> {
     encryption_key: &'a EncryptionKey,
     mode: CreatorMode,
     server_id: Option<String>,
+    /// why the last save was refused
+    error: Option<String>,
 }
 
 // impl Widget for &mut ServerCreator {
@@ -141,15 +218,19 @@ impl<'a> ServerCreator<'a> {
         config: &'a mut Config,
         encryption_key: &'a EncryptionKey,
     ) -> Self {
+        let mut input = vec![
+            String::new(),
+            String::new(),
+            "22".to_string(),
+            String::new(),
+            String::new(),
+            String::new(),
+            "bash".to_string(),
+        ];
+        // the remaining items are optional and start empty
+        input.resize(CurrentSelect::COUNT as usize, String::new());
         Self {
-            input: vec![
-                String::new(),
-                String::new(),
-                "22".to_string(),
-                String::new(),
-                String::new(),
-                "bash".to_string(),
-            ],
+            input,
             character_index: 0,
             current_select: CurrentSelect::User,
             vault,
@@ -157,6 +238,7 @@ impl<'a> ServerCreator<'a> {
             encryption_key,
             mode: CreatorMode::New,
             server_id: None,
+            error: None,
         }
     }
 
@@ -179,9 +261,9 @@ impl<'a> ServerCreator<'a> {
             .password
             .clone();
         let decrypted_password = decrypt_password(
-            &server_id,
+            server_id,
             &password,
-            &convert_to_array(&encryption_key)
+            &convert_to_array(encryption_key)
                 .map_err(|e| anyhow::anyhow!("encryption key convert failed: {}", e))?,
         )?;
         Ok(Self {
@@ -190,8 +272,32 @@ impl<'a> ServerCreator<'a> {
                 server.ip.clone(),
                 server.port.to_string(),
                 decrypted_password,
+                server.identity_files.join(", "),
                 server.name.clone(),
                 server.shell.clone(),
+                server.group.clone(),
+                server.tags.join(", "),
+                server.notes.clone(),
+                server
+                    .multiplexer
+                    .as_ref()
+                    .map_or(String::new(), |multiplexer| multiplexer.kind.to_string()),
+                server
+                    .multiplexer
+                    .as_ref()
+                    .map_or(String::new(), |multiplexer| multiplexer.session.clone()),
+                server.ssh_options.kex.join(", "),
+                server.ssh_options.ciphers.join(", "),
+                server.ssh_options.macs.join(", "),
+                server.ssh_options.host_key_algorithms.join(", "),
+                if server.ssh_options.compression {
+                    "yes".to_string()
+                } else {
+                    String::new()
+                },
+                optional_to_string(server.ssh_options.connect_timeout),
+                optional_to_string(server.ssh_options.inactivity_timeout),
+                optional_to_string(server.ssh_options.keepalive_interval),
             ],
             character_index: 0,
             current_select: CurrentSelect::User,
@@ -200,79 +306,64 @@ impl<'a> ServerCreator<'a> {
             encryption_key,
             mode: CreatorMode::Edit,
             server_id: Some(server_id.to_string()),
+            error: None,
         })
     }
 
     fn render_header(&self, area: Rect, buf: &mut Buffer) {
-        let text = Text::from("Enter server details below:").yellow();
+        let text = match &self.error {
+            Some(error) => Text::from(error.as_str()).red(),
+            None => Text::from("Enter server details below:").yellow(),
+        };
         Widget::render(text, area, buf);
     }
 
     fn render_footer(&self, area: Rect, buf: &mut Buffer) {
-        let text = Text::from("  Save (^S), Quit (ESC)").dim();
+        let text = Text::from("  Save (^S), Pick key (^O), Pick group (^G), Notes (^N), Quit (ESC)").dim();
         Widget::render(text, area, buf);
     }
 
     fn render_form(&self, area: Rect, buf: &mut Buffer) {
-        // highlight currently selected item
-        let mut user: Vec<Span> = vec![
-            "    user:".into(),
-            self.input[CurrentSelect::User as usize].clone().into(),
-        ];
-        let mut ip: Vec<Span> = vec![
-            "      ip:".into(),
-            self.input[CurrentSelect::Ip as usize].clone().into(),
-        ];
-        let mut port: Vec<Span> = vec![
-            "    port:".into(),
-            self.input[CurrentSelect::Port as usize].clone().into(),
-        ];
-        // we use * to replace the password
-        let password_length = self.input[CurrentSelect::Password as usize].len();
-        let masked_password: String = "*".repeat(password_length);
-        let mut password: Vec<Span> = vec!["password:".into(), masked_password.into()];
-        let mut name: Vec<Span> = vec![
-            "    name:".into(),
-            self.input[CurrentSelect::Name as usize].clone().into(),
-        ];
-        let mut shell: Vec<Span> = vec![
-            "   shell:".into(),
-            self.input[CurrentSelect::Shell as usize].clone().into(),
-        ];
-
-        match self.current_select {
-            CurrentSelect::User => user[0] = Span::styled("    user:", Style::new().bold()),
-            CurrentSelect::Ip => ip[0] = Span::styled("      ip:", Style::new().bold()),
-            CurrentSelect::Port => port[0] = Span::styled("    port:", Style::new().bold()),
-            CurrentSelect::Password => password[0] = Span::styled("password:", Style::new().bold()),
-            CurrentSelect::Name => name[0] = Span::styled("    name:", Style::new().bold()),
-            CurrentSelect::Shell => shell[0] = Span::styled("   shell:", Style::new().bold()),
-        }
-
-        let user_line = Line::from(user);
-        let ip_line = Line::from(ip);
-        let port_line = Line::from(port);
-        let password_line = if password_length == 0 {
-            password[1] =
-                Span::styled("leave empty to use the default SSH key", Style::new().dim());
-            Line::from(password)
-        } else {
-            Line::from(password)
-        };
-        let name_line = Line::from(name);
-        let shell_line = Line::from(shell);
-        let text = vec![
-            user_line,
-            ip_line,
-            port_line,
-            password_line,
-            name_line,
-            shell_line,
-        ];
-        let form = Paragraph::new(text);
+        let text: Vec<Line> = (0..CurrentSelect::COUNT)
+            .map(CurrentSelect::from_index)
+            .map(|item| {
+                let label = format!("{:>width$}:", item.label(), width = LABEL_WIDTH - 1);
+                // highlight currently selected item
+                let label = if item == self.current_select {
+                    Span::styled(label, Style::new().bold())
+                } else {
+                    label.into()
+                };
+                let input = &self.input[item as usize];
+                let value = if input.is_empty() {
+                    Span::styled(item.placeholder(), Style::new().dim())
+                } else if item == CurrentSelect::Password {
+                    // we use * to replace the password
+                    "*".repeat(input.len()).into()
+                } else if item == CurrentSelect::Notes {
+                    // notes may span lines, the form has room for one
+                    let more = input.lines().count().saturating_sub(1);
+                    let first = input.lines().next().unwrap_or_default();
+                    if more > 0 {
+                        format!("{} (+{} more)", first, more).into()
+                    } else {
+                        first.to_string().into()
+                    }
+                } else {
+                    input.clone().into()
+                };
+                Line::from(vec![label, value])
+            })
+            .collect();
+        let form = Paragraph::new(text).scroll((self.scroll_offset(area.height), 0));
         Widget::render(&form, area, buf);
     }
 
+    /// scroll the form so that the selected item stays visible
+    fn scroll_offset(&self, height: u16) -> u16 {
+        (self.current_select as u16).saturating_sub(height.saturating_sub(1))
+    }
+
     fn move_cursor_left(&mut self) {
         let cursor_moved_left = self.character_index.saturating_sub(1);
         self.character_index = self.clamp_cursor(cursor_moved_left);
@@ -334,6 +425,10 @@ impl<'a> ServerCreator<'a> {
     }
 
     fn clamp_cursor(&self, new_cursor_pos: usize) -> usize {
+        // the notes row is a preview, its text is edited elsewhere
+        if self.current_select == CurrentSelect::Notes {
+            return 0;
+        }
         new_cursor_pos.clamp(0, self.input[self.current_select as usize].chars().count())
     }
 
@@ -347,8 +442,199 @@ impl<'a> ServerCreator<'a> {
 }
 
 impl<'a> ServerCreator<'a> {
+    /**
+     * Validate the form and save it to config and vault
+     * true -> saved
+     * false -> the form is not valid yet
+     */
+    fn save(&mut self) -> Result<bool> {
+        if let Some(item) = (0..CurrentSelect::COUNT)
+            .map(CurrentSelect::from_index)
+            .find(|item| item.is_required() && self.input[*item as usize].trim().is_empty())
+        {
+            self.error = Some(format!("{} is required", item.label()));
+            return Ok(false);
+        }
+        let multiplexer_input = self.input[CurrentSelect::Multiplexer as usize].trim();
+        let multiplexer = if multiplexer_input.is_empty() {
+            None
+        } else {
+            let Ok(kind) = multiplexer_input.parse::<MultiplexerKind>() else {
+                self.error = Some("mux must be tmux, screen or empty".to_string());
+                return Ok(false);
+            };
+            Some(Multiplexer {
+                kind,
+                session: self.input[CurrentSelect::Session as usize].trim().to_string(),
+            })
+        };
+        let ssh_options = match self.ssh_options() {
+            Ok(ssh_options) => ssh_options,
+            Err(e) => {
+                self.error = Some(e.to_string());
+                return Ok(false);
+            }
+        };
+        let encryption_key = convert_to_array(self.encryption_key)?;
+        let mut config_server = Server::new(
+            self.input[CurrentSelect::Name as usize].clone(),
+            self.input[CurrentSelect::Ip as usize].clone(),
+            self.input[CurrentSelect::User as usize].clone(),
+            self.input[CurrentSelect::Shell as usize].clone(),
+            self.input[CurrentSelect::Port as usize]
+                .parse::<u16>()
+                .unwrap_or(22),
+        );
+        // keep what the form doesn't show, like imported jump hosts
+        if let Some(saved) = self
+            .server_id
+            .as_ref()
+            .and_then(|id| self.config.servers.iter().find(|s| &s.id == id))
+        {
+            config_server.proxy_jump = saved.proxy_jump.clone();
+        }
+        config_server.identity_files = self.list(CurrentSelect::Identity);
+        config_server.group = normalize_group(&self.input[CurrentSelect::Group as usize]);
+        config_server.tags = self.list(CurrentSelect::Tags);
+        config_server.notes = self.input[CurrentSelect::Notes as usize].trim_end().to_string();
+        config_server.multiplexer = multiplexer;
+        config_server.ssh_options = ssh_options;
+        if self.mode == CreatorMode::Edit {
+            let Some(server_id) = self.server_id.clone() else {
+                return Err(anyhow::anyhow!("Server ID not found"));
+            };
+            config_server.id = server_id;
+        }
+        let passwd = encrypt_password(
+            &config_server.id,
+            self.input[CurrentSelect::Password as usize].as_str(),
+            &encryption_key,
+        )?;
+        let vault_server = app_vault::Server::new(config_server.id.clone(), passwd);
+
+        store::update(self.config, self.vault, &encryption_key, |config, vault| {
+            if config.servers.iter().any(|s| s.id == config_server.id) {
+                // branch 1: modify server
+                config.modify_server(config_server.id.as_str(), config_server.clone())?;
+            } else {
+                //branch 2: add server
+                config.add_server(config_server.clone());
+            }
+            vault.set_server(vault_server);
+            Ok(())
+        })?;
+        Ok(true)
+    }
+
+    /// the entries of a comma separated input
+    fn list(&self, item: CurrentSelect) -> Vec<String> {
+        self.input[item as usize]
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// collect and check the SSH transport overrides of the form
+    fn ssh_options(&self) -> Result<SshOptions> {
+        let list = |item: CurrentSelect| self.list(item);
+        let seconds = |item: CurrentSelect| -> Result<Option<u64>> {
+            let input = self.input[item as usize].trim();
+            if input.is_empty() {
+                return Ok(None);
+            }
+            input
+                .parse::<u64>()
+                .map(Some)
+                .map_err(|_| anyhow::anyhow!("{} must be a number of seconds", item.label()))
+        };
+        let compression = match self.input[CurrentSelect::Compression as usize]
+            .trim()
+            .to_lowercase()
+            .as_str()
+        {
+            "" | "no" | "n" | "off" | "false" => false,
+            "yes" | "y" | "on" | "true" => true,
+            _ => return Err(anyhow::anyhow!("compression must be yes or no")),
+        };
+        let ssh_options = SshOptions {
+            kex: list(CurrentSelect::Kex),
+            ciphers: list(CurrentSelect::Ciphers),
+            macs: list(CurrentSelect::Macs),
+            host_key_algorithms: list(CurrentSelect::HostKeys),
+            compression,
+            connect_timeout: seconds(CurrentSelect::ConnectTimeout)?,
+            inactivity_timeout: seconds(CurrentSelect::InactivityTimeout)?,
+            keepalive_interval: seconds(CurrentSelect::KeepaliveInterval)?,
+        };
+        // reject algorithm names russh does not know before saving them
+        ssh_config(&ssh_options)?;
+        Ok(ssh_options)
+    }
+
+    /// browse `~/.ssh` and append the picked key to the identity files
+    fn pick_identity(&mut self, terminal: &mut Terminal<impl Backend>) -> Result<()> {
+        let start_dir = dirs::home_dir()
+            .map(|home| home.join(".ssh"))
+            .filter(|dir| dir.is_dir())
+            .or_else(dirs::home_dir)
+            .unwrap_or_else(|| PathBuf::from("/"));
+        let Some(path) = FilePicker::new(start_dir)?.run(terminal)? else {
+            return Ok(());
+        };
+        let identity = &mut self.input[CurrentSelect::Identity as usize];
+        if !identity.trim().is_empty() {
+            identity.push_str(", ");
+        }
+        identity.push_str(&display_path(&path));
+        self.current_select = CurrentSelect::Identity;
+        self.character_index = identity.chars().count();
+        terminal.clear()?;
+        Ok(())
+    }
+
+    /// move the server to one of the existing groups
+    fn pick_group(&mut self, terminal: &mut Terminal<impl Backend>) -> Result<()> {
+        let mut groups: Vec<String> = Vec::new();
+        for server in &self.config.servers {
+            // parents are groups too
+            let levels: Vec<&str> = server.group.split('/').collect();
+            for depth in 1..=levels.len() {
+                let group = levels[..depth].join("/");
+                if !group.is_empty() && !groups.contains(&group) {
+                    groups.push(group);
+                }
+            }
+        }
+        groups.sort();
+        groups.insert(0, "(none)".to_string());
+        let picked = ListPicker::new(" Move to group: ".to_string(), groups.clone()).run(terminal)?;
+        if let Some(index) = picked {
+            let group = if index == 0 { String::new() } else { groups.swap_remove(index) };
+            self.character_index = group.chars().count();
+            self.input[CurrentSelect::Group as usize] = group;
+            self.current_select = CurrentSelect::Group;
+        }
+        terminal.clear()?;
+        Ok(())
+    }
+
+    /// edit the notes, which may span lines, in their own view
+    fn edit_notes(&mut self, terminal: &mut Terminal<impl Backend>) -> Result<()> {
+        let notes = &self.input[CurrentSelect::Notes as usize];
+        let title = format!("Notes for {}:", self.input[CurrentSelect::Name as usize]);
+        if let Some(notes) = TextEditor::new(title, notes).run(terminal)? {
+            self.input[CurrentSelect::Notes as usize] = notes;
+        }
+        self.current_select = CurrentSelect::Notes;
+        self.character_index = 0;
+        terminal.clear()?;
+        Ok(())
+    }
+
     fn draw(&mut self, terminal: &mut Terminal<impl Backend>) -> Result<()> {
-        terminal.draw(|f| ui(f, &self))?;
+        terminal.draw(|f| ui(f, self))?;
         Ok(())
     }
 
@@ -357,81 +643,49 @@ impl<'a> ServerCreator<'a> {
      * true -> add a new server
      * false -> cancelled
      */
-    pub fn run(&mut self, mut terminal: &mut Terminal<impl Backend>) -> Result<bool> {
+    pub fn run(&mut self, terminal: &mut Terminal<impl Backend>) -> Result<bool> {
         loop {
-            self.draw(&mut terminal)?;
+            self.draw(terminal)?;
             if let Event::Key(key) = event::read()? {
                 if key.kind == KeyEventKind::Press {
                     match key.code {
                         KeyCode::Char(to_insert) => {
                             // Set this hotkey because of man's habit
-                            if to_insert == 'c' {
-                                if key.modifiers == event::KeyModifiers::CONTROL {
-                                    return Ok(false);
-                                }
+                            if to_insert == 'c' && key.modifiers == event::KeyModifiers::CONTROL {
+                                return Ok(false);
                             }
                             // Save current server's config
-                            if to_insert == 's' {
-                                if key.modifiers == event::KeyModifiers::CONTROL {
-                                    if self.input.iter().enumerate().any(|(i, input)| {
-                                        i != CurrentSelect::Password as usize && input.trim().is_empty()
-                                    }) {
-                                        continue;
-                                    }
-                                    let encryption_key = convert_to_array(&self.encryption_key)?;
-                                    let mut config_server = Server::new(
-                                        self.input[CurrentSelect::Name as usize].clone(),
-                                        self.input[CurrentSelect::Ip as usize].clone(),
-                                        self.input[CurrentSelect::User as usize].clone(),
-                                        self.input[CurrentSelect::Shell as usize].clone(),
-                                        self.input[CurrentSelect::Port as usize]
-                                            .parse::<u16>()
-                                            .unwrap_or(22),
-                                    );
-                                    if self.mode == CreatorMode::Edit {
-                                        let Some(server_id) = self.server_id.clone() else {
-                                            return Err(anyhow::anyhow!("Server ID not found"));
-                                        };
-                                        config_server.id = server_id;
-                                    }
-                                    let passwd = encrypt_password(
-                                        &config_server.id,
-                                        self.input[CurrentSelect::Password as usize]
-                                            .clone()
-                                            .as_str(),
-                                        &encryption_key,
-                                    )?;
-                                    let vault_server =
-                                        app_vault::Server::new(config_server.id.clone(), passwd);
-
-                                    if self
-                                        .config
-                                        .servers
-                                        .iter()
-                                        .find(|s| s.id == config_server.id)
-                                        .is_some()
-                                    {
-                                        // branch 1: modify server
-                                        self.config.modify_server(
-                                            config_server.id.as_str(),
-                                            config_server.clone(),
-                                        )?;
-                                        self.vault.modify_server(
-                                            config_server.id.as_str(),
-                                            vault_server,
-                                            &encryption_key,
-                                        )?;
-                                    } else {
-                                        //branch 2: add server
-                                        self.config.add_server(config_server.clone())?;
-                                        self.vault.add_server(vault_server, &encryption_key)?;
-                                    }
+                            if to_insert == 's' && key.modifiers == event::KeyModifiers::CONTROL {
+                                if self.save()? {
                                     return Ok(true);
                                 }
+                                continue;
+                            }
+                            // Pick an identity file from disk
+                            if to_insert == 'o' && key.modifiers == event::KeyModifiers::CONTROL {
+                                self.pick_identity(terminal)?;
+                                continue;
+                            }
+                            // Pick one of the existing groups
+                            if to_insert == 'g' && key.modifiers == event::KeyModifiers::CONTROL {
+                                self.pick_group(terminal)?;
+                                continue;
+                            }
+                            // Write the notes
+                            if to_insert == 'n' && key.modifiers == event::KeyModifiers::CONTROL {
+                                self.edit_notes(terminal)?;
+                                continue;
+                            }
+                            // notes are only written in their own view
+                            if self.current_select == CurrentSelect::Notes {
+                                continue;
                             }
                             self.enter_char(to_insert);
                         }
-                        KeyCode::Backspace => {
+                        KeyCode::Enter if self.current_select == CurrentSelect::Notes => {
+                            self.edit_notes(terminal)?;
+                        }
+                        KeyCode::Backspace if self.current_select != CurrentSelect::Notes => {
                             self.delete_char();
                         }
                         KeyCode::Left => {
@@ -470,10 +724,13 @@ fn ui(f: &mut Frame, server_creator: &ServerCreator) {
     server_creator.render_form(body_area, f.buffer_mut());
     server_creator.render_footer(foot_area, f.buffer_mut());
     let character_index = server_creator.character_index as u16;
-    //due to input character index start at 9
-    //eg: "password:"
-    //so here add 9
-    let cursor_x = body_area.x + character_index + 9;
-    let cursor_y = body_area.y + server_creator.current_select as u16;
+    // input starts right after the label
+    let cursor_x = body_area.x + character_index + LABEL_WIDTH as u16;
+    let cursor_y = body_area.y + server_creator.current_select as u16
+        - server_creator.scroll_offset(body_area.height);
     f.set_cursor_position((cursor_x, cursor_y));
-}
\ No newline at end of file
+}
+
+fn optional_to_string(value: Option<u64>) -> String {
+    value.map_or(String::new(), |value| value.to_string())
+}
@@ -0,0 +1,110 @@
+use anyhow::Result;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::backend::Backend;
+use ratatui::style::{Modifier, Style, Stylize};
+use ratatui::text::Text;
+use ratatui::widgets::{Block, Borders, HighlightSpacing, List, ListItem, ListState};
+use ratatui::Terminal;
+
+use crate::helper;
+use crate::widgets::popup_input_box::PopupInputBox;
+
+/// sub-menu listing the remote multiplexer sessions
+pub struct SessionPicker {
+    title: String,
+    sessions: Vec<String>,
+    state: ListState,
+}
+
+impl SessionPicker {
+    pub fn new(title: String, sessions: Vec<String>) -> Self {
+        let mut state = ListState::default();
+        state.select(Some(0));
+        Self {
+            title,
+            sessions,
+            state,
+        }
+    }
+
+    /// the last entry is always "new session"
+    fn len(&self) -> usize {
+        self.sessions.len() + 1
+    }
+
+    fn next(&mut self) {
+        let i = self.state.selected().map_or(0, |i| (i + 1) % self.len());
+        self.state.select(Some(i));
+    }
+
+    fn previous(&mut self) {
+        let i = self
+            .state
+            .selected()
+            .map_or(0, |i| (i + self.len() - 1) % self.len());
+        self.state.select(Some(i));
+    }
+
+    fn draw(&mut self, terminal: &mut Terminal<impl Backend>) -> Result<()> {
+        let mut items: Vec<ListItem> = self
+            .sessions
+            .iter()
+            .map(|session| ListItem::new(session.clone()))
+            .collect();
+        items.push(ListItem::new(Text::from("<new session>").dim()));
+        let list = List::new(items)
+            .block(
+                Block::default()
+                    .title(self.title.clone())
+                    .borders(Borders::ALL),
+            )
+            .highlight_style(
+                Style::default()
+                    .add_modifier(Modifier::BOLD)
+                    .add_modifier(Modifier::REVERSED),
+            )
+            .highlight_symbol("> ")
+            .highlight_spacing(HighlightSpacing::Always);
+
+        terminal.draw(|f| {
+            let area = helper::centered_rect(50, 80, f.area());
+            f.render_stateful_widget(list, area, &mut self.state)
+        })?;
+        Ok(())
+    }
+
+    /**
+     * Run and get the session to join
+     * Some(name) -> attach to (or create) this session
+     * None -> cancelled
+     */
+    pub fn run(&mut self, terminal: &mut Terminal<impl Backend>) -> Result<Option<String>> {
+        loop {
+            self.draw(terminal)?;
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press {
+                    match key.code {
+                        KeyCode::Char('j') | KeyCode::Down => self.next(),
+                        KeyCode::Char('k') | KeyCode::Up => self.previous(),
+                        KeyCode::Enter => {
+                            let selected = self.state.selected().unwrap_or(0);
+                            if let Some(session) = self.sessions.get(selected) {
+                                return Ok(Some(session.clone()));
+                            }
+                            let mut input_box =
+                                PopupInputBox::new_plain(" New session name: ".to_string());
+                            match input_box.run(terminal)? {
+                                Some(name) if !name.trim().is_empty() => {
+                                    return Ok(Some(name.trim().to_string()))
+                                }
+                                _ => continue,
+                            }
+                        }
+                        KeyCode::Esc | KeyCode::Char('q') => return Ok(None),
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+}
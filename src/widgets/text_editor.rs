@@ -0,0 +1,163 @@
+use anyhow::Result;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::{
+    backend::Backend,
+    layout::{Constraint, Layout},
+    style::Stylize,
+    text::Text,
+    widgets::Paragraph,
+    Frame, Terminal,
+};
+
+/// multi-line text input, Enter starts a new line
+pub struct TextEditor {
+    title: String,
+    lines: Vec<String>,
+    /// cursor position, column counted in chars
+    row: usize,
+    column: usize,
+}
+
+impl TextEditor {
+    pub fn new(title: String, text: &str) -> Self {
+        let mut lines: Vec<String> = text.lines().map(str::to_string).collect();
+        if lines.is_empty() {
+            lines.push(String::new());
+        }
+        let row = lines.len() - 1;
+        let column = lines[row].chars().count();
+        Self {
+            title,
+            lines,
+            row,
+            column,
+        }
+    }
+
+    fn byte_index(&self) -> usize {
+        let line = &self.lines[self.row];
+        line.char_indices()
+            .nth(self.column)
+            .map_or(line.len(), |(i, _)| i)
+    }
+
+    fn line_len(&self, row: usize) -> usize {
+        self.lines[row].chars().count()
+    }
+
+    fn insert(&mut self, c: char) {
+        let index = self.byte_index();
+        self.lines[self.row].insert(index, c);
+        self.column += 1;
+    }
+
+    fn new_line(&mut self) {
+        let index = self.byte_index();
+        let rest = self.lines[self.row].split_off(index);
+        self.row += 1;
+        self.lines.insert(self.row, rest);
+        self.column = 0;
+    }
+
+    fn backspace(&mut self) {
+        if self.column > 0 {
+            self.column -= 1;
+            let index = self.byte_index();
+            self.lines[self.row].remove(index);
+        } else if self.row > 0 {
+            // join with the line above
+            let line = self.lines.remove(self.row);
+            self.row -= 1;
+            self.column = self.line_len(self.row);
+            self.lines[self.row].push_str(&line);
+        }
+    }
+
+    fn move_cursor(&mut self, code: KeyCode) {
+        match code {
+            KeyCode::Left if self.column > 0 => self.column -= 1,
+            KeyCode::Left if self.row > 0 => {
+                self.row -= 1;
+                self.column = self.line_len(self.row);
+            }
+            KeyCode::Right if self.column < self.line_len(self.row) => self.column += 1,
+            KeyCode::Right if self.row + 1 < self.lines.len() => {
+                self.row += 1;
+                self.column = 0;
+            }
+            KeyCode::Up if self.row > 0 => {
+                self.row -= 1;
+                self.column = self.column.min(self.line_len(self.row));
+            }
+            KeyCode::Down if self.row + 1 < self.lines.len() => {
+                self.row += 1;
+                self.column = self.column.min(self.line_len(self.row));
+            }
+            _ => {}
+        }
+    }
+
+    /// the text without trailing empty lines
+    fn text(&self) -> String {
+        self.lines.join("\n").trim_end().to_string()
+    }
+
+    fn draw(&self, terminal: &mut Terminal<impl Backend>) -> Result<()> {
+        terminal.draw(|f| ui(f, self))?;
+        Ok(())
+    }
+
+    /**
+     * Run and get a result
+     * Some(text) -> saved
+     * None -> cancelled
+     */
+    pub fn run(&mut self, terminal: &mut Terminal<impl Backend>) -> Result<Option<String>> {
+        loop {
+            self.draw(terminal)?;
+            if let Event::Key(key) = event::read()? {
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                match key.code {
+                    KeyCode::Char('s') if key.modifiers == KeyModifiers::CONTROL => {
+                        return Ok(Some(self.text()));
+                    }
+                    KeyCode::Char('c') if key.modifiers == KeyModifiers::CONTROL => {
+                        return Ok(None);
+                    }
+                    KeyCode::Esc => return Ok(None),
+                    KeyCode::Char(c) => self.insert(c),
+                    KeyCode::Enter => self.new_line(),
+                    KeyCode::Backspace => self.backspace(),
+                    code => self.move_cursor(code),
+                }
+            }
+        }
+    }
+}
+
+fn ui(f: &mut Frame, editor: &TextEditor) {
+    let [head_area, body_area, foot_area] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Min(0),
+        Constraint::Length(1),
+    ])
+    .areas(f.area());
+    f.render_widget(Text::from(editor.title.as_str()).yellow(), head_area);
+    // keep the cursor line in view
+    let scroll = (editor.row as u16).saturating_sub(body_area.height.saturating_sub(1));
+    let lines: Vec<&str> = editor.lines.iter().map(String::as_str).collect();
+    f.render_widget(
+        Paragraph::new(Text::from(lines.join("\n"))).scroll((scroll, 0)),
+        body_area,
+    );
+    f.render_widget(
+        Text::from("  Done (^S), Cancel (ESC)").dim(),
+        foot_area,
+    );
+    f.set_cursor_position((
+        body_area.x + editor.column as u16,
+        body_area.y + editor.row as u16 - scroll,
+    ));
+}
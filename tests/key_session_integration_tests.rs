@@ -4,6 +4,7 @@ mod tests {
 use ssh_utils_lib::{
     ssh::key_session::KeySession,
     ssh::ssh_session::{AuthMethod, SshSession},
+    config::app_config::SshOptions,
 };
 use std::env;
 
@@ -16,7 +17,7 @@ async fn test_key_session_integration() {
     let key = load_secret_key(key_path.clone(), None).expect("Failed to load secret key");
     let auth = AuthMethod::Key(key);
     
-    let mut session = KeySession::connect(user, auth, addr).await.expect("Failed to connect");
+    let mut session = KeySession::connect(&SshOptions::default(), user, auth, addr).await.expect("Failed to connect");
 
     // 测试执行命令
     let exit_code = session.call("echo 'Hello, World!'").await.expect("Failed to execute command");
@@ -3,6 +3,7 @@ mod tests {
     use ssh_utils_lib::{
         ssh::password_session::PasswordSession,
         ssh::ssh_session::{AuthMethod, SshSession},
+        config::app_config::SshOptions,
     };
     use std::env;
 
@@ -14,7 +15,7 @@ mod tests {
 
         let auth = AuthMethod::Password(password);
         
-        let mut session = PasswordSession::connect(user, auth, addr).await.expect("Failed to connect");
+        let mut session = PasswordSession::connect(&SshOptions::default(), user, auth, addr).await.expect("Failed to connect");
 
         // 测试执行命令
         let exit_code = session.call("echo 'Hello, World!'").await.expect("Failed to execute command");